@@ -0,0 +1,456 @@
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::{Pool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::models::{SubscriptionPlan, SubscriptionStatus};
+
+#[derive(Debug, Deserialize)]
+pub struct StripeEvent {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub created: i64,
+    pub data: StripeEventData,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StripeEventData {
+    pub object: serde_json::Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckoutSession {
+    client_reference_id: Option<String>,
+    customer: Option<String>,
+    subscription: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StripeSubscription {
+    id: String,
+    customer: String,
+    status: String,
+    #[serde(default)]
+    cancel_at_period_end: bool,
+    cancel_at: Option<i64>,
+    current_period_end: Option<i64>,
+    ended_at: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Invoice {
+    subscription: Option<String>,
+}
+
+fn timestamp(secs: i64) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(secs, 0)
+}
+
+/// Stripe has more states than we model; trials count as active and paused as unpaid.
+fn map_status(status: &str) -> SubscriptionStatus {
+    match status {
+        "active" | "trialing" => SubscriptionStatus::Active,
+        "past_due" => SubscriptionStatus::PastDue,
+        "canceled" => SubscriptionStatus::Canceled,
+        "incomplete" | "incomplete_expired" => SubscriptionStatus::Incomplete,
+        _ => SubscriptionStatus::Unpaid,
+    }
+}
+
+/// What an event changes about a subscription, worked out from the payload before
+/// anything touches the database.
+#[derive(Debug, PartialEq)]
+enum SubscriptionChange {
+    CheckoutCompleted {
+        user_id: Uuid,
+        customer_id: Option<String>,
+        subscription_id: Option<String>,
+    },
+    Updated {
+        subscription_id: String,
+        customer_id: String,
+        status: SubscriptionStatus,
+        ends_at: Option<DateTime<Utc>>,
+    },
+    Deleted {
+        subscription_id: String,
+        ended_at: DateTime<Utc>,
+    },
+    PaymentFailed {
+        subscription_id: String,
+    },
+}
+
+/// Returns `None` for events that don't change any subscription.
+fn parse_change(kind: &str, object: serde_json::Value, event_at: DateTime<Utc>) -> Result<Option<SubscriptionChange>, String> {
+    let change = match kind {
+        "checkout.session.completed" => {
+            let session: CheckoutSession = serde_json::from_value(object).map_err(|e| e.to_string())?;
+
+            // Checkout sessions are created with our user id as the client reference.
+            let Some(user_id) = session.client_reference_id.as_deref().and_then(|id| Uuid::parse_str(id).ok()) else {
+                log::warn!("Checkout session completed without a valid client_reference_id");
+                return Ok(None);
+            };
+
+            SubscriptionChange::CheckoutCompleted {
+                user_id,
+                customer_id: session.customer,
+                subscription_id: session.subscription,
+            }
+        }
+        "customer.subscription.created" | "customer.subscription.updated" => {
+            let subscription: StripeSubscription = serde_json::from_value(object).map_err(|e| e.to_string())?;
+            let status = map_status(&subscription.status);
+
+            // A subscription set to cancel stays active until the period it was paid for ends.
+            let ends_at = if status == SubscriptionStatus::Canceled {
+                subscription.ended_at.or(subscription.current_period_end)
+            } else if subscription.cancel_at_period_end {
+                subscription.current_period_end.or(subscription.cancel_at)
+            } else {
+                subscription.cancel_at
+            }
+            .and_then(timestamp);
+
+            SubscriptionChange::Updated {
+                subscription_id: subscription.id,
+                customer_id: subscription.customer,
+                status,
+                ends_at,
+            }
+        }
+        "customer.subscription.deleted" => {
+            let subscription: StripeSubscription = serde_json::from_value(object).map_err(|e| e.to_string())?;
+
+            SubscriptionChange::Deleted {
+                subscription_id: subscription.id,
+                ended_at: subscription.ended_at.and_then(timestamp).unwrap_or(event_at),
+            }
+        }
+        "invoice.payment_failed" => {
+            let invoice: Invoice = serde_json::from_value(object).map_err(|e| e.to_string())?;
+
+            // One-off invoices have no subscription to mark.
+            let Some(subscription_id) = invoice.subscription else {
+                return Ok(None);
+            };
+
+            SubscriptionChange::PaymentFailed { subscription_id }
+        }
+        _ => return Ok(None),
+    };
+
+    Ok(Some(change))
+}
+
+/// Records the event in `billing_events` and applies it in one transaction, so
+/// redeliveries are acknowledged without being applied twice. Events older than the
+/// last one applied to a subscription are ignored. Returns `false` for duplicates.
+///
+/// An event for a subscription we don't have yet (Stripe doesn't guarantee delivery
+/// order) is an error and isn't recorded, so the webhook answers non-2xx and Stripe
+/// retries it once the subscription exists.
+pub async fn process_event(db: &Pool<Postgres>, event: StripeEvent) -> Result<bool, String> {
+    let event_at = timestamp(event.created).ok_or("Invalid event timestamp")?;
+    let change = parse_change(&event.kind, event.data.object, event_at)?;
+    let mut tx = db.begin().await.map_err(|e| e.to_string())?;
+
+    let recorded = sqlx::query!(
+        r#"
+        INSERT INTO billing_events (id, type)
+        VALUES ($1, $2)
+        ON CONFLICT (id) DO NOTHING
+        "#,
+        event.id,
+        event.kind
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    if recorded.rows_affected() == 0 {
+        return Ok(false);
+    }
+
+    if let Some(change) = change
+        && !apply_change(&mut tx, change, event_at).await?
+    {
+        // Dropping the transaction rolls back the billing_events row too.
+        return Err("No subscription matches the event".to_string());
+    }
+
+    tx.commit().await.map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// Locks the subscription the change is for and applies it unless a newer event
+/// already has. Returns `false` when there's no such subscription.
+async fn apply_change(
+    tx: &mut Transaction<'_, Postgres>,
+    change: SubscriptionChange,
+    event_at: DateTime<Utc>,
+) -> Result<bool, String> {
+    match change {
+        SubscriptionChange::CheckoutCompleted { user_id, customer_id, subscription_id } => {
+            let Some(id) = sqlx::query_scalar!(
+                "SELECT id FROM subscriptions WHERE user_id = $1 FOR UPDATE",
+                user_id
+            )
+            .fetch_optional(&mut **tx)
+            .await
+            .map_err(|e| e.to_string())? else {
+                return Ok(false);
+            };
+
+            sqlx::query!(
+                r#"
+                UPDATE subscriptions
+                   SET plan = $2,
+                       status = $3,
+                       stripe_customer_id = COALESCE($4, stripe_customer_id),
+                       stripe_subscription_id = COALESCE($5, stripe_subscription_id),
+                       ends_at = NULL,
+                       billing_event_at = $6,
+                       updated_at = CURRENT_TIMESTAMP
+                 WHERE id = $1
+                   AND (billing_event_at IS NULL OR billing_event_at <= $6)
+                "#,
+                id,
+                SubscriptionPlan::Pro as SubscriptionPlan,
+                SubscriptionStatus::Active as SubscriptionStatus,
+                customer_id,
+                subscription_id,
+                event_at
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+        SubscriptionChange::Updated { subscription_id, customer_id, status, ends_at } => {
+            let Some(id) = sqlx::query_scalar!(
+                r#"
+                SELECT id
+                  FROM subscriptions
+                 WHERE stripe_subscription_id = $1 OR (stripe_subscription_id IS NULL AND stripe_customer_id = $2)
+                   FOR UPDATE
+                "#,
+                subscription_id,
+                customer_id
+            )
+            .fetch_optional(&mut **tx)
+            .await
+            .map_err(|e| e.to_string())? else {
+                return Ok(false);
+            };
+
+            sqlx::query!(
+                r#"
+                UPDATE subscriptions
+                   SET plan = $3,
+                       status = $4,
+                       ends_at = $5,
+                       stripe_subscription_id = $2,
+                       billing_event_at = $6,
+                       updated_at = CURRENT_TIMESTAMP
+                 WHERE id = $1
+                   AND (billing_event_at IS NULL OR billing_event_at <= $6)
+                "#,
+                id,
+                subscription_id,
+                SubscriptionPlan::Pro as SubscriptionPlan,
+                status as SubscriptionStatus,
+                ends_at,
+                event_at
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+        SubscriptionChange::Deleted { subscription_id, ended_at } => {
+            let Some(id) = subscription_for_update(tx, &subscription_id).await? else {
+                return Ok(false);
+            };
+
+            sqlx::query!(
+                r#"
+                UPDATE subscriptions
+                   SET plan = $2,
+                       status = $3,
+                       ends_at = $4,
+                       billing_event_at = $5,
+                       updated_at = CURRENT_TIMESTAMP
+                 WHERE id = $1
+                   AND (billing_event_at IS NULL OR billing_event_at <= $5)
+                "#,
+                id,
+                SubscriptionPlan::Free as SubscriptionPlan,
+                SubscriptionStatus::Canceled as SubscriptionStatus,
+                ended_at,
+                event_at
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+        SubscriptionChange::PaymentFailed { subscription_id } => {
+            let Some(id) = subscription_for_update(tx, &subscription_id).await? else {
+                return Ok(false);
+            };
+
+            sqlx::query!(
+                r#"
+                UPDATE subscriptions
+                   SET status = $2,
+                       billing_event_at = $3,
+                       updated_at = CURRENT_TIMESTAMP
+                 WHERE id = $1
+                   AND status = 'active'
+                   AND (billing_event_at IS NULL OR billing_event_at <= $3)
+                "#,
+                id,
+                SubscriptionStatus::PastDue as SubscriptionStatus,
+                event_at
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+    }
+
+    Ok(true)
+}
+
+async fn subscription_for_update(tx: &mut Transaction<'_, Postgres>, subscription_id: &str) -> Result<Option<Uuid>, String> {
+    sqlx::query_scalar!(
+        "SELECT id FROM subscriptions WHERE stripe_subscription_id = $1 FOR UPDATE",
+        subscription_id
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change_for(fixture: &str) -> Option<SubscriptionChange> {
+        let event: StripeEvent = serde_json::from_str(fixture).unwrap();
+        let event_at = timestamp(event.created).unwrap();
+        parse_change(&event.kind, event.data.object, event_at).unwrap()
+    }
+
+    #[test]
+    fn checkout_session_completed() {
+        assert_eq!(
+            change_for(include_str!("fixtures/checkout_session_completed.json")),
+            Some(SubscriptionChange::CheckoutCompleted {
+                user_id: Uuid::parse_str("2f1b6c1e-8c1a-4f4e-9a57-3c2d5e6f7a8b").unwrap(),
+                customer_id: Some("cus_Q1a2b3c4".to_string()),
+                subscription_id: Some("sub_1Q9x8y7z".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn checkout_session_without_our_reference_is_ignored() {
+        let fixture = include_str!("fixtures/checkout_session_completed.json")
+            .replace("2f1b6c1e-8c1a-4f4e-9a57-3c2d5e6f7a8b", "not-a-uuid");
+        assert_eq!(change_for(&fixture), None);
+    }
+
+    #[test]
+    fn customer_subscription_created() {
+        // Trials count as active, and nothing is scheduled to end.
+        assert_eq!(
+            change_for(include_str!("fixtures/customer_subscription_created.json")),
+            Some(SubscriptionChange::Updated {
+                subscription_id: "sub_1Q9x8y7z".to_string(),
+                customer_id: "cus_Q1a2b3c4".to_string(),
+                status: SubscriptionStatus::Active,
+                ends_at: None,
+            })
+        );
+    }
+
+    #[test]
+    fn customer_subscription_updated() {
+        // Cancelling at period end keeps the plan until the paid period runs out.
+        assert_eq!(
+            change_for(include_str!("fixtures/customer_subscription_updated.json")),
+            Some(SubscriptionChange::Updated {
+                subscription_id: "sub_1Q9x8y7z".to_string(),
+                customer_id: "cus_Q1a2b3c4".to_string(),
+                status: SubscriptionStatus::Active,
+                ends_at: timestamp(1762592000),
+            })
+        );
+    }
+
+    #[test]
+    fn customer_subscription_deleted() {
+        assert_eq!(
+            change_for(include_str!("fixtures/customer_subscription_deleted.json")),
+            Some(SubscriptionChange::Deleted {
+                subscription_id: "sub_1Q9x8y7z".to_string(),
+                ended_at: timestamp(1762592000).unwrap(),
+            })
+        );
+
+        // Without `ended_at` the subscription ends when the event was sent.
+        let fixture = include_str!("fixtures/customer_subscription_deleted.json")
+            .replace(r#""ended_at": 1762592000"#, r#""ended_at": null"#);
+        assert_eq!(
+            change_for(&fixture),
+            Some(SubscriptionChange::Deleted {
+                subscription_id: "sub_1Q9x8y7z".to_string(),
+                ended_at: timestamp(1762592005).unwrap(),
+            })
+        );
+    }
+
+    #[test]
+    fn invoice_payment_failed() {
+        assert_eq!(
+            change_for(include_str!("fixtures/invoice_payment_failed.json")),
+            Some(SubscriptionChange::PaymentFailed { subscription_id: "sub_1Q9x8y7z".to_string() })
+        );
+
+        let fixture = include_str!("fixtures/invoice_payment_failed.json")
+            .replace(r#""subscription": "sub_1Q9x8y7z""#, r#""subscription": null"#);
+        assert_eq!(change_for(&fixture), None);
+    }
+
+    #[test]
+    fn unhandled_event_types_change_nothing() {
+        let fixture = include_str!("fixtures/invoice_payment_failed.json")
+            .replace("invoice.payment_failed", "invoice.paid");
+        assert_eq!(change_for(&fixture), None);
+    }
+
+    #[test]
+    fn malformed_objects_are_errors() {
+        let event_at = timestamp(1760000000).unwrap();
+        let object = serde_json::json!({ "id": "sub_1Q9x8y7z" });
+        assert!(parse_change("customer.subscription.updated", object, event_at).is_err());
+    }
+
+    #[test]
+    fn maps_stripe_statuses() {
+        let cases = [
+            ("active", SubscriptionStatus::Active),
+            ("trialing", SubscriptionStatus::Active),
+            ("past_due", SubscriptionStatus::PastDue),
+            ("canceled", SubscriptionStatus::Canceled),
+            ("incomplete", SubscriptionStatus::Incomplete),
+            ("incomplete_expired", SubscriptionStatus::Incomplete),
+            ("unpaid", SubscriptionStatus::Unpaid),
+            ("paused", SubscriptionStatus::Unpaid),
+        ];
+        for (stripe, status) in cases {
+            assert_eq!(map_status(stripe), status, "{stripe}");
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+use crate::utils::generate_token;
+
+use super::{process_event, BillingProvider, BillingSession, CheckoutRequest, StripeEvent, StripeEventData};
+
+const PERIOD_DAYS: i64 = 30;
+
+/// In-process stand-in for Stripe. Instead of hosting pages it feeds the same
+/// events a real webhook would deliver through `process_event`, so `subscriptions`
+/// goes through exactly the same transitions.
+pub struct FakeProvider {
+    db: Pool<Postgres>,
+}
+
+impl FakeProvider {
+    pub fn new(db: Pool<Postgres>) -> Self {
+        Self { db }
+    }
+}
+
+fn fake_id(prefix: &str) -> String {
+    format!("{}_fake_{}", prefix, &generate_token()[..24])
+}
+
+fn fake_event(kind: &str, object: serde_json::Value) -> StripeEvent {
+    StripeEvent {
+        id: fake_id("evt"),
+        kind: kind.to_string(),
+        created: Utc::now().timestamp(),
+        data: StripeEventData { object },
+    }
+}
+
+fn period_end() -> i64 {
+    (Utc::now() + ChronoDuration::days(PERIOD_DAYS)).timestamp()
+}
+
+impl BillingProvider for FakeProvider {
+    /// Checkout succeeds immediately and sends the user straight to `success_url`.
+    fn create_checkout<'a>(&'a self, request: &'a CheckoutRequest) -> BoxFuture<'a, Result<BillingSession, String>> {
+        Box::pin(async move {
+            let customer_id = request.customer_id.clone().unwrap_or_else(|| fake_id("cus"));
+            let subscription_id = fake_id("sub");
+
+            process_event(&self.db, fake_event("checkout.session.completed", serde_json::json!({
+                "client_reference_id": request.user_id.to_string(),
+                "customer": customer_id,
+                "subscription": subscription_id,
+            }))).await?;
+
+            process_event(&self.db, fake_event("customer.subscription.created", serde_json::json!({
+                "id": subscription_id,
+                "customer": customer_id,
+                "status": "active",
+                "current_period_end": period_end(),
+            }))).await?;
+
+            Ok(BillingSession { url: request.success_url.clone() })
+        })
+    }
+
+    /// There is no fake portal page; use `simulate_fake_event` to act on the subscription.
+    fn create_portal<'a>(&'a self, _customer_id: &'a str, return_url: &'a str) -> BoxFuture<'a, Result<BillingSession, String>> {
+        Box::pin(async move { Ok(BillingSession { url: return_url.to_string() }) })
+    }
+}
+
+/// Things that happen on Stripe's side after checkout.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FakeAction {
+    CancelAtPeriodEnd,
+    Resume,
+    PaymentFailed,
+    Unpaid,
+    End,
+}
+
+/// Sends the event Stripe would for `action` on the user's subscription.
+/// Returns `Ok(false)` when the user has no subscription with the provider.
+pub async fn simulate_fake_event(db: &Pool<Postgres>, user_id: Uuid, action: FakeAction) -> Result<bool, String> {
+    let row = sqlx::query!(
+        r#"
+        SELECT stripe_customer_id, stripe_subscription_id
+          FROM subscriptions
+         WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let Some((customer_id, subscription_id)) = row.and_then(|r| r.stripe_customer_id.zip(r.stripe_subscription_id)) else {
+        return Ok(false);
+    };
+
+    let updated = |status: &str, cancel_at_period_end: bool| serde_json::json!({
+        "id": subscription_id,
+        "customer": customer_id,
+        "status": status,
+        "cancel_at_period_end": cancel_at_period_end,
+        "current_period_end": period_end(),
+    });
+
+    let event = match action {
+        FakeAction::CancelAtPeriodEnd => fake_event("customer.subscription.updated", updated("active", true)),
+        FakeAction::Resume => fake_event("customer.subscription.updated", updated("active", false)),
+        FakeAction::PaymentFailed => fake_event("invoice.payment_failed", serde_json::json!({ "subscription": subscription_id })),
+        FakeAction::Unpaid => fake_event("customer.subscription.updated", updated("unpaid", false)),
+        FakeAction::End => fake_event("customer.subscription.deleted", serde_json::json!({
+            "id": subscription_id,
+            "customer": customer_id,
+            "status": "canceled",
+            "ended_at": Utc::now().timestamp(),
+        })),
+    };
+
+    process_event(db, event).await?;
+    Ok(true)
+}
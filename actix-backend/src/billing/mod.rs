@@ -0,0 +1,55 @@
+use futures_util::future::BoxFuture;
+use sqlx::{Pool, Postgres};
+use uuid::Uuid;
+
+mod events;
+pub use events::{process_event, StripeEvent, StripeEventData};
+
+mod fake_provider;
+pub use fake_provider::{simulate_fake_event, FakeAction, FakeProvider};
+
+mod stripe_provider;
+pub use stripe_provider::StripeProvider;
+
+#[derive(Debug, Clone)]
+pub struct CheckoutRequest {
+    pub user_id: Uuid,
+    pub email: String,
+    /// Set when the user has paid before, so the provider reuses their customer.
+    pub customer_id: Option<String>,
+    pub success_url: String,
+    pub cancel_url: String,
+}
+
+/// A provider-hosted page the client should redirect to.
+#[derive(Debug, Clone)]
+pub struct BillingSession {
+    pub url: String,
+}
+
+pub trait BillingProvider: Send + Sync {
+    /// Starts an upgrade to Pro for the user.
+    fn create_checkout<'a>(&'a self, request: &'a CheckoutRequest) -> BoxFuture<'a, Result<BillingSession, String>>;
+
+    /// Opens the page where an existing customer manages or cancels their subscription.
+    fn create_portal<'a>(&'a self, customer_id: &'a str, return_url: &'a str) -> BoxFuture<'a, Result<BillingSession, String>>;
+}
+
+pub fn uses_fake_provider() -> bool {
+    std::env::var("BILLING_PROVIDER").as_deref() == Ok("fake")
+}
+
+/// Picks a backend from `BILLING_PROVIDER` (`stripe` or `fake`). Unset means billing
+/// is disabled; the fake has to be chosen explicitly since it upgrades without payment.
+pub fn from_env(db: Pool<Postgres>) -> Option<Box<dyn BillingProvider>> {
+    match std::env::var("BILLING_PROVIDER").as_deref() {
+        Ok("stripe") => {
+            let secret_key = std::env::var("STRIPE_SECRET_KEY").expect("STRIPE_SECRET_KEY must be set when BILLING_PROVIDER=stripe");
+            let price_id = std::env::var("STRIPE_PRICE_ID").expect("STRIPE_PRICE_ID must be set when BILLING_PROVIDER=stripe");
+            let api_base = std::env::var("STRIPE_API_BASE").unwrap_or_else(|_| "https://api.stripe.com".to_string());
+            Some(Box::new(StripeProvider::new(api_base, secret_key, price_id)))
+        }
+        Ok("fake") => Some(Box::new(FakeProvider::new(db))),
+        _ => None,
+    }
+}
@@ -0,0 +1,71 @@
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+
+use super::{BillingProvider, BillingSession, CheckoutRequest};
+
+/// Talks to the Stripe API (or anything speaking it, like stripe-mock via `STRIPE_API_BASE`).
+pub struct StripeProvider {
+    client: reqwest::Client,
+    api_base: String,
+    secret_key: String,
+    price_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct StripeSession {
+    url: String,
+}
+
+impl StripeProvider {
+    pub fn new(api_base: String, secret_key: String, price_id: String) -> Self {
+        Self { client: reqwest::Client::new(), api_base, secret_key, price_id }
+    }
+
+    async fn create_session(&self, path: &str, form: &[(&str, &str)]) -> Result<BillingSession, String> {
+        let res = self.client
+            .post(format!("{}/v1/{}", self.api_base.trim_end_matches('/'), path))
+            .bearer_auth(&self.secret_key)
+            .form(form)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await.unwrap_or_default();
+            return Err(format!("Stripe returned {}: {}", status, body));
+        }
+
+        let session: StripeSession = res.json().await.map_err(|e| e.to_string())?;
+        Ok(BillingSession { url: session.url })
+    }
+}
+
+impl BillingProvider for StripeProvider {
+    fn create_checkout<'a>(&'a self, request: &'a CheckoutRequest) -> BoxFuture<'a, Result<BillingSession, String>> {
+        Box::pin(async move {
+            let user_id = request.user_id.to_string();
+            let mut form = vec![
+                ("mode", "subscription"),
+                ("line_items[0][price]", self.price_id.as_str()),
+                ("line_items[0][quantity]", "1"),
+                // The webhook maps the completed session back to the user through this.
+                ("client_reference_id", user_id.as_str()),
+                ("success_url", request.success_url.as_str()),
+                ("cancel_url", request.cancel_url.as_str()),
+            ];
+            match &request.customer_id {
+                Some(customer_id) => form.push(("customer", customer_id.as_str())),
+                None => form.push(("customer_email", request.email.as_str())),
+            }
+
+            self.create_session("checkout/sessions", &form).await
+        })
+    }
+
+    fn create_portal<'a>(&'a self, customer_id: &'a str, return_url: &'a str) -> BoxFuture<'a, Result<BillingSession, String>> {
+        Box::pin(async move {
+            self.create_session("billing_portal/sessions", &[("customer", customer_id), ("return_url", return_url)]).await
+        })
+    }
+}
@@ -10,8 +10,9 @@ use sqlx::{Error};
 use uuid::Uuid;
 
 use crate::{
+    handlers::verification_handler::send_verification_email,
     models::{Claims, UserData},
-    utils::test_password,
+    utils::{clear_login_failures, generate_token, hash_token, login_lockout, record_login_failure, test_password},
     AppState,
 };
 
@@ -46,24 +47,33 @@ pub async fn register(
     let res = sqlx::query!(
         r#"
         WITH new_user AS (
-          INSERT INTO users (email, username, full_name, password_hash)
-          VALUES ($1, $2, $3, $4)
+          INSERT INTO users (email, username, full_name, password_hash, email_verified)
+          VALUES ($1, $2, $3, $4, FALSE)
           RETURNING id
+        ),
+        new_subscription AS (
+          INSERT INTO subscriptions (user_id)
+          SELECT id FROM new_user
         )
-        INSERT INTO subscriptions (user_id)
         SELECT id FROM new_user;
         "#,
-        req.email,
+        req.email.trim(),
         req.username,
         req.full_name,
         password_hash
     )
-    .execute(&app_state.db)
+    .fetch_one(&app_state.db)
     .await;
 
     match res {
-        Ok(_) => HttpResponse::Created().json(serde_json::json!({ "error": null })),
-        Err(Error::Database(db)) if db.message().contains("users_email_key") => {
+        Ok(user) => {
+            // The account exists either way; a failed send can be retried via /verify-email/resend.
+            if let Err(e) = send_verification_email(&app_state, user.id, req.email.trim()).await {
+                log::error!("Failed to send verification email: {}", e);
+            }
+            HttpResponse::Created().json(serde_json::json!({ "error": null }))
+        }
+        Err(Error::Database(db)) if matches!(db.constraint(), Some("users_email_key" | "users_email_lower_key")) => {
             HttpResponse::Conflict().json(serde_json::json!({ "error": "Email already registered" }))
         }
         Err(Error::Database(db)) if db.message().contains("users_username_key") => {
@@ -94,9 +104,36 @@ pub async fn login(
             .json(serde_json::json!({ "error": "Missing required fields" }));
     }
 
+    let ip_address = req
+        .connection_info()
+        .realip_remote_addr()
+        .map(str::to_owned);
+
+    match login_lockout(&app_state.db, &body.email, ip_address.as_deref()).await {
+        Ok(None) => (),
+        Ok(Some(retry_after)) => {
+            return HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .json(serde_json::json!({ "error": "Too many login attempts, try again later" }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": "DB query error" }));
+        }
+    }
+
     let row = sqlx::query!(
-        "SELECT id, password_hash FROM users WHERE email = $1",
-        body.email
+        r#"
+        SELECT
+          u.id,
+          u.password_hash,
+          COALESCE(m.enabled, FALSE) AS "mfa_enabled!"
+        FROM users u
+        LEFT JOIN user_mfa m
+          ON m.user_id = u.id
+        WHERE lower(u.email) = lower($1)
+        "#,
+        body.email.trim()
     )
     .fetch_optional(&app_state.db)
     .await;
@@ -104,6 +141,9 @@ pub async fn login(
     let row = match row {
         Ok(Some(r)) => r,
         Ok(None) => {
+            if let Err(e) = record_login_failure(&app_state.db, &body.email, ip_address.as_deref()).await {
+                log::error!("Failed to record login failure: {}", e);
+            }
             return HttpResponse::Unauthorized()
                 .json(serde_json::json!({ "error": "Invalid credentials" }));
         }
@@ -116,6 +156,9 @@ pub async fn login(
     match verify(&body.password, &row.password_hash) {
         Ok(true) => (),
         Ok(false) => {
+            if let Err(e) = record_login_failure(&app_state.db, &body.email, ip_address.as_deref()).await {
+                log::error!("Failed to record login failure: {}", e);
+            }
             return HttpResponse::Unauthorized()
                 .json(serde_json::json!({ "error": "Invalid credentials" }));
         }
@@ -125,21 +168,102 @@ pub async fn login(
         }
     }
 
-    let user_id = row.id;
+    // With two-factor on, failures are only cleared once the second step passes.
+    if row.mfa_enabled {
+        let mfa_token = generate_token();
+        let expires_at = Utc::now() + ChronoDuration::minutes(5);
+
+        let res = sqlx::query!(
+            r#"
+            INSERT INTO mfa_challenges (user_id, token_hash, expires_at)
+            VALUES ($1, $2, $3)
+            "#,
+            row.id,
+            hash_token(&mfa_token),
+            expires_at
+        )
+        .execute(&app_state.db)
+        .await;
+
+        if res.is_err() {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": "MFA challenge creation failed" }));
+        }
+
+        return HttpResponse::Ok()
+            .json(serde_json::json!({ "mfaRequired": true, "mfaToken": mfa_token, "error": null }));
+    }
+
+    if let Err(e) = clear_login_failures(&app_state.db, &body.email).await {
+        log::error!("Failed to clear login failures: {}", e);
+    }
+
+    start_session(&app_state, &req, row.id).await
+}
+
+/// Issues the access/refresh token pair for `user_id`, stores the session for
+/// the caller's device and sets the `jwt` and `device_id` cookies.
+pub async fn start_session(
+    app_state: &AppState,
+    req: &HttpRequest,
+    user_id: Uuid,
+) -> HttpResponse {
+    let device_id = match req.cookie("device_id").map(|c| c.value().to_string()) {
+        Some(id) => id,
+        None => Uuid::new_v4().to_string(),
+    };
+
+    let session = match issue_session(app_state, req, user_id, device_id).await {
+        Ok(s) => s,
+        Err(res) => return res,
+    };
+
+    let refresh_cookie = Cookie::build("jwt", session.refresh_token)
+        .http_only(true)
+        .same_site(SameSite::None)
+        .secure(true)
+        .max_age(time::Duration::hours(24))
+        .path("/")
+        .finish();
+
+    let device_cookie = Cookie::build("device_id", session.device_id)
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .secure(true)
+        .max_age(time::Duration::days(365))
+        .path("/")
+        .finish();
+
+    HttpResponse::Ok()
+        .cookie(refresh_cookie)
+        .cookie(device_cookie)
+        .json(serde_json::json!({ "accessToken": session.access_token, "error": null }))
+}
+
+pub struct IssuedSession {
+    pub access_token: String,
+    pub refresh_token: String,
+    pub device_id: String,
+}
+
+/// Creates (or re-activates) the `user_sessions` row for `device_id` and
+/// returns fresh tokens for it. Errors come back as ready-to-send responses.
+pub async fn issue_session(
+    app_state: &AppState,
+    req: &HttpRequest,
+    user_id: Uuid,
+    device_id: String,
+) -> Result<IssuedSession, HttpResponse> {
     let now = Utc::now();
     let access_exp = now + ChronoDuration::minutes(15);
     let refresh_exp = now + ChronoDuration::hours(24);
 
-    let access_claims = Claims { exp: access_exp.timestamp() as usize, user: UserData { id: user_id } };
-    let refresh_claims = Claims { exp: refresh_exp.timestamp() as usize, user: UserData { id: user_id } };
+    let access_claims = Claims { exp: access_exp.timestamp() as usize, user: UserData { id: user_id }, jti: Uuid::new_v4() };
+    let refresh_claims = Claims { exp: refresh_exp.timestamp() as usize, user: UserData { id: user_id }, jti: Uuid::new_v4() };
 
-    let access_token = match encode(
-        &Header::default(),
-        &access_claims,
-        &EncodingKey::from_secret(app_state.jwt_access_secret.as_bytes()),
-    ) {
+    let access_token = match app_state.jwt_keys.sign(&access_claims) {
         Ok(token) => token,
-        Err(_) => return HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Access token creation failed" }))
+        Err(_) => return Err(HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Access token creation failed" })))
     };
 
     let refresh_token = match encode(
@@ -148,7 +272,7 @@ pub async fn login(
         &EncodingKey::from_secret(app_state.jwt_refresh_secret.as_bytes()),
     ) {
         Ok(token) => token,
-        Err(_) => return HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Refresh token creation failed" }))
+        Err(_) => return Err(HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Refresh token creation failed" })))
     };
 
     let user_agent = req.headers()
@@ -160,22 +284,26 @@ pub async fn login(
         .realip_remote_addr()
         .map(str::to_owned);
 
-    let device_id = match req.cookie("device_id").map(|c| c.value().to_string()) {
-        Some(id) => id,
-        None => Uuid::new_v4().to_string(),
-    };
-
+    // Signing in again on a device starts its session over, so tokens rotated
+    // out before that no longer count as reuse of this session.
     let updated = sqlx::query!(
         r#"
-        UPDATE user_sessions
-           SET refresh_token = $1,
-               user_agent    = $2,
-               ip_address    = $3,
-               last_used_at  = CURRENT_TIMESTAMP
-         WHERE user_id    = $4
-           AND device_id  = $5
-           AND revoked     = false
-        RETURNING id
+        WITH updated AS (
+            UPDATE user_sessions
+               SET refresh_token = $1,
+                   user_agent    = $2,
+                   ip_address    = $3,
+                   last_used_at  = CURRENT_TIMESTAMP,
+                   revoked       = false
+             WHERE user_id    = $4
+               AND device_id  = $5
+            RETURNING id
+        ),
+        forgotten AS (
+            DELETE FROM user_session_rotated_tokens
+             WHERE session_id IN (SELECT id FROM updated)
+        )
+        SELECT id AS "id!" FROM updated
         "#,
         refresh_token,
         user_agent,
@@ -203,37 +331,18 @@ pub async fn login(
             .execute(&app_state.db)
             .await;
 
-            if let Err(_) = res {
-                return HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Session insert failed" }));
+            if res.is_err() {
+                return Err(HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Session insert failed" })));
             };
         }
         Err(_) => {
-            return HttpResponse::InternalServerError()
-                .json(serde_json::json!({ "error": "Session rotation failed" }));
+            return Err(HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": "Session rotation failed" })));
         }
         _ => {}
     }
 
-    let refresh_cookie = Cookie::build("jwt", refresh_token.clone())
-        .http_only(true)
-        .same_site(SameSite::None)
-        .secure(true)
-        .max_age(time::Duration::hours(24))
-        .path("/")
-        .finish();
-
-    let device_cookie = Cookie::build("device_id", device_id.clone())
-        .http_only(true)
-        .same_site(SameSite::Lax)
-        .secure(true)
-        .max_age(time::Duration::days(365))
-        .path("/")
-        .finish();
-
-    HttpResponse::Ok()
-        .cookie(refresh_cookie)
-        .cookie(device_cookie)
-        .json(serde_json::json!({ "accessToken": access_token, "error": null }))
+    Ok(IssuedSession { access_token, refresh_token, device_id })
 }
 
 #[post("/logout")]
@@ -281,7 +390,7 @@ pub async fn logout(
     .execute(&app_state.db)
     .await;
 
-    if let Err(_) = res {
+    if res.is_err() {
         return HttpResponse::InternalServerError()
             .json(serde_json::json!({ "error": "Failed to revoke session" }));
     }
@@ -310,22 +419,115 @@ pub async fn logout(
         .json(serde_json::json!({ "error": null }))
 }
 
+/// Answers a refresh with a token that isn't the session's current one. A token
+/// we rotated out means it leaked, so the device's session is revoked.
+async fn reject_reused_refresh_token(
+    app_state: &AppState,
+    refresh_token: &str,
+    user_id: Uuid,
+    device_id: &str,
+) -> HttpResponse {
+    let reused = sqlx::query!(
+        r#"
+        SELECT s.id
+          FROM user_session_rotated_tokens rt
+          JOIN user_sessions s
+            ON s.id = rt.session_id
+         WHERE rt.refresh_token = $1
+           AND s.user_id        = $2
+           AND s.device_id      = $3
+        "#,
+        refresh_token,
+        user_id,
+        device_id
+    )
+    .fetch_optional(&app_state.db)
+    .await;
+
+    match reused {
+        Ok(Some(_)) => {
+            log::warn!("Refresh token reuse detected for user {} on device {}", user_id, device_id);
+
+            let res = sqlx::query!(
+                r#"
+                UPDATE user_sessions
+                   SET revoked = TRUE
+                 WHERE user_id   = $1
+                   AND device_id = $2
+                "#,
+                user_id,
+                device_id
+            )
+            .execute(&app_state.db)
+            .await;
+
+            if res.is_err() {
+                return HttpResponse::InternalServerError()
+                    .json(serde_json::json!({ "error": "Failed to revoke session" }));
+            }
+
+            let mut clear_jwt = Cookie::build("jwt", "")
+                .http_only(true)
+                .same_site(SameSite::None)
+                .secure(true)
+                .path("/")
+                .finish();
+            clear_jwt.make_removal();
+
+            let mut clear_dev = Cookie::build("device_id", "")
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .secure(true)
+                .path("/")
+                .finish();
+            clear_dev.make_removal();
+
+            HttpResponse::Unauthorized()
+                .cookie(clear_jwt)
+                .cookie(clear_dev)
+                .json(serde_json::json!({ "error": "Refresh token reuse detected" }))
+        }
+        Ok(None) => HttpResponse::Unauthorized()
+            .json(serde_json::json!({ "error": "Invalid or revoked session" })),
+        Err(_) => HttpResponse::InternalServerError()
+            .json(serde_json::json!({ "error": "DB error fetching session" })),
+    }
+}
+
+/// Clients without cookies, such as editors signed in through `/device/token`,
+/// send the tokens they were given in the body instead.
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+    pub device_id: String,
+}
+
+/// Browsers refresh with the `jwt` and `device_id` cookies and get the new refresh
+/// token as a cookie. Clients that send a body get it back in the body.
 #[post("/refresh")]
 pub async fn refresh(
     app_state: web::Data<AppState>,
-    req: HttpRequest
+    req: HttpRequest,
+    refresh_json: Option<web::Json<RefreshRequest>>,
 ) -> impl Responder {
-    let refresh_token = if let Some(c) = req.cookie("jwt") {
-        c.value().to_string()
-    } else {
-        return HttpResponse::Unauthorized()
-            .json(serde_json::json!({ "error": "No refresh token cookie" }));
-    };
-    let device_id = if let Some(c) = req.cookie("device_id") {
-        c.value().to_string()
-    } else {
-        return HttpResponse::Unauthorized()
-            .json(serde_json::json!({ "error": "No device_id cookie" }));
+    let from_body = refresh_json.is_some();
+    let (refresh_token, device_id) = match refresh_json.map(web::Json::into_inner) {
+        Some(body) => (body.refresh_token, body.device_id),
+        None => {
+            let refresh_token = if let Some(c) = req.cookie("jwt") {
+                c.value().to_string()
+            } else {
+                return HttpResponse::Unauthorized()
+                    .json(serde_json::json!({ "error": "No refresh token cookie" }));
+            };
+            let device_id = if let Some(c) = req.cookie("device_id") {
+                c.value().to_string()
+            } else {
+                return HttpResponse::Unauthorized()
+                    .json(serde_json::json!({ "error": "No device_id cookie" }));
+            };
+            (refresh_token, device_id)
+        }
     };
 
     let token_data = match decode::<Claims>(
@@ -341,30 +543,96 @@ pub async fn refresh(
     };
     let user_id = token_data.claims.user.id;
 
-    let session = match sqlx::query!(
+    let now = Utc::now();
+    let access_exp = now + ChronoDuration::minutes(15);
+    let refresh_exp = now + ChronoDuration::hours(24);
+
+    let access_claims = Claims { exp: access_exp.timestamp() as usize, user: UserData { id: user_id }, jti: Uuid::new_v4() };
+    let refresh_claims = Claims { exp: refresh_exp.timestamp() as usize, user: UserData { id: user_id }, jti: Uuid::new_v4() };
+
+    let new_refresh_token = match encode(
+        &Header::default(),
+        &refresh_claims,
+        &EncodingKey::from_secret(app_state.jwt_refresh_secret.as_bytes()),
+    ) {
+        Ok(tok) => tok,
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": "Failed to create refresh token" }));
+        }
+    };
+
+    // Swap the presented token for the new one and remember the old one, so a
+    // second use of it can be told apart from a token we never issued. Refresh
+    // tokens expire after 24 hours and are rejected before this point, so older
+    // entries can't match anything and are pruned along the way.
+    let rotated = sqlx::query!(
         r#"
-        SELECT *
-          FROM user_sessions
-         WHERE user_id      = $1
-           AND device_id    = $2
-           AND refresh_token= $3
-           AND revoked      = FALSE
+        WITH rotated AS (
+            UPDATE user_sessions
+               SET refresh_token = $4,
+                   last_used_at  = CURRENT_TIMESTAMP
+             WHERE user_id       = $1
+               AND device_id     = $2
+               AND refresh_token = $3
+               AND revoked       = FALSE
+            RETURNING id
+        ),
+        pruned AS (
+            DELETE FROM user_session_rotated_tokens
+             WHERE rotated_at < CURRENT_TIMESTAMP - INTERVAL '24 hours'
+        )
+        INSERT INTO user_session_rotated_tokens (session_id, refresh_token)
+        SELECT id, $3 FROM rotated
+        RETURNING session_id
         "#,
         user_id,
         device_id,
-        refresh_token
+        refresh_token,
+        new_refresh_token
     )
     .fetch_optional(&app_state.db)
-    .await
-    {
-        Ok(Some(s)) => s,
+    .await;
+
+    let new_refresh_token = match rotated {
+        Ok(Some(_)) => new_refresh_token,
         Ok(None) => {
-            return HttpResponse::Unauthorized()
-                .json(serde_json::json!({ "error": "Invalid or revoked session" }));
+            // Tabs sharing the cookie can refresh at the same moment, so a token
+            // rotated in the last 30 seconds may be presented once more. That
+            // request gets the session's current token instead of a new one.
+            let grace = sqlx::query_scalar!(
+                r#"
+                UPDATE user_session_rotated_tokens rt
+                   SET grace_used = TRUE
+                  FROM user_sessions s
+                 WHERE s.id              = rt.session_id
+                   AND rt.refresh_token  = $1
+                   AND s.user_id         = $2
+                   AND s.device_id       = $3
+                   AND s.revoked         = FALSE
+                   AND rt.grace_used     = FALSE
+                   AND rt.rotated_at     > CURRENT_TIMESTAMP - INTERVAL '30 seconds'
+                RETURNING s.refresh_token
+                "#,
+                refresh_token,
+                user_id,
+                device_id
+            )
+            .fetch_optional(&app_state.db)
+            .await;
+
+            match grace {
+                Ok(Some(current_token)) => current_token,
+                Ok(None) => return reject_reused_refresh_token(&app_state, &refresh_token, user_id, &device_id).await,
+                Err(_) => {
+                    return HttpResponse::InternalServerError()
+                        .json(serde_json::json!({ "error": "DB error fetching session" }));
+                }
+            }
         }
         Err(_) => {
             return HttpResponse::InternalServerError()
-                .json(serde_json::json!({ "error": "DB error fetching session" }));
+                .json(serde_json::json!({ "error": "Session rotation failed" }));
         }
     };
 
@@ -374,6 +642,7 @@ pub async fn refresh(
           u.email,
           u.username,
           u.profile_picture_url,
+          COALESCE(u.email_verified, FALSE) AS "email_verified!",
           COALESCE(s.plan::TEXT, 'free') AS subscription_plan
         FROM users u
         LEFT JOIN subscriptions s
@@ -392,14 +661,7 @@ pub async fn refresh(
         }
     };
 
-    let exp = Utc::now() + ChronoDuration::minutes(15);
-    let claims = Claims { exp: exp.timestamp() as usize, user: UserData { id: session.user_id } };
-
-    let access_token = match encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(app_state.jwt_access_secret.as_bytes()),
-    ) {
+    let access_token = match app_state.jwt_keys.sign(&access_claims) {
         Ok(tok) => tok,
         Err(_) => {
             return HttpResponse::InternalServerError()
@@ -407,14 +669,33 @@ pub async fn refresh(
         }
     };
 
-   HttpResponse::Ok().json(serde_json::json!({
+    let mut body = serde_json::json!({
         "accessToken": access_token,
         "user": {
             "email": row.email,
             "username": row.username,
             "profilePicture": row.profile_picture_url,
+            "emailVerified": row.email_verified,
             "subscriptionPlan": row.subscription_plan,
         },
         "error": null
-    }))
+    });
+
+    if from_body {
+        body["refresh_token"] = serde_json::json!(new_refresh_token);
+        body["device_id"] = serde_json::json!(device_id);
+        return HttpResponse::Ok().json(body);
+    }
+
+    let refresh_cookie = Cookie::build("jwt", new_refresh_token)
+        .http_only(true)
+        .same_site(SameSite::None)
+        .secure(true)
+        .max_age(time::Duration::hours(24))
+        .path("/")
+        .finish();
+
+    HttpResponse::Ok()
+        .cookie(refresh_cookie)
+        .json(body)
 }
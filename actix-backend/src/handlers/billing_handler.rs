@@ -0,0 +1,149 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use chrono::Utc;
+use serde::Deserialize;
+
+use crate::{
+    billing::{process_event, simulate_fake_event, CheckoutRequest, FakeAction, StripeEvent},
+    models::{Entitlements, SubscriptionPlan, UserData},
+    utils::verify_stripe_signature,
+    AppState,
+};
+
+#[post("/checkout")]
+pub async fn checkout(
+    app_state: web::Data<AppState>,
+    user_data: web::ReqData<UserData>,
+    entitlements: Entitlements,
+) -> impl Responder {
+    let Some(billing) = app_state.billing.as_deref() else {
+        return HttpResponse::ServiceUnavailable().json(serde_json::json!({ "error": "Billing is not configured" }));
+    };
+
+    if entitlements.plan == SubscriptionPlan::Pro {
+        return HttpResponse::Conflict().json(serde_json::json!({ "error": "Already subscribed to Pro" }));
+    }
+
+    let row = sqlx::query!(
+        r#"
+        SELECT u.email, s.stripe_customer_id AS "stripe_customer_id?"
+          FROM users u
+          LEFT JOIN subscriptions s ON s.user_id = u.id
+         WHERE u.id = $1
+        "#,
+        user_data.id
+    )
+    .fetch_one(&app_state.db)
+    .await;
+
+    let Ok(row) = row else {
+        return HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Server error" }));
+    };
+
+    let request = CheckoutRequest {
+        user_id: user_data.id,
+        email: row.email,
+        customer_id: row.stripe_customer_id,
+        success_url: format!("{}/billing?checkout=success", app_state.app_url),
+        cancel_url: format!("{}/billing?checkout=canceled", app_state.app_url),
+    };
+
+    match billing.create_checkout(&request).await {
+        Ok(session) => HttpResponse::Ok().json(serde_json::json!({ "url": session.url })),
+        Err(e) => {
+            log::error!("Failed to create checkout session: {}", e);
+            HttpResponse::BadGateway().json(serde_json::json!({ "error": "Billing provider error" }))
+        }
+    }
+}
+
+#[post("/portal")]
+pub async fn portal(
+    app_state: web::Data<AppState>,
+    user_data: web::ReqData<UserData>,
+) -> impl Responder {
+    let Some(billing) = app_state.billing.as_deref() else {
+        return HttpResponse::ServiceUnavailable().json(serde_json::json!({ "error": "Billing is not configured" }));
+    };
+
+    let customer_id = sqlx::query_scalar!(
+        r#"SELECT stripe_customer_id FROM subscriptions WHERE user_id = $1"#,
+        user_data.id
+    )
+    .fetch_optional(&app_state.db)
+    .await;
+
+    let customer_id = match customer_id {
+        Ok(Some(Some(id))) => id,
+        Ok(_) => return HttpResponse::Conflict().json(serde_json::json!({ "error": "No billing account yet" })),
+        Err(_) => return HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Server error" })),
+    };
+
+    let return_url = format!("{}/billing", app_state.app_url);
+
+    match billing.create_portal(&customer_id, &return_url).await {
+        Ok(session) => HttpResponse::Ok().json(serde_json::json!({ "url": session.url })),
+        Err(e) => {
+            log::error!("Failed to create portal session: {}", e);
+            HttpResponse::BadGateway().json(serde_json::json!({ "error": "Billing provider error" }))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FakeEventRequest {
+    pub action: FakeAction,
+}
+
+/// Only mounted with `BILLING_PROVIDER=fake`; stands in for what a user or Stripe
+/// would do from the hosted portal.
+#[post("/fake/events")]
+pub async fn fake_event(
+    app_state: web::Data<AppState>,
+    user_data: web::ReqData<UserData>,
+    body: web::Json<FakeEventRequest>,
+) -> impl Responder {
+    match simulate_fake_event(&app_state.db, user_data.id, body.action).await {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::Conflict().json(serde_json::json!({ "error": "No billing account yet" })),
+        Err(e) => {
+            log::error!("Failed to simulate billing event: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Server error" }))
+        }
+    }
+}
+
+#[post("/webhook")]
+pub async fn webhook(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    body: web::Bytes,
+) -> impl Responder {
+    let Some(secret) = app_state.stripe_webhook_secret.as_deref() else {
+        return HttpResponse::ServiceUnavailable().json(serde_json::json!({ "error": "Billing webhooks are not configured" }));
+    };
+
+    let Some(signature) = req.headers().get("Stripe-Signature").and_then(|h| h.to_str().ok()) else {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Missing Stripe-Signature header" }));
+    };
+
+    if let Err(e) = verify_stripe_signature(signature, &body, secret, Utc::now().timestamp()) {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": e }));
+    }
+
+    let event: StripeEvent = match serde_json::from_slice(&body) {
+        Ok(event) => event,
+        Err(_) => return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Invalid event payload" })),
+    };
+
+    let (id, kind) = (event.id.clone(), event.kind.clone());
+
+    // Leaving the event unrecorded on failure lets Stripe's retry apply it later.
+    match process_event(&app_state.db, event).await {
+        Ok(true) => HttpResponse::Ok().json(serde_json::json!({ "received": true })),
+        Ok(false) => HttpResponse::Ok().json(serde_json::json!({ "received": true, "duplicate": true })),
+        Err(e) => {
+            log::error!("Failed to apply billing event {} ({}): {}", id, kind, e);
+            HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Server error" }))
+        }
+    }
+}
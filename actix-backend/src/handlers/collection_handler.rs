@@ -0,0 +1,510 @@
+use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, Pool, Postgres};
+use uuid::Uuid;
+
+use crate::{models::{SnippetVisibility, UserData}, AppState};
+
+#[derive(Serialize, FromRow)]
+pub struct CollectionData {
+    pub id:            Uuid,
+    pub owner_id:      Uuid,
+    pub parent_id:     Option<Uuid>,
+    pub name:          String,
+    pub description:   Option<String>,
+    pub visibility:    SnippetVisibility,
+    pub position:      i32,
+    pub snippet_count: i64,
+    pub created_at:    DateTime<Utc>,
+}
+
+async fn owns_collection(db: &Pool<Postgres>, collection_id: Uuid, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM snippets_extension.collections WHERE id = $1 AND owner_id = $2
+        ) AS "exists!"
+        "#,
+        collection_id,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+}
+
+/// Checks that `parent_id` is one of the user's collections and, when moving an
+/// existing collection, isn't the collection itself or one of its descendants.
+async fn valid_parent(db: &Pool<Postgres>, parent_id: Uuid, user_id: Uuid, moving: Option<Uuid>) -> Result<bool, sqlx::Error> {
+    if !owns_collection(db, parent_id, user_id).await? {
+        return Ok(false);
+    }
+
+    let Some(collection_id) = moving else {
+        return Ok(true);
+    };
+
+    let creates_cycle = sqlx::query_scalar!(
+        r#"
+        WITH RECURSIVE subtree AS (
+            SELECT id FROM snippets_extension.collections WHERE id = $1
+            UNION ALL
+            SELECT c.id
+              FROM snippets_extension.collections c
+              JOIN subtree ON c.parent_id = subtree.id
+        )
+        SELECT EXISTS(SELECT 1 FROM subtree WHERE id = $2) AS "exists!"
+        "#,
+        collection_id,
+        parent_id
+    )
+    .fetch_one(db)
+    .await?;
+
+    Ok(!creates_cycle)
+}
+
+#[derive(Deserialize)]
+pub struct CollectionRequest {
+    pub name:        String,
+    pub description: Option<String>,
+    /// Top level when omitted.
+    pub parent_id:   Option<Uuid>,
+    #[serde(default)]
+    pub visibility:  SnippetVisibility,
+}
+
+#[post("")]
+pub async fn create_collection(
+    app_data: web::Data<AppState>,
+    user_data: web::ReqData<UserData>,
+    json_data: web::Json<CollectionRequest>,
+) -> actix_web::Result<impl Responder> {
+    let user_id = user_data.id;
+
+    if let Some(parent_id) = json_data.parent_id
+        && !valid_parent(&app_data.db, parent_id, user_id, None).await.map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "Invalid parent collection" })));
+    }
+
+    // New collections go to the end of their siblings.
+    let rec = sqlx::query!(
+        r#"
+        INSERT INTO snippets_extension.collections (owner_id, parent_id, name, description, visibility, position)
+        SELECT $1, $2, $3, $4, $5,
+               COALESCE(MAX(position) + 1, 0)
+          FROM snippets_extension.collections
+         WHERE owner_id = $1
+           AND parent_id IS NOT DISTINCT FROM $2
+        RETURNING id
+        "#,
+        user_id,
+        json_data.parent_id,
+        json_data.name,
+        json_data.description,
+        json_data.visibility as SnippetVisibility,
+    )
+    .fetch_one(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": rec.id })))
+}
+
+/// Renames, re-describes, changes visibility and moves a collection. Moving to a
+/// new parent puts it at the end of that parent's children.
+#[put("/{collectionId}")]
+pub async fn update_collection(
+    app_data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user_data: web::ReqData<UserData>,
+    json_data: web::Json<CollectionRequest>,
+) -> actix_web::Result<impl Responder> {
+    let collection_id = path.into_inner();
+    let user_id = user_data.id;
+
+    if !owns_collection(&app_data.db, collection_id, user_id).await.map_err(actix_web::error::ErrorInternalServerError)? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    if let Some(parent_id) = json_data.parent_id
+        && !valid_parent(&app_data.db, parent_id, user_id, Some(collection_id)).await.map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "Invalid parent collection" })));
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE snippets_extension.collections c
+           SET name        = $3,
+               description = $4,
+               visibility  = $5,
+               position    = CASE
+                               WHEN c.parent_id IS NOT DISTINCT FROM $2 THEN c.position
+                               ELSE COALESCE((SELECT MAX(o.position) + 1
+                                                FROM snippets_extension.collections o
+                                               WHERE o.owner_id = c.owner_id
+                                                 AND o.parent_id IS NOT DISTINCT FROM $2), 0)
+                             END,
+               parent_id   = $2
+         WHERE c.id = $1
+        "#,
+        collection_id,
+        json_data.parent_id,
+        json_data.name,
+        json_data.description,
+        json_data.visibility as SnippetVisibility,
+    )
+    .execute(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Deletes the collection and its sub-collections. The snippets themselves stay.
+#[delete("/{collectionId}")]
+pub async fn delete_collection(
+    app_data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user_data: web::ReqData<UserData>,
+) -> actix_web::Result<impl Responder> {
+    let collection_id = path.into_inner();
+
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM snippets_extension.collections
+         WHERE id = $1
+           AND owner_id = $2
+        "#,
+        collection_id,
+        user_data.id
+    )
+    .execute(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    if result.rows_affected() == 0 {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize)]
+pub struct ReorderCollectionsRequest {
+    /// Whose children are being ordered; top level when omitted.
+    pub parent_id:      Option<Uuid>,
+    pub collection_ids: Vec<Uuid>,
+}
+
+/// Sets sibling order to the order of `collection_ids`. IDs that aren't children of
+/// `parent_id` are ignored.
+#[put("/order")]
+pub async fn reorder_collections(
+    app_data: web::Data<AppState>,
+    user_data: web::ReqData<UserData>,
+    json_data: web::Json<ReorderCollectionsRequest>,
+) -> actix_web::Result<impl Responder> {
+    sqlx::query!(
+        r#"
+        UPDATE snippets_extension.collections c
+           SET position = o.ord - 1
+          FROM unnest($1::UUID[]) WITH ORDINALITY AS o(id, ord)
+         WHERE c.id = o.id
+           AND c.owner_id = $2
+           AND c.parent_id IS NOT DISTINCT FROM $3
+        "#,
+        &json_data.collection_ids,
+        user_data.id,
+        json_data.parent_id
+    )
+    .execute(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize)]
+pub struct AddSnippetRequest {
+    pub snippet_id: Uuid,
+}
+
+/// Adds one of the user's own snippets to the end of the collection.
+#[post("/{collectionId}/snippets")]
+pub async fn add_snippet(
+    app_data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user_data: web::ReqData<UserData>,
+    json_data: web::Json<AddSnippetRequest>,
+) -> actix_web::Result<impl Responder> {
+    let collection_id = path.into_inner();
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO snippets_extension.collection_snippets (collection_id, snippet_id, position)
+        SELECT c.id, s.id,
+               COALESCE((SELECT MAX(cs.position) + 1
+                           FROM snippets_extension.collection_snippets cs
+                          WHERE cs.collection_id = c.id), 0)
+          FROM snippets_extension.collections c
+          JOIN snippets_extension.snippets s
+            ON s.owner_id = c.owner_id
+         WHERE c.id = $1
+           AND s.id = $2
+           AND c.owner_id = $3
+        ON CONFLICT DO NOTHING
+        "#,
+        collection_id,
+        json_data.snippet_id,
+        user_data.id
+    )
+    .execute(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    if result.rows_affected() == 0
+        && !owns_collection(&app_data.db, collection_id, user_data.id).await.map_err(actix_web::error::ErrorInternalServerError)?
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[delete("/{collectionId}/snippets/{snippetId}")]
+pub async fn remove_snippet(
+    app_data: web::Data<AppState>,
+    path: web::Path<(Uuid, Uuid)>,
+    user_data: web::ReqData<UserData>,
+) -> actix_web::Result<impl Responder> {
+    let (collection_id, snippet_id) = path.into_inner();
+
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM snippets_extension.collection_snippets cs
+         USING snippets_extension.collections c
+         WHERE cs.collection_id = c.id
+           AND c.id = $1
+           AND c.owner_id = $2
+           AND cs.snippet_id = $3
+        "#,
+        collection_id,
+        user_data.id,
+        snippet_id
+    )
+    .execute(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    if result.rows_affected() == 0 {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+#[derive(Deserialize)]
+pub struct ReorderSnippetsRequest {
+    pub snippet_ids: Vec<Uuid>,
+}
+
+#[put("/{collectionId}/snippets/order")]
+pub async fn reorder_snippets(
+    app_data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user_data: web::ReqData<UserData>,
+    json_data: web::Json<ReorderSnippetsRequest>,
+) -> actix_web::Result<impl Responder> {
+    let collection_id = path.into_inner();
+
+    if !owns_collection(&app_data.db, collection_id, user_data.id).await.map_err(actix_web::error::ErrorInternalServerError)? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    sqlx::query!(
+        r#"
+        UPDATE snippets_extension.collection_snippets cs
+           SET position = o.ord - 1
+          FROM unnest($1::UUID[]) WITH ORDINALITY AS o(id, ord)
+         WHERE cs.collection_id = $2
+           AND cs.snippet_id = o.id
+        "#,
+        &json_data.snippet_ids,
+        collection_id
+    )
+    .execute(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// A user's collections as a flat list; build the tree from `parent_id` and
+/// `position`. Other users only see public ones, and only count public snippets.
+#[get("/{userId}/collections")]
+pub async fn get_user_collections(
+    app_data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user_data: web::ReqData<UserData>,
+) -> actix_web::Result<impl Responder> {
+    let user_id = path.into_inner();
+    let req_user_id = user_data.id;
+
+    let collections = sqlx::query_as!(
+        CollectionData,
+        r#"
+        SELECT
+            c.id,
+            c.owner_id,
+            c.parent_id,
+            c.name,
+            c.description,
+            c.visibility AS "visibility: SnippetVisibility",
+            c.position,
+            (SELECT COUNT(*)
+               FROM snippets_extension.collection_snippets cs
+               JOIN snippets_extension.snippets s ON s.id = cs.snippet_id
+              WHERE cs.collection_id = c.id
+                AND ($1::UUID = $2::UUID OR s.visibility = 'public')) AS "snippet_count!",
+            c.created_at
+        FROM snippets_extension.collections c
+        WHERE c.owner_id = $1
+          AND ($1 = $2 OR c.visibility = 'public')
+        ORDER BY c.parent_id NULLS FIRST, c.position
+        "#,
+        user_id,
+        req_user_id
+    )
+    .fetch_all(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "collections": collections,
+        "owner": user_id == req_user_id
+    })))
+}
+
+// _______________________________________ Public collection routes _______________________________________
+
+#[derive(Deserialize)]
+pub struct CollectionPageParams {
+    pub page:  Option<u32>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct CollectionPageResponse {
+    pub total_records: i64,
+    pub total_pages:   u32,
+    pub current_page:  u32,
+    pub records:       Vec<CollectionData>,
+}
+
+#[get("")]
+pub async fn get_page_collections(
+    app_data: web::Data<AppState>,
+    params:   web::Query<CollectionPageParams>,
+) -> actix_web::Result<impl Responder> {
+    let current_page = params.page.unwrap_or(1).max(1);
+    let per_page     = params.limit.unwrap_or(12).clamp(1, 100);
+    let offset       = (current_page - 1) * per_page;
+
+    let total_records = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM snippets_extension.collections WHERE visibility = 'public'"#
+    )
+    .fetch_one(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let records = sqlx::query_as!(
+        CollectionData,
+        r#"
+        SELECT
+            c.id,
+            c.owner_id,
+            c.parent_id,
+            c.name,
+            c.description,
+            c.visibility AS "visibility: SnippetVisibility",
+            c.position,
+            (SELECT COUNT(*)
+               FROM snippets_extension.collection_snippets cs
+               JOIN snippets_extension.snippets s ON s.id = cs.snippet_id
+              WHERE cs.collection_id = c.id
+                AND s.visibility = 'public') AS "snippet_count!",
+            c.created_at
+        FROM snippets_extension.collections c
+        WHERE c.visibility = 'public'
+        ORDER BY c.created_at DESC
+        LIMIT $1
+        OFFSET $2
+        "#,
+        per_page as i64,
+        offset as i64
+    )
+    .fetch_all(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let total_pages = ((total_records as f64) / (per_page as f64)).ceil() as u32;
+
+    Ok(HttpResponse::Ok().json(CollectionPageResponse {
+        total_records,
+        total_pages,
+        current_page,
+        records,
+    }))
+}
+
+/// A public or unlisted collection with its public sub-collections. Its snippets
+/// are listed through `GET /v1/snippets?collection={id}`.
+#[get("/{collectionId}")]
+pub async fn get_collection(
+    app_data: web::Data<AppState>,
+    path:     web::Path<Uuid>,
+) -> actix_web::Result<impl Responder> {
+    let collection_id = path.into_inner();
+
+    let collections = sqlx::query_as!(
+        CollectionData,
+        r#"
+        SELECT
+            c.id,
+            c.owner_id,
+            c.parent_id,
+            c.name,
+            c.description,
+            c.visibility AS "visibility: SnippetVisibility",
+            c.position,
+            (SELECT COUNT(*)
+               FROM snippets_extension.collection_snippets cs
+               JOIN snippets_extension.snippets s ON s.id = cs.snippet_id
+              WHERE cs.collection_id = c.id
+                AND s.visibility = 'public') AS "snippet_count!",
+            c.created_at
+        FROM snippets_extension.collections c
+        WHERE (c.id = $1 AND c.visibility <> 'private')
+           OR (c.parent_id = $1 AND c.visibility = 'public'
+               AND EXISTS (SELECT 1 FROM snippets_extension.collections p WHERE p.id = $1 AND p.visibility <> 'private'))
+        ORDER BY c.position
+        "#,
+        collection_id
+    )
+    .fetch_all(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let (mut found, children): (Vec<_>, Vec<_>) = collections.into_iter().partition(|c| c.id == collection_id);
+
+    match found.pop() {
+        Some(collection) => Ok(HttpResponse::Ok().json(serde_json::json!({
+            "collection": collection,
+            "children":   children,
+        }))),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
@@ -0,0 +1,227 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use chrono::{Duration as ChronoDuration, Utc};
+use rand::Rng;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    handlers::auth_handler::issue_session,
+    models::UserData,
+    utils::{generate_token, hash_token},
+    AppState,
+};
+
+// RFC 8628 §6.1: no vowels so codes can't spell words, no look-alike characters.
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LENGTH: usize = 8;
+const DEVICE_CODE_TTL_MINUTES: i64 = 15;
+const POLL_INTERVAL_SECONDS: i32 = 5;
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+/// How many fresh codes to try when one collides with an existing user code.
+const USER_CODE_ATTEMPTS: usize = 5;
+
+fn generate_user_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..USER_CODE_LENGTH)
+        .map(|_| USER_CODE_ALPHABET[rng.gen_range(0..USER_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Users may type the code with or without the dash and in any case.
+fn normalize_user_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+#[post("/code")]
+pub async fn device_code(
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    // Expired codes are kept for an hour so late polls still get `expired_token`,
+    // then cleared out here rather than by a separate job.
+    let cleared = sqlx::query!(
+        "DELETE FROM device_authorizations WHERE expires_at < CURRENT_TIMESTAMP - INTERVAL '1 hour'"
+    )
+    .execute(&app_state.db)
+    .await;
+
+    if cleared.is_err() {
+        return HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Server error" }));
+    }
+
+    let expires_at = Utc::now() + ChronoDuration::minutes(DEVICE_CODE_TTL_MINUTES);
+    let mut attempts = 0;
+
+    // User codes are short enough to collide with a pending one now and then.
+    let (device_code, user_code) = loop {
+        let device_code = generate_token();
+        let user_code = generate_user_code();
+        attempts += 1;
+
+        let res = sqlx::query!(
+            r#"
+            INSERT INTO device_authorizations (device_code_hash, user_code, interval_seconds, expires_at)
+            VALUES ($1, $2, $3, $4)
+            "#,
+            hash_token(&device_code),
+            user_code,
+            POLL_INTERVAL_SECONDS,
+            expires_at
+        )
+        .execute(&app_state.db)
+        .await;
+
+        match res {
+            Ok(_) => break (device_code, user_code),
+            Err(sqlx::Error::Database(e)) if e.is_unique_violation() && attempts < USER_CODE_ATTEMPTS => continue,
+            Err(_) => {
+                return HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Server error" }));
+            }
+        }
+    };
+
+    let display_code = format!("{}-{}", &user_code[..4], &user_code[4..]);
+    let verification_uri = format!("{}/device", app_state.app_url);
+
+    HttpResponse::Ok().json(serde_json::json!({
+        "device_code": device_code,
+        "user_code": display_code,
+        "verification_uri": verification_uri,
+        "verification_uri_complete": format!("{}?user_code={}", verification_uri, display_code),
+        "expires_in": DEVICE_CODE_TTL_MINUTES * 60,
+        "interval": POLL_INTERVAL_SECONDS,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceApproveRequest {
+    pub user_code: String,
+    pub approve: bool,
+}
+
+/// Called from the signed-in web app after the user types the code shown in the editor.
+#[post("/approve")]
+pub async fn approve_device(
+    app_state: web::Data<AppState>,
+    user_data: web::ReqData<UserData>,
+    approve_json: web::Json<DeviceApproveRequest>,
+) -> impl Responder {
+    let body = approve_json.into_inner();
+    let status = if body.approve { "approved" } else { "denied" };
+
+    let res = sqlx::query!(
+        r#"
+        UPDATE device_authorizations
+           SET status  = $3,
+               user_id = $2
+         WHERE user_code  = $1
+           AND status     = 'pending'
+           AND expires_at > CURRENT_TIMESTAMP
+        RETURNING id
+        "#,
+        normalize_user_code(&body.user_code),
+        user_data.id,
+        status
+    )
+    .fetch_optional(&app_state.db)
+    .await;
+
+    match res {
+        Ok(Some(_)) => HttpResponse::Ok().json(serde_json::json!({ "error": null })),
+        Ok(None) => HttpResponse::NotFound()
+            .json(serde_json::json!({ "error": "Invalid or expired code" })),
+        Err(_) => HttpResponse::InternalServerError()
+            .json(serde_json::json!({ "error": "Server error" })),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceTokenRequest {
+    pub device_code: String,
+    pub grant_type: Option<String>,
+}
+
+/// Polled by the editor. Error codes follow RFC 8628 §3.5.
+#[post("/token")]
+pub async fn device_token(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    token_json: web::Json<DeviceTokenRequest>,
+) -> impl Responder {
+    let body = token_json.into_inner();
+    if body.grant_type.as_deref().is_some_and(|g| g != DEVICE_CODE_GRANT) {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "unsupported_grant_type" }));
+    }
+
+    // Record the poll and report whether it came in faster than the agreed interval.
+    let row = match sqlx::query!(
+        r#"
+        UPDATE device_authorizations d
+           SET last_polled_at = CURRENT_TIMESTAMP
+          FROM device_authorizations prev
+         WHERE d.id = prev.id
+           AND d.device_code_hash = $1
+        RETURNING
+          d.id,
+          d.status,
+          d.user_id,
+          d.expires_at <= CURRENT_TIMESTAMP AS "expired!",
+          COALESCE(prev.last_polled_at > CURRENT_TIMESTAMP - make_interval(secs => d.interval_seconds), FALSE) AS "too_fast!"
+        "#,
+        hash_token(&body.device_code)
+    )
+    .fetch_optional(&app_state.db)
+    .await
+    {
+        Ok(Some(r)) => r,
+        Ok(None) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({ "error": "invalid_grant" }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({ "error": "server_error" }));
+        }
+    };
+
+    if row.expired {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "expired_token" }));
+    }
+    if row.too_fast {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "slow_down" }));
+    }
+
+    match (row.status.as_str(), row.user_id) {
+        ("approved", Some(user_id)) => {
+            let claimed = sqlx::query!(
+                "UPDATE device_authorizations SET status = 'consumed' WHERE id = $1 AND status = 'approved'",
+                row.id
+            )
+            .execute(&app_state.db)
+            .await;
+
+            match claimed {
+                Ok(r) if r.rows_affected() > 0 => {}
+                Ok(_) => return HttpResponse::BadRequest().json(serde_json::json!({ "error": "invalid_grant" })),
+                Err(_) => return HttpResponse::InternalServerError().json(serde_json::json!({ "error": "server_error" })),
+            }
+
+            // Every editor sign-in is its own device so it can be revoked on its own.
+            let session = match issue_session(&app_state, &req, user_id, Uuid::new_v4().to_string()).await {
+                Ok(s) => s,
+                Err(res) => return res,
+            };
+
+            HttpResponse::Ok().json(serde_json::json!({
+                "access_token": session.access_token,
+                "refresh_token": session.refresh_token,
+                "device_id": session.device_id,
+                "token_type": "Bearer",
+                "expires_in": 15 * 60,
+            }))
+        }
+        ("denied", _) => HttpResponse::BadRequest().json(serde_json::json!({ "error": "access_denied" })),
+        ("pending", _) => HttpResponse::BadRequest().json(serde_json::json!({ "error": "authorization_pending" })),
+        _ => HttpResponse::BadRequest().json(serde_json::json!({ "error": "invalid_grant" })),
+    }
+}
@@ -0,0 +1,11 @@
+use actix_web::{get, web, HttpResponse, Responder};
+
+use crate::AppState;
+
+/// Lets other Principium services verify our access tokens without a shared secret.
+#[get("/.well-known/jwks.json")]
+pub async fn jwks(app_state: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(("Cache-Control", "public, max-age=300"))
+        .json(app_state.jwt_keys.jwks())
+}
@@ -0,0 +1,358 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use bcrypt::verify;
+use serde::Deserialize;
+
+use crate::{
+    handlers::auth_handler::start_session,
+    models::UserData,
+    utils::{
+        base32_encode, clear_login_failures, generate_token, generate_totp_secret, hash_token, login_lockout,
+        provisioning_uri, record_login_failure, verify_totp,
+    },
+    AppState,
+};
+
+const RECOVERY_CODE_COUNT: usize = 10;
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+fn unix_now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+/// Recovery codes are shown as `xxxxxxxx-xxxxxxxx`; users may type them with
+/// or without the dash and in any case.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[post("/enroll")]
+pub async fn enroll(
+    app_state: web::Data<AppState>,
+    user_data: web::ReqData<UserData>,
+) -> impl Responder {
+    let secret = generate_totp_secret();
+
+    // Re-enrolling before confirming just replaces the pending secret.
+    let res = sqlx::query!(
+        r#"
+        INSERT INTO user_mfa (user_id, secret)
+        VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE
+           SET secret         = EXCLUDED.secret,
+               last_used_step = NULL,
+               created_at     = CURRENT_TIMESTAMP
+         WHERE user_mfa.enabled = FALSE
+        RETURNING (SELECT email FROM users WHERE id = $1) AS "email!"
+        "#,
+        user_data.id,
+        &secret
+    )
+    .fetch_optional(&app_state.db)
+    .await;
+
+    match res {
+        Ok(Some(row)) => HttpResponse::Ok().json(serde_json::json!({
+            "secret": base32_encode(&secret),
+            "provisioningUri": provisioning_uri(&secret, &row.email, "Principium"),
+            "error": null
+        })),
+        Ok(None) => HttpResponse::Conflict()
+            .json(serde_json::json!({ "error": "Two-factor authentication is already enabled" })),
+        Err(_) => HttpResponse::InternalServerError()
+            .json(serde_json::json!({ "error": "Server error" })),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaCodeRequest {
+    pub code: String,
+}
+
+#[post("/confirm")]
+pub async fn confirm(
+    app_state: web::Data<AppState>,
+    user_data: web::ReqData<UserData>,
+    code_json: web::Json<MfaCodeRequest>,
+) -> impl Responder {
+    let body = code_json.into_inner();
+
+    let pending = match sqlx::query!(
+        "SELECT secret FROM user_mfa WHERE user_id = $1 AND enabled = FALSE",
+        user_data.id
+    )
+    .fetch_optional(&app_state.db)
+    .await
+    {
+        Ok(Some(r)) => r,
+        Ok(None) => {
+            return HttpResponse::BadRequest()
+                .json(serde_json::json!({ "error": "No pending two-factor enrollment" }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": "DB query error" }));
+        }
+    };
+
+    let step = match verify_totp(&pending.secret, body.code.trim(), unix_now()) {
+        Some(step) => step,
+        None => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Invalid code" }));
+        }
+    };
+
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let raw = generate_token();
+            format!("{}-{}", &raw[..8], &raw[8..16])
+        })
+        .collect();
+    let hashes: Vec<String> = codes.iter().map(|c| hash_token(&normalize_recovery_code(c))).collect();
+
+    let res = sqlx::query!(
+        r#"
+        WITH enabled AS (
+            UPDATE user_mfa
+               SET enabled        = TRUE,
+                   confirmed_at   = CURRENT_TIMESTAMP,
+                   last_used_step = $2
+             WHERE user_id = $1
+               AND enabled = FALSE
+            RETURNING user_id
+        ),
+        cleared AS (
+            DELETE FROM user_mfa_recovery_codes
+             WHERE user_id IN (SELECT user_id FROM enabled)
+        )
+        INSERT INTO user_mfa_recovery_codes (user_id, code_hash)
+        SELECT user_id, unnest($3::text[]) FROM enabled
+        "#,
+        user_data.id,
+        step as i64,
+        &hashes
+    )
+    .execute(&app_state.db)
+    .await;
+
+    match res {
+        Ok(r) if r.rows_affected() > 0 => HttpResponse::Ok()
+            .json(serde_json::json!({ "recoveryCodes": codes, "error": null })),
+        Ok(_) => HttpResponse::BadRequest()
+            .json(serde_json::json!({ "error": "No pending two-factor enrollment" })),
+        Err(_) => HttpResponse::InternalServerError()
+            .json(serde_json::json!({ "error": "Server error" })),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisableMfaRequest {
+    pub password: String,
+}
+
+#[post("/disable")]
+pub async fn disable(
+    app_state: web::Data<AppState>,
+    user_data: web::ReqData<UserData>,
+    disable_json: web::Json<DisableMfaRequest>,
+) -> impl Responder {
+    let body = disable_json.into_inner();
+
+    let row = match sqlx::query!(
+        "SELECT password_hash FROM users WHERE id = $1",
+        user_data.id
+    )
+    .fetch_one(&app_state.db)
+    .await
+    {
+        Ok(r) => r,
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": "DB query error" }));
+        }
+    };
+
+    match verify(&body.password, &row.password_hash) {
+        Ok(true) => (),
+        Ok(false) => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Invalid credentials" }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": "Password verification error" }));
+        }
+    }
+
+    let res = sqlx::query!(
+        r#"
+        WITH cleared AS (
+            DELETE FROM user_mfa_recovery_codes
+             WHERE user_id = $1
+        )
+        DELETE FROM user_mfa
+         WHERE user_id = $1
+        "#,
+        user_data.id
+    )
+    .execute(&app_state.db)
+    .await;
+
+    match res {
+        Ok(_) => HttpResponse::Ok().json(serde_json::json!({ "error": null })),
+        Err(_) => HttpResponse::InternalServerError()
+            .json(serde_json::json!({ "error": "Server error" })),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaVerifyRequest {
+    #[serde(rename = "mfaToken")]
+    pub mfa_token: String,
+    pub code: String,
+}
+
+/// Second half of login for users with two-factor enabled. Accepts either a
+/// current TOTP code or one of the unused recovery codes.
+#[post("/verify")]
+pub async fn verify_mfa(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    verify_json: web::Json<MfaVerifyRequest>,
+) -> impl Responder {
+    let body = verify_json.into_inner();
+    if body.mfa_token.is_empty() || body.code.is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Missing required fields" }));
+    }
+
+    let challenge = match sqlx::query!(
+        r#"
+        UPDATE mfa_challenges
+           SET attempts = attempts + 1
+         WHERE token_hash = $1
+           AND used_at IS NULL
+           AND expires_at > CURRENT_TIMESTAMP
+           AND attempts < $2
+        RETURNING id, user_id, (SELECT email FROM users WHERE id = user_id) AS "email!"
+        "#,
+        hash_token(&body.mfa_token),
+        MAX_CHALLENGE_ATTEMPTS
+    )
+    .fetch_optional(&app_state.db)
+    .await
+    {
+        Ok(Some(c)) => c,
+        Ok(None) => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Invalid or expired MFA token" }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": "DB query error" }));
+        }
+    };
+
+    // Wrong codes count against the same lockout as wrong passwords, so fresh
+    // challenges from `/login` don't give fresh guesses.
+    let ip_address = req.connection_info().realip_remote_addr().map(str::to_owned);
+    match login_lockout(&app_state.db, &challenge.email, ip_address.as_deref()).await {
+        Ok(None) => (),
+        Ok(Some(retry_after)) => {
+            return HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .json(serde_json::json!({ "error": "Too many login attempts, try again later" }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": "DB query error" }));
+        }
+    }
+
+    let mfa = match sqlx::query!(
+        "SELECT secret, last_used_step FROM user_mfa WHERE user_id = $1 AND enabled = TRUE",
+        challenge.user_id
+    )
+    .fetch_optional(&app_state.db)
+    .await
+    {
+        Ok(Some(m)) => m,
+        Ok(None) => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Two-factor authentication is not enabled" }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": "DB query error" }));
+        }
+    };
+
+    let accepted = match verify_totp(&mfa.secret, body.code.trim(), unix_now()) {
+        // A code is only good once, even while it's still inside its window.
+        Some(step) if mfa.last_used_step.is_none_or(|last| (step as i64) > last) => sqlx::query!(
+            r#"
+            UPDATE user_mfa
+               SET last_used_step = $2
+             WHERE user_id = $1
+               AND (last_used_step IS NULL OR last_used_step < $2)
+            "#,
+            challenge.user_id,
+            step as i64
+        )
+        .execute(&app_state.db)
+        .await
+        .map(|r| r.rows_affected() > 0),
+        Some(_) => Ok(false),
+        None => sqlx::query!(
+            r#"
+            UPDATE user_mfa_recovery_codes
+               SET used_at = CURRENT_TIMESTAMP
+             WHERE user_id   = $1
+               AND code_hash = $2
+               AND used_at IS NULL
+            "#,
+            challenge.user_id,
+            hash_token(&normalize_recovery_code(&body.code))
+        )
+        .execute(&app_state.db)
+        .await
+        .map(|r| r.rows_affected() > 0),
+    };
+
+    match accepted {
+        Ok(true) => (),
+        Ok(false) => {
+            if let Err(e) = record_login_failure(&app_state.db, &challenge.email, ip_address.as_deref()).await {
+                log::error!("Failed to record login failure: {}", e);
+            }
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Invalid code" }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": "DB query error" }));
+        }
+    }
+
+    let res = sqlx::query!(
+        "UPDATE mfa_challenges SET used_at = CURRENT_TIMESTAMP WHERE id = $1 AND used_at IS NULL",
+        challenge.id
+    )
+    .execute(&app_state.db)
+    .await;
+
+    match res {
+        Ok(r) if r.rows_affected() > 0 => {
+            if let Err(e) = clear_login_failures(&app_state.db, &challenge.email).await {
+                log::error!("Failed to clear login failures: {}", e);
+            }
+            start_session(&app_state, &req, challenge.user_id).await
+        }
+        Ok(_) => HttpResponse::Unauthorized()
+            .json(serde_json::json!({ "error": "Invalid or expired MFA token" })),
+        Err(_) => HttpResponse::InternalServerError()
+            .json(serde_json::json!({ "error": "DB query error" })),
+    }
+}
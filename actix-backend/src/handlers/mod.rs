@@ -1,2 +1,15 @@
 pub mod auth_handler;
-pub mod snippet_handler;
\ No newline at end of file
+pub mod billing_handler;
+pub mod collection_handler;
+pub mod device_handler;
+pub mod jwks_handler;
+pub mod mfa_handler;
+pub mod password_handler;
+pub mod session_handler;
+pub mod snippet_fork_handler;
+pub mod snippet_handler;
+pub mod snippet_import_export_handler;
+pub mod snippet_revision_handler;
+pub mod snippet_trending_handler;
+pub mod token_handler;
+pub mod verification_handler;
\ No newline at end of file
@@ -0,0 +1,289 @@
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
+use bcrypt::{hash, verify};
+use chrono::{Duration as ChronoDuration, Utc};
+use serde::Deserialize;
+
+use crate::{
+    mailer::Mail,
+    models::UserData,
+    rate_limit::{RateLimitPolicy, RateLimitStore},
+    utils::{generate_token, hash_token, test_password},
+    AppState,
+};
+
+// Every request can send an email, so each address gets a few per hour.
+// IPs get more room because several people can share one address behind NAT.
+const FORGOT_PASSWORD_EMAIL_LIMIT: RateLimitPolicy = RateLimitPolicy::per_hour(3);
+const FORGOT_PASSWORD_IP_LIMIT: RateLimitPolicy = RateLimitPolicy::per_hour(10);
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub email: String,
+}
+
+/// Seconds until `ip` may ask for another reset mail to `email`, if it has to wait.
+/// These buckets are separate from the general per-IP request limit.
+async fn forgot_password_limit(
+    store: &dyn RateLimitStore,
+    email: &str,
+    ip: &str,
+) -> Result<Option<u64>, String> {
+    let by_ip = store.take(&format!("forgot-password:ip:{}", ip), FORGOT_PASSWORD_IP_LIMIT).await?;
+    if !by_ip.allowed {
+        return Ok(Some(by_ip.retry_after));
+    }
+    let email_key = format!("forgot-password:email:{}", email.trim().to_lowercase());
+    let by_email = store.take(&email_key, FORGOT_PASSWORD_EMAIL_LIMIT).await?;
+    Ok((!by_email.allowed).then_some(by_email.retry_after))
+}
+
+/// Always answers the same way so the endpoint can't be used to probe for accounts.
+/// The mail goes out in the background so response times don't give it away either.
+#[post("/forgot-password")]
+pub async fn forgot_password(
+    app_state: web::Data<AppState>,
+    http_req: HttpRequest,
+    forgot_json: web::Json<ForgotPasswordRequest>,
+) -> impl Responder {
+    let req = forgot_json.into_inner();
+    if req.email.is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Missing required fields" }));
+    }
+
+    let ip_address = http_req.connection_info().realip_remote_addr().unwrap_or("unknown").to_string();
+    match forgot_password_limit(app_state.rate_limit_store.as_ref(), &req.email, &ip_address).await {
+        Ok(None) => (),
+        Ok(Some(retry_after)) => {
+            return HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .json(serde_json::json!({ "error": "Too many password reset requests, try again later" }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Server error" }));
+        }
+    }
+
+    let token = generate_token();
+    let expires_at = Utc::now() + ChronoDuration::hours(1);
+
+    let issued = sqlx::query!(
+        r#"
+        WITH target AS (
+            SELECT id, email FROM users WHERE lower(email) = lower($1)
+        ),
+        cleared AS (
+            DELETE FROM password_reset_tokens
+             WHERE user_id IN (SELECT id FROM target)
+        )
+        INSERT INTO password_reset_tokens (user_id, token_hash, expires_at)
+        SELECT id, $2, $3 FROM target
+        RETURNING (SELECT email FROM target) AS "email!"
+        "#,
+        req.email.trim(),
+        hash_token(&token),
+        expires_at
+    )
+    .fetch_optional(&app_state.db)
+    .await;
+
+    match issued {
+        Ok(Some(issued)) => {
+            let mail = Mail {
+                to: issued.email,
+                subject: "Reset your Principium password".to_string(),
+                body: format!(
+                    "Someone asked to reset the password for this account. If it was you, open the link below:\n\n{}/reset-password?token={}\n\nThe link expires in 1 hour. If it wasn't you, you can ignore this email.",
+                    app_state.app_url, token
+                ),
+            };
+            let app_state = app_state.clone();
+            actix_web::rt::spawn(async move {
+                if let Err(e) = app_state.mailer.send(&mail).await {
+                    log::error!("Failed to send password reset email: {}", e);
+                }
+            });
+        }
+        Ok(None) => {}
+        Err(_) => {
+            return HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Server error" }));
+        }
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({ "error": null }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub password: String,
+}
+
+#[post("/reset-password")]
+pub async fn reset_password(
+    app_state: web::Data<AppState>,
+    reset_json: web::Json<ResetPasswordRequest>,
+) -> impl Responder {
+    let req = reset_json.into_inner();
+    if req.token.is_empty() || req.password.is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Missing required fields" }));
+    }
+    if let Some(err) = test_password(&req.password) {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": err }));
+    }
+
+    let password_hash = match hash(&req.password, 12) {
+        Ok(x) => x,
+        Err(_) => return HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Hash failed" }))
+    };
+
+    // The requester isn't signed in, so there is no current session worth keeping.
+    let res = sqlx::query!(
+        r#"
+        WITH used AS (
+            UPDATE password_reset_tokens
+               SET used_at = CURRENT_TIMESTAMP
+             WHERE token_hash = $1
+               AND used_at IS NULL
+               AND expires_at > CURRENT_TIMESTAMP
+            RETURNING user_id
+        ),
+        revoked AS (
+            UPDATE user_sessions
+               SET revoked = TRUE
+             WHERE user_id IN (SELECT user_id FROM used)
+        )
+        UPDATE users
+           SET password_hash = $2
+         WHERE id IN (SELECT user_id FROM used)
+        RETURNING id
+        "#,
+        hash_token(&req.token),
+        password_hash
+    )
+    .fetch_optional(&app_state.db)
+    .await;
+
+    match res {
+        Ok(Some(_)) => HttpResponse::Ok().json(serde_json::json!({ "error": null })),
+        Ok(None) => HttpResponse::BadRequest()
+            .json(serde_json::json!({ "error": "Invalid or expired reset token" })),
+        Err(_) => HttpResponse::InternalServerError()
+            .json(serde_json::json!({ "error": "Server error" })),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[post("")]
+pub async fn change_password(
+    app_state: web::Data<AppState>,
+    req: HttpRequest,
+    user_data: web::ReqData<UserData>,
+    change_json: web::Json<ChangePasswordRequest>,
+) -> impl Responder {
+    let body = change_json.into_inner();
+    if body.current_password.is_empty() || body.new_password.is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Missing required fields" }));
+    }
+    if let Some(err) = test_password(&body.new_password) {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": err }));
+    }
+
+    let row = match sqlx::query!(
+        "SELECT password_hash FROM users WHERE id = $1",
+        user_data.id
+    )
+    .fetch_one(&app_state.db)
+    .await
+    {
+        Ok(r) => r,
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": "DB query error" }));
+        }
+    };
+
+    match verify(&body.current_password, &row.password_hash) {
+        Ok(true) => (),
+        Ok(false) => {
+            return HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Current password is incorrect" }));
+        }
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": "Password verification error" }));
+        }
+    }
+
+    let password_hash = match hash(&body.new_password, 12) {
+        Ok(x) => x,
+        Err(_) => return HttpResponse::InternalServerError().json(serde_json::json!({ "error": "Hash failed" }))
+    };
+
+    // Keep the device making the change signed in; everything else has to log in again.
+    let device_id = req.cookie("device_id").map(|c| c.value().to_string());
+
+    let res = sqlx::query!(
+        r#"
+        WITH revoked AS (
+            UPDATE user_sessions
+               SET revoked = TRUE
+             WHERE user_id = $1
+               AND device_id IS DISTINCT FROM $3
+        )
+        UPDATE users
+           SET password_hash = $2
+         WHERE id = $1
+        "#,
+        user_data.id,
+        password_hash,
+        device_id
+    )
+    .execute(&app_state.db)
+    .await;
+
+    match res {
+        Ok(_) => HttpResponse::Ok().json(serde_json::json!({ "error": null })),
+        Err(_) => HttpResponse::InternalServerError()
+            .json(serde_json::json!({ "error": "Failed to update password" })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::MemoryStore;
+
+    #[actix_web::test]
+    async fn each_email_gets_a_few_requests_an_hour() {
+        let store = MemoryStore::new();
+        for n in 0..3 {
+            let ip = format!("10.0.0.{n}");
+            assert_eq!(forgot_password_limit(&store, "a@x.io", &ip).await, Ok(None));
+        }
+        // the address is normalized the same way whoever asks
+        let retry_after = forgot_password_limit(&store, " A@X.io", "10.0.0.9").await.unwrap().unwrap();
+        assert!((1190..=1200).contains(&retry_after), "{retry_after}");
+
+        assert_eq!(forgot_password_limit(&store, "b@x.io", "10.0.0.9").await, Ok(None));
+    }
+
+    #[actix_web::test]
+    async fn each_ip_gets_more_but_not_unlimited_requests() {
+        let store = MemoryStore::new();
+        for n in 0..10 {
+            let email = format!("user{n}@x.io");
+            assert_eq!(forgot_password_limit(&store, &email, "10.0.0.1").await, Ok(None));
+        }
+        assert!(forgot_password_limit(&store, "other@x.io", "10.0.0.1").await.unwrap().is_some());
+
+        // a refused request doesn't use up the address's allowance
+        for _ in 0..3 {
+            assert_eq!(forgot_password_limit(&store, "other@x.io", "10.0.0.2").await, Ok(None));
+        }
+    }
+}
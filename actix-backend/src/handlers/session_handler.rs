@@ -0,0 +1,119 @@
+use actix_web::{delete, get, web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use uuid::Uuid;
+
+use crate::{models::UserData, AppState};
+
+#[derive(Deserialize, Serialize, FromRow)]
+pub struct SessionData {
+    pub id:           Uuid,
+    pub user_agent:   Option<String>,
+    pub ip_address:   Option<String>,
+    pub created_at:   DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+    pub current:      bool,
+}
+
+#[get("")]
+pub async fn list_sessions(
+    app_data: web::Data<AppState>,
+    req: HttpRequest,
+    user_data: web::ReqData<UserData>,
+) -> actix_web::Result<impl Responder> {
+    let device_id = req.cookie("device_id").map(|c| c.value().to_string());
+
+    let sessions = sqlx::query_as!(
+        SessionData,
+        r#"
+        SELECT
+            id,
+            user_agent,
+            ip_address,
+            created_at,
+            last_used_at,
+            (device_id = $2) IS TRUE AS "current!"
+        FROM user_sessions
+        WHERE user_id = $1
+          AND revoked = FALSE
+        ORDER BY last_used_at DESC
+        "#,
+        user_data.id,
+        device_id
+    )
+    .fetch_all(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "sessions": sessions })))
+}
+
+#[delete("/{sessionId}")]
+pub async fn revoke_session(
+    app_data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user_data: web::ReqData<UserData>,
+) -> actix_web::Result<impl Responder> {
+    let session_id = path.into_inner();
+
+    let rec = sqlx::query!(
+        r#"
+        UPDATE user_sessions
+           SET revoked = TRUE
+         WHERE id      = $1
+           AND user_id = $2
+           AND revoked = FALSE
+        RETURNING id
+        "#,
+        session_id,
+        user_data.id
+    )
+    .fetch_optional(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    if rec.is_some() {
+        Ok(HttpResponse::Ok().json(serde_json::json!({ "error": null })))
+    } else {
+        Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("No active session found with id {}", session_id)
+        })))
+    }
+}
+
+/// Signs out every device except the one making the request.
+#[delete("")]
+pub async fn revoke_other_sessions(
+    app_data: web::Data<AppState>,
+    req: HttpRequest,
+    user_data: web::ReqData<UserData>,
+) -> actix_web::Result<impl Responder> {
+    let device_id = match req.cookie("device_id") {
+        Some(c) => c.value().to_string(),
+        None => {
+            return Ok(HttpResponse::BadRequest()
+                .json(serde_json::json!({ "error": "No device_id cookie" })));
+        }
+    };
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE user_sessions
+           SET revoked = TRUE
+         WHERE user_id    = $1
+           AND device_id <> $2
+           AND revoked    = FALSE
+        "#,
+        user_data.id,
+        device_id
+    )
+    .execute(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "revoked": result.rows_affected(),
+        "error": null
+    })))
+}
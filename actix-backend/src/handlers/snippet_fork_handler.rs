@@ -0,0 +1,139 @@
+use actix_web::{get, post, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::prelude::FromRow;
+use uuid::Uuid;
+
+use crate::{
+    handlers::{
+        snippet_handler::count_owned_snippets,
+        snippet_revision_handler::{record_revision, set_snippet_tags},
+    },
+    models::{Entitlements, UserData},
+    AppState,
+};
+
+/// Copies a snippet the caller can see, tags included, into their account.
+/// The fork starts out private.
+#[post("")]
+pub async fn fork_snippet(
+    app_data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user_data: web::ReqData<UserData>,
+    entitlements: Entitlements,
+) -> actix_web::Result<impl Responder> {
+    let source_id = path.into_inner();
+    let user_id = user_data.id;
+
+    let source = sqlx::query!(
+        r#"
+        SELECT
+            s.code,
+            COALESCE(
+                (SELECT array_agg(t.name)
+                   FROM snippets_extension.snippet_tags st
+                   JOIN snippets_extension.tags t ON t.id = st.tag_id
+                  WHERE st.snippet_id = s.id),
+                ARRAY[]::TEXT[]
+            ) AS "tags!: Vec<String>"
+        FROM snippets_extension.snippets s
+        WHERE s.id = $1
+          AND (s.visibility <> 'private' OR s.owner_id = $2)
+        "#,
+        source_id,
+        user_id
+    )
+    .fetch_optional(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let Some(source) = source else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    entitlements.check_snippet_content(source.code.as_deref().unwrap_or_default(), &source.tags)?;
+
+    let mut tx = app_data.db.begin().await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let owned = count_owned_snippets(&mut tx, user_id).await.map_err(actix_web::error::ErrorInternalServerError)?;
+    entitlements.check_snippet_count(owned)?;
+
+    // Forks start out private whatever the source was; the owner publishes them.
+    let fork = sqlx::query!(
+        r#"
+        INSERT INTO snippets_extension.snippets
+            (owner_id, title, description, code, language, visibility, prefixes, scope, forked_from, forked_at)
+        SELECT $2, title, description, code, language, 'private', prefixes, scope, id, NOW()
+          FROM snippets_extension.snippets
+         WHERE id = $1
+        RETURNING id
+        "#,
+        source_id,
+        user_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    set_snippet_tags(&mut tx, fork.id, &source.tags)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    record_revision(&mut tx, fork.id, user_id, None, entitlements.max_revisions)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    tx.commit().await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "id": fork.id })))
+}
+
+#[derive(Serialize, FromRow)]
+pub struct ForkData {
+    pub id:        Uuid,
+    pub title:     String,
+    pub owner_id:  Uuid,
+    pub forked_at: Option<DateTime<Utc>>,
+}
+
+/// Public forks of a snippet that is itself public or unlisted.
+#[get("/{snippetId}/forks")]
+pub async fn list_forks(
+    app_data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+) -> actix_web::Result<impl Responder> {
+    let snippet_id = path.into_inner();
+
+    let visible = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM snippets_extension.snippets WHERE id = $1 AND visibility <> 'private'
+        ) AS "exists!"
+        "#,
+        snippet_id
+    )
+    .fetch_one(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    if !visible {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let forks = sqlx::query_as!(
+        ForkData,
+        r#"
+        SELECT id, title, owner_id, forked_at
+          FROM snippets_extension.snippets
+         WHERE forked_from = $1
+           AND visibility = 'public'
+         ORDER BY forked_at DESC
+        "#,
+        snippet_id
+    )
+    .fetch_all(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "forks": forks })))
+}
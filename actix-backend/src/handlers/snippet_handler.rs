@@ -1,51 +1,135 @@
 use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{ prelude::FromRow, QueryBuilder};
+use sqlx::{ prelude::FromRow, Postgres, QueryBuilder, Transaction};
 use uuid::Uuid;
 
-use crate::{models::UserData, AppState};
+use crate::{handlers::snippet_revision_handler::{record_revision, set_snippet_tags}, models::{Entitlements, SnippetVisibility, SubscriptionPlan, UserData}, snippet_formats::placeholders::{self, SnippetSyntax}, utils::{Cursor, CursorDirection}, AppState};
  
 // _______________________________________ User related routes _______________________________________
 #[derive(Debug, Deserialize)]
 pub struct CreateSnippetRequest {
     pub title: String,
     pub language: String,
+    /// Can also be set later with an update.
+    pub code: Option<String>,
+    /// Public when omitted, as every snippet was before visibility existed.
+    #[serde(default = "public")]
+    pub visibility: SnippetVisibility,
 }
 
-#[post("/snippets")]
+fn public() -> SnippetVisibility {
+    SnippetVisibility::Public
+}
+
+/// 400 for a body whose snippet syntax is broken, listing every diagnostic.
+pub fn invalid_syntax(syntax: SnippetSyntax) -> HttpResponse {
+    HttpResponse::BadRequest().json(serde_json::json!({
+        "error": "Invalid snippet syntax",
+        "diagnostics": syntax.diagnostics,
+    }))
+}
+
+/// How many snippets the user owns. Holds their `users` row until the transaction
+/// ends, so concurrent creates can't all pass the quota check on the same count.
+pub async fn count_owned_snippets(tx: &mut Transaction<'_, Postgres>, user_id: Uuid) -> Result<i64, sqlx::Error> {
+    sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+        .fetch_one(&mut **tx)
+        .await?;
+
+    sqlx::query_scalar!(
+        r#"SELECT COUNT(*) AS "count!" FROM snippets_extension.snippets WHERE owner_id = $1"#,
+        user_id,
+    )
+    .fetch_one(&mut **tx)
+    .await
+}
+
+#[post("")]
 pub async fn create_snippet(
     app_data: web::Data<AppState>, 
     data_json: web::Json<CreateSnippetRequest>,
     user_data: web::ReqData<UserData>,
+    entitlements: Entitlements,
 ) -> actix_web::Result<impl Responder> {
+    let code = data_json.code.as_deref().unwrap_or_default();
+    entitlements.check_snippet_content(code, &[])?;
+    let syntax = placeholders::analyze(code);
+    if syntax.has_errors() {
+        return Ok(invalid_syntax(syntax));
+    }
+
+    let mut tx = app_data.db.begin().await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let owned = count_owned_snippets(&mut tx, user_data.id).await.map_err(actix_web::error::ErrorInternalServerError)?;
+    entitlements.check_snippet_count(owned)?;
+
     let rec = sqlx::query!(
         r#"
-        INSERT INTO snippets_extension.snippets (title, language, owner_id)
-        VALUES ($1, $2, $3)
+        INSERT INTO snippets_extension.snippets (title, language, owner_id, visibility, code)
+        VALUES ($1, $2, $3, $4, $5)
         RETURNING id
         "#,
         data_json.title,
         data_json.language,
         user_data.id,
+        data_json.visibility as SnippetVisibility,
+        data_json.code,
     )
-    .fetch_one(&app_data.db)
+    .fetch_one(&mut *tx)
     .await
     .map_err(actix_web::error::ErrorInternalServerError)?;
 
-    Ok(HttpResponse::Ok().json(serde_json::json!({"id": rec.id})))
+    record_revision(&mut tx, rec.id, user_data.id, None, entitlements.max_revisions)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    tx.commit().await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({"id": rec.id, "diagnostics": syntax.diagnostics})))
+}
+
+#[derive(Deserialize)]
+pub struct UserSnippetsParams {
+    pub collection: Option<Uuid>,
+    /// `next_cursor` / `prev_cursor` from a previous response.
+    pub cursor:     Option<String>,
+    pub limit:      Option<u32>,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct UserSnippetRecord {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub snippet: SnippetData,
+    #[serde(skip)]
+    pub cursor_key: Vec<String>,
 }
 
 #[get("/{userId}/snippets")]
 pub async fn get_user_snippets (
     app_data: web::Data<AppState>,
     path: web::Path<Uuid>, 
+    params: web::Query<UserSnippetsParams>,
     user_data: web::ReqData<UserData>,
 ) -> actix_web::Result<impl Responder> {
     let user_id = path.into_inner();
     let req_user_id = user_data.id;
+    let UserSnippetsParams { collection, cursor, limit } = params.into_inner();
+    let per_page = limit.unwrap_or(50).clamp(1, 100);
 
-    let snippets: Vec<SnippetData> = sqlx::query_as!(
-        SnippetData,
+    // collection order first when filtering by one
+    let mut keys = Vec::new();
+    if let Some(collection_id) = collection {
+        keys.push(SortKey::Position(collection_id));
+    }
+    keys.extend([SortKey::Created, SortKey::Id]);
+
+    let Ok(cursor) = parse_cursor(cursor.as_deref(), &keys) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "Invalid cursor" })));
+    };
+
+    let mut qb = QueryBuilder::new(
         r#"
         WITH star_counts AS (
           SELECT 
@@ -69,27 +153,80 @@ pub async fn get_user_snippets (
           s.description,
           s.code,
           s.language,
-          COALESCE(sc.stars, 0) AS "stars!: i64", -- Built Stars Column (Defaults to 0)
-          COALESCE(tl.tags, ARRAY[]::TEXT[]) AS "tags!: Vec<String>" -- Built Tags Column (Defaults to Empty Array of Text)
+          s.visibility,
+          s.prefixes,
+          s.scope,
+          COALESCE(sc.stars, 0) AS stars, -- Built Stars Column (Defaults to 0)
+          COALESCE(tl.tags, ARRAY[]::TEXT[]) AS tags, -- Built Tags Column (Defaults to Empty Array of Text)
+          s.forked_from,
+          (SELECT COUNT(*) FROM snippets_extension.snippets f WHERE f.forked_from = s.id AND f.visibility = 'public') AS forks,
+          -- only reported while the fork's owner can still see the upstream
+          COALESCE(
+            (SELECT up.updated_at > s.forked_at
+               FROM snippets_extension.snippets up
+              WHERE up.id = s.forked_from
+                AND (up.visibility <> 'private' OR up.owner_id = s.owner_id)),
+            FALSE
+          ) AS upstream_changed,
+        "#
+    );
+    push_cursor_key(&mut qb, &keys);
+    qb.push(
+        r#"
         FROM snippets_extension.snippets s
         LEFT JOIN star_counts sc
           ON sc.snippet_id = s.id
         LEFT JOIN tag_lists tl
           ON tl.snippet_id = s.id
-        WHERE s.owner_id = $1
-        ORDER BY s.created_at DESC
-        "#,
-        user_id
+        WHERE s.owner_id = "#
     )
-    .fetch_all(&app_data.db)
-    .await
-    .map_err(actix_web::error::ErrorInternalServerError)?;
+    .push_bind(user_id);
 
+    // other users only see what's listed publicly
+    let is_owner = user_id == req_user_id;
+    if !is_owner {
+        qb.push(" AND s.visibility = 'public'");
+    }
+
+    if let Some(collection_id) = collection {
+        qb.push(
+            r#"
+            AND EXISTS (
+              SELECT 1
+                FROM snippets_extension.collection_snippets cs
+                JOIN snippets_extension.collections c
+                  ON c.id = cs.collection_id
+               WHERE cs.snippet_id = s.id
+                 AND cs.collection_id = "#
+        )
+        .push_bind(collection_id);
+        if !is_owner {
+            qb.push(" AND c.visibility <> 'private'");
+        }
+        qb.push(")");
+    }
+
+    if let Some(cursor) = &cursor {
+        push_keyset(&mut qb, &keys, cursor);
+    }
+    push_order_by(&mut qb, &keys, cursor.as_ref().map_or(CursorDirection::Next, |c| c.dir));
+    qb.push(" LIMIT ").push_bind(per_page as i64 + 1);
+
+    let snippets: Vec<UserSnippetRecord> = qb
+        .build_query_as()
+        .fetch_all(&app_data.db)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let (snippets, next_cursor, prev_cursor) =
+        paginate(snippets, per_page as usize, &keys, cursor.as_ref(), false, |r| &r.cursor_key);
 
     Ok(HttpResponse::Ok().json(
         serde_json::json!({
             "snippets": snippets, 
-            "owner": user_id == req_user_id
+            "owner": is_owner,
+            "next_cursor": next_cursor,
+            "prev_cursor": prev_cursor,
         })
     ))
 }
@@ -130,8 +267,21 @@ pub async fn get_user_snippet(
             s.description,
             s.code,
             s.language,
+            s.visibility AS "visibility: SnippetVisibility",
+            s.prefixes,
+            s.scope,
             COALESCE(sc.stars, 0) AS "stars!: i64", -- Built Stars Column (Defaults to 0)
-            COALESCE(tl.tags, ARRAY[]::TEXT[]) AS "tags!: Vec<String>" -- Built Tags Column (Defaults to Empty Array of Text)
+            COALESCE(tl.tags, ARRAY[]::TEXT[]) AS "tags!: Vec<String>", -- Built Tags Column (Defaults to Empty Array of Text)
+            s.forked_from,
+            (SELECT COUNT(*) FROM snippets_extension.snippets f WHERE f.forked_from = s.id AND f.visibility = 'public') AS "forks!: i64",
+            -- only reported while the fork's owner can still see the upstream
+            COALESCE(
+              (SELECT up.updated_at > s.forked_at
+                 FROM snippets_extension.snippets up
+                WHERE up.id = s.forked_from
+                  AND (up.visibility <> 'private' OR up.owner_id = s.owner_id)),
+              FALSE
+            ) AS "upstream_changed!"
         FROM snippets_extension.snippets s
         LEFT JOIN star_counts sc
             ON sc.snippet_id = s.id
@@ -141,9 +291,12 @@ pub async fn get_user_snippet(
             s.owner_id = $1
         AND 
             s.id = $2
+        AND
+            (s.owner_id = $3 OR s.visibility <> 'private')
         "#,
         user_id,
-        snippet_id
+        snippet_id,
+        req_user_id
     )
     .fetch_optional(&app_data.db)
     .await
@@ -155,10 +308,13 @@ pub async fn get_user_snippet(
         None => return Ok(HttpResponse::NotFound().finish()),
     };
 
+    let syntax = placeholders::analyze(snippet.code.as_deref().unwrap_or_default());
+
     // respond with the snippet and a flag telling whether the
     // requesting user “owns” it
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "snippet": snippet,
+        "syntax":  syntax,
         "owner":   user_id == req_user_id
     })))
 }
@@ -170,19 +326,34 @@ pub struct UpdateSnippetRequest {
     pub description: String,
     pub code: String,
     pub language: String,
-    pub tags: Vec<String>
+    pub tags: Vec<String>,
+    /// Left unchanged when omitted.
+    pub visibility: Option<SnippetVisibility>,
+    /// Left unchanged when omitted.
+    pub prefixes: Option<Vec<String>>,
+    /// Left unchanged when omitted; an empty string clears it.
+    pub scope: Option<String>,
 }
 
-#[put("/snippets/{snippetId}")]
+#[put("/{snippetId}")]
 pub async fn update_snippet(
     app_data: web::Data<AppState>,
     path: web::Path<Uuid>,
     user_data: web::ReqData<UserData>,
-    json_data: web::Json<UpdateSnippetRequest>
+    json_data: web::Json<UpdateSnippetRequest>,
+    entitlements: Entitlements,
 ) -> actix_web::Result<impl Responder> {
     let snippet_id: Uuid = path.into_inner();
     let user_id = user_data.id;
 
+    entitlements.check_snippet_content(&json_data.code, &json_data.tags)?;
+    let syntax = placeholders::analyze(&json_data.code);
+    if syntax.has_errors() {
+        return Ok(invalid_syntax(syntax));
+    }
+
+    let mut tx = app_data.db.begin().await.map_err(actix_web::error::ErrorInternalServerError)?;
+
     let result = sqlx::query!(
         r#"
         UPDATE snippets_extension.snippets
@@ -191,6 +362,9 @@ pub async fn update_snippet(
             description = $2,
             code        = $3,
             language    = $4,
+            visibility  = COALESCE($7, visibility),
+            prefixes    = COALESCE($8, prefixes),
+            scope       = CASE WHEN $9::TEXT IS NULL THEN scope ELSE NULLIF($9, '') END,
             updated_at  = NOW()
         WHERE
             id       = $5
@@ -202,8 +376,11 @@ pub async fn update_snippet(
         json_data.language,
         snippet_id,
         user_id,
+        json_data.visibility as Option<SnippetVisibility>,
+        json_data.prefixes.as_deref(),
+        json_data.scope.as_deref(),
     )
-    .execute(&app_data.db)
+    .execute(&mut *tx)
     .await
     .map_err(actix_web::error::ErrorInternalServerError)?;
 
@@ -212,43 +389,22 @@ pub async fn update_snippet(
             serde_json::json!({ "error": "snippet not found or not owned by you" })
         ));
     }
-   
-    sqlx::query!(
-        r#"
-        WITH new_tags AS (
-            INSERT INTO snippets_extension.tags(name)
-            SELECT unnest($1::text[])
-            ON CONFLICT (name) DO NOTHING
-            RETURNING id
-        ),
-        all_tags AS (
-            SELECT id
-              FROM snippets_extension.tags
-             WHERE name = ANY($1::text[])
-        ),
-        deleted AS (
-            DELETE FROM snippets_extension.snippet_tags st
-             WHERE st.snippet_id = $2
-               AND st.tag_id NOT IN (SELECT id FROM all_tags)
-        ),
-        inserted AS (
-            INSERT INTO snippets_extension.snippet_tags(snippet_id, tag_id)
-            SELECT $2, id FROM all_tags
-            ON CONFLICT DO NOTHING
-        )
-        SELECT 1 as unused;
-        "#,
-        &json_data.tags,
-        snippet_id,
-    )
-    .fetch_one(&app_data.db)
-    .await
-    .map_err(actix_web::error::ErrorInternalServerError)?;
 
-    Ok(HttpResponse::Ok().finish())
+    set_snippet_tags(&mut tx, snippet_id, &json_data.tags)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    record_revision(&mut tx, snippet_id, user_id, None, entitlements.max_revisions)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    tx.commit().await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    // warnings don't block saving, but are worth showing
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "diagnostics": syntax.diagnostics })))
 }
 
-#[delete("/snippets/{snippetId}")]
+#[delete("/{snippetId}")]
 pub async fn delete_snippet(
     app_data: web::Data<AppState>,
     path: web::Path<Uuid>,
@@ -271,12 +427,12 @@ pub async fn delete_snippet(
     .await
     .map_err(actix_web::error::ErrorInternalServerError)?;
 
-    if let Some(_) = rec {
-        return Ok(
+    if rec.is_some() {
+        Ok(
             HttpResponse::Ok().finish()
         )
     } else {
-        return Ok(
+        Ok(
             HttpResponse::NotFound().json(serde_json::json!({
                 "error": format!("No snippet found with id {}", snippet_id)
             }))
@@ -292,23 +448,294 @@ pub struct SnippetData {
     pub description: Option<String>,
     pub code:        Option<String>,
     pub language:    String,
+    pub visibility:  SnippetVisibility,
+    /// Words that trigger the snippet in an editor.
+    pub prefixes:    Vec<String>,
+    /// Comma-separated editor language IDs; `language` is used when unset.
+    pub scope:       Option<String>,
     pub stars:       i64,
     pub tags:        Vec<String>,
+    pub forked_from: Option<Uuid>,
+    /// Public forks only, the same ones `/snippets/{id}/forks` lists.
+    pub forks:       i64,
+    /// The upstream was edited after this fork was made.
+    pub upstream_changed: bool,
 }
 
 #[derive(Deserialize)]
 pub struct PageParams {
-    pub language: Option<String>,
-    pub title:    Option<String>,
+    /// One language or a comma-separated list.
+    pub language:   Option<String>,
+    pub title:      Option<String>,
+    pub collection: Option<Uuid>,
+    /// Comma-separated tag names, combined according to `tag_match`.
+    pub tags:       Option<String>,
+    #[serde(default)]
+    pub tag_match:  TagMatch,
+    /// Username of the snippets' owner.
+    pub owner:      Option<String>,
+    pub min_stars:  Option<i64>,
+    pub created_after:  Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after:  Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+    /// Full-text query in `websearch_to_tsquery` syntax (`"exact phrase"`, `or`, `-exclude`).
+    pub q:          Option<String>,
+    /// Also match titles that are merely similar to `title` (typos, word order).
+    #[serde(default)]
+    pub fuzzy:      bool,
+    /// Minimum trigram word similarity for fuzzy title matches, 0.1 to 1.0.
+    pub similarity: Option<f32>,
+    #[serde(default)]
+    pub sort:       PageSort,
+    /// `next_cursor` / `prev_cursor` from a previous response; takes precedence over `page`.
+    pub cursor:     Option<String>,
+    /// Defaults to true for offset pages and false for cursor pages.
+    pub include_total: Option<bool>,
     pub page:     Option<u32>,
     pub limit:    Option<u32>,
 }
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PageSort {
+    #[default]
+    Newest,
+    /// Best full-text match first, or closest fuzzy title match; same as `newest` otherwise.
+    Relevance,
+    Stars,
+    /// Most recently edited first.
+    Updated,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TagMatch {
+    /// The snippet has at least one of the tags.
+    #[default]
+    Any,
+    /// The snippet has every one of the tags.
+    All,
+}
+
+pub fn split_list(value: Option<String>) -> Vec<String> {
+    value
+        .map(|v| v.split(',').map(|item| item.trim().to_string()).filter(|item| !item.is_empty()).collect())
+        .unwrap_or_default()
+}
+
+#[derive(Serialize, FromRow)]
+pub struct PageRecord {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub snippet:   SnippetData,
+    /// Matched fragments of the description and code, wrapped in `<mark>`.
+    pub highlight: Option<String>,
+    #[serde(skip)]
+    pub cursor_key: Vec<String>,
+}
+
 #[derive(Serialize)]
 pub struct PageResponse {
-    pub total_records: i64,
-    pub total_pages:   u32,
-    pub current_page:  u32,
-    pub records:       Vec<SnippetData>,
+    /// Totals are only computed for offset pages or when `include_total=true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_records: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_pages:   Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub current_page:  Option<u32>,
+    pub records:       Vec<PageRecord>,
+    pub next_cursor:   Option<String>,
+    pub prev_cursor:   Option<String>,
+    /// Only present when nothing matched and a close alternative exists.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub did_you_mean:  Option<DidYouMean>,
+}
+
+#[derive(Serialize, Default)]
+pub struct DidYouMean {
+    /// A public title or tag close to the `q` / `title` search text.
+    pub query:    Option<String>,
+    /// A language in use by public snippets close to the `language` filter.
+    pub language: Option<String>,
+}
+
+const DEFAULT_FUZZY_SIMILARITY: f32 = 0.3;
+
+/// One column of a listing's order. Listings sort by every key descending and
+/// always end with `created_at, id`, so a row's key values pin its position
+/// and can be compared as a single row value for keyset pagination.
+enum SortKey {
+    Rank(String),
+    Similarity(String),
+    Stars,
+    Updated,
+    /// Negated, so the collection's own ascending order still sorts descending.
+    Position(Uuid),
+    Created,
+    Id,
+}
+
+impl SortKey {
+    fn name(&self) -> &'static str {
+        match self {
+            SortKey::Rank(_)       => "rank",
+            SortKey::Similarity(_) => "similarity",
+            SortKey::Stars         => "stars",
+            SortKey::Updated       => "updated",
+            SortKey::Position(_)   => "position",
+            SortKey::Created       => "created",
+            SortKey::Id            => "id",
+        }
+    }
+
+    fn sql_type(&self) -> &'static str {
+        match self {
+            SortKey::Rank(_) | SortKey::Similarity(_) => "REAL",
+            SortKey::Stars                            => "BIGINT",
+            SortKey::Updated | SortKey::Created       => "TIMESTAMPTZ",
+            SortKey::Position(_)                      => "INT",
+            SortKey::Id                               => "UUID",
+        }
+    }
+
+    /// Whether a cursor value casts cleanly to `sql_type`, so a tampered
+    /// cursor is a bad request rather than a failed query.
+    fn accepts(&self, value: &str) -> bool {
+        match self {
+            SortKey::Rank(_) | SortKey::Similarity(_) => value.parse::<f32>().is_ok(),
+            SortKey::Stars                            => value.parse::<i64>().is_ok(),
+            SortKey::Updated | SortKey::Created       => DateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f%#z").is_ok(),
+            SortKey::Position(_)                      => value.parse::<i32>().is_ok(),
+            SortKey::Id                               => Uuid::parse_str(value).is_ok(),
+        }
+    }
+
+    fn push_expr(&self, qb: &mut QueryBuilder<'_, Postgres>) {
+        match self {
+            SortKey::Rank(q) => {
+                qb.push("ts_rank(s.search_vector, websearch_to_tsquery('english', ").push_bind(q.clone()).push("))");
+            }
+            SortKey::Similarity(t) => {
+                qb.push("word_similarity(").push_bind(t.clone()).push(", s.title)");
+            }
+            SortKey::Stars => {
+                qb.push("(SELECT COUNT(*) FROM snippets_extension.snippet_stars ks WHERE ks.snippet_id = s.id)");
+            }
+            SortKey::Updated => {
+                qb.push("s.updated_at");
+            }
+            SortKey::Position(collection_id) => {
+                qb.push("-(SELECT kc.position FROM snippets_extension.collection_snippets kc WHERE kc.snippet_id = s.id AND kc.collection_id = ")
+                    .push_bind(*collection_id)
+                    .push(")");
+            }
+            SortKey::Created => {
+                qb.push("s.created_at");
+            }
+            SortKey::Id => {
+                qb.push("s.id");
+            }
+        }
+    }
+}
+
+fn sort_signature(keys: &[SortKey]) -> String {
+    keys.iter().map(SortKey::name).collect::<Vec<_>>().join(",")
+}
+
+/// Selects the row's key values as text so they can be put in a cursor.
+fn push_cursor_key(qb: &mut QueryBuilder<'_, Postgres>, keys: &[SortKey]) {
+    qb.push("ARRAY[");
+    for (i, key) in keys.iter().enumerate() {
+        if i > 0 {
+            qb.push(", ");
+        }
+        qb.push("(");
+        key.push_expr(qb);
+        qb.push(")::TEXT");
+    }
+    qb.push("] AS cursor_key");
+}
+
+/// Restricts to rows after (or before) the cursor's row.
+fn push_keyset(qb: &mut QueryBuilder<'_, Postgres>, keys: &[SortKey], cursor: &Cursor) {
+    qb.push(" AND (");
+    for (i, key) in keys.iter().enumerate() {
+        if i > 0 {
+            qb.push(", ");
+        }
+        key.push_expr(qb);
+    }
+    qb.push(if cursor.dir == CursorDirection::Next { ") < (" } else { ") > (" });
+    for (i, (key, value)) in keys.iter().zip(&cursor.key).enumerate() {
+        if i > 0 {
+            qb.push(", ");
+        }
+        qb.push("CAST(").push_bind(value.clone()).push(" AS ").push(key.sql_type()).push(")");
+    }
+    qb.push(")");
+}
+
+/// `Prev` pages are read in reverse so the rows nearest the cursor come first.
+fn push_order_by(qb: &mut QueryBuilder<'_, Postgres>, keys: &[SortKey], dir: CursorDirection) {
+    qb.push(" ORDER BY ");
+    for (i, key) in keys.iter().enumerate() {
+        if i > 0 {
+            qb.push(", ");
+        }
+        key.push_expr(qb);
+        qb.push(if dir == CursorDirection::Next { " DESC" } else { " ASC" });
+    }
+}
+
+/// Parses the `cursor` param, rejecting ones made for a different ordering.
+fn parse_cursor(value: Option<&str>, keys: &[SortKey]) -> Result<Option<Cursor>, ()> {
+    let Some(value) = value.filter(|v| !v.is_empty()) else {
+        return Ok(None);
+    };
+    match Cursor::decode(value) {
+        Some(cursor)
+            if cursor.sort == sort_signature(keys)
+                && cursor.key.len() == keys.len()
+                && keys.iter().zip(&cursor.key).all(|(key, value)| key.accepts(value)) =>
+        {
+            Ok(Some(cursor))
+        }
+        _ => Err(()),
+    }
+}
+
+/// Trims the look-ahead row (queries fetch `per_page + 1`) and works out the
+/// cursors either side of the page. `skipped` is whether an offset page has
+/// rows before it.
+fn paginate<T>(
+    mut rows: Vec<T>,
+    per_page: usize,
+    keys: &[SortKey],
+    cursor: Option<&Cursor>,
+    skipped: bool,
+    cursor_key: impl Fn(&T) -> &Vec<String>,
+) -> (Vec<T>, Option<String>, Option<String>) {
+    let has_more = rows.len() > per_page;
+    rows.truncate(per_page);
+
+    let dir = cursor.map(|c| c.dir).unwrap_or(CursorDirection::Next);
+    if dir == CursorDirection::Prev {
+        rows.reverse();
+    }
+    let (more_before, more_after) = match cursor {
+        None => (skipped, has_more),
+        Some(c) if c.dir == CursorDirection::Next => (true, has_more),
+        Some(_) => (has_more, true),
+    };
+
+    let make = |row: Option<&T>, dir: CursorDirection| {
+        row.map(|row| Cursor { sort: sort_signature(keys), dir, key: cursor_key(row).clone() }.encode())
+    };
+    let next_cursor = if more_after { make(rows.last(), CursorDirection::Next) } else { None };
+    let prev_cursor = if more_before { make(rows.first(), CursorDirection::Prev) } else { None };
+    (rows, next_cursor, prev_cursor)
 }
 
 // _______________________________________ Snippets related routes _______________________________________
@@ -318,15 +745,48 @@ pub async fn get_page_snippets(
     app_data: web::Data<AppState>,
     params:   web::Query<PageParams>,
 ) -> actix_web::Result<impl Responder> {
-    let PageParams { language, title, page, limit } = params.into_inner();
-    let current_page = page.unwrap_or(1).max(1);
+    let PageParams {
+        language, title, collection, tags, tag_match, owner, min_stars,
+        created_after, created_before, updated_after, updated_before,
+        q, fuzzy, similarity, sort, cursor, include_total, page, limit,
+    } = params.into_inner();
+    let languages = split_list(language);
+    let tags = split_list(tags);
+    let query = q.map(|q| q.trim().to_string()).filter(|q| !q.is_empty());
+    let fuzzy_title = title.clone().filter(|t| fuzzy && !t.trim().is_empty());
     let per_page     = limit.unwrap_or(12).clamp(1, 100);
-    let offset       = (current_page - 1) * per_page;
+
+    let mut keys = Vec::new();
+    match sort {
+        PageSort::Relevance => {
+            if let Some(q) = &query {
+                keys.push(SortKey::Rank(q.clone()));
+            } else if let Some(t) = &fuzzy_title {
+                keys.push(SortKey::Similarity(t.clone()));
+            }
+        }
+        PageSort::Stars   => keys.push(SortKey::Stars),
+        PageSort::Updated => keys.push(SortKey::Updated),
+        PageSort::Newest  => {}
+    }
+    if let Some(collection_id) = collection {
+        keys.push(SortKey::Position(collection_id));
+    }
+    keys.extend([SortKey::Created, SortKey::Id]);
+
+    let Ok(cursor) = parse_cursor(cursor.as_deref(), &keys) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "Invalid cursor" })));
+    };
+    // A cursor replaces `page`; offsets stay available for numbered pagination.
+    let current_page = cursor.is_none().then(|| page.unwrap_or(1).max(1));
+    let offset       = current_page.map_or(0, |p| (p - 1) * per_page);
+    let include_total = include_total.unwrap_or(cursor.is_none());
 
     let mut count_qb = QueryBuilder::new(
         r#"
         SELECT COUNT(*) AS total 
-        FROM snippets_extension.snippets
+        FROM snippets_extension.snippets s
+        WHERE visibility = 'public'
         "#
     );
 
@@ -338,14 +798,43 @@ pub async fn get_page_snippets(
             s.description,
             s.code,
             s.language,
+            s.visibility,
+            s.prefixes,
+            s.scope,
             -- count how many stars this snippet has
-            COUNT(ss.user_id) AS stars,
+            COUNT(DISTINCT ss.user_id) AS stars,
             -- collect its tags (empty array if none)
             COALESCE(
               array_agg(DISTINCT t.name) 
               FILTER (WHERE t.name IS NOT NULL),
               ARRAY[]::TEXT[]
-            ) AS tags
+            ) AS tags,
+            s.forked_from,
+            (SELECT COUNT(*) FROM snippets_extension.snippets f WHERE f.forked_from = s.id AND f.visibility = 'public') AS forks,
+            -- only reported while the fork's owner can still see the upstream
+            COALESCE(
+              (SELECT up.updated_at > s.forked_at
+                 FROM snippets_extension.snippets up
+                WHERE up.id = s.forked_from
+                  AND (up.visibility <> 'private' OR up.owner_id = s.owner_id)),
+              FALSE
+            ) AS upstream_changed,
+        "#
+    );
+    match &query {
+        Some(q) => {
+            data_qb
+                .push("ts_headline('english', concat_ws(' ', s.description, s.code), websearch_to_tsquery('english', ")
+                .push_bind(q.clone())
+                .push("), 'MaxFragments=2, MaxWords=20, MinWords=5, StartSel=<mark>, StopSel=</mark>') AS highlight, ");
+        }
+        None => {
+            data_qb.push("NULL::TEXT AS highlight, ");
+        }
+    }
+    push_cursor_key(&mut data_qb, &keys);
+    data_qb.push(
+        r#"
         FROM snippets_extension.snippets s
         LEFT JOIN snippets_extension.snippet_stars AS ss
           ON ss.snippet_id = s.id
@@ -353,58 +842,223 @@ pub async fn get_page_snippets(
           ON st.snippet_id = s.id
         LEFT JOIN snippets_extension.tags AS t
           ON t.id = st.tag_id
+        WHERE s.visibility = 'public'
         "#
     );
 
-    let mut has_where = false;
-    match &language {
-        Some(lang) if !lang.is_empty() => {
-            let clause = if has_where { " AND language = " } else { " WHERE language = " };
-            count_qb.push(clause).push_bind(lang);
-            data_qb.push(clause).push_bind(lang);
-            has_where = true;
+    // Both queries already filter on visibility, so every filter is an AND.
+    if !languages.is_empty() {
+        count_qb.push(" AND s.language = ANY(").push_bind(languages.clone()).push(")");
+        data_qb.push(" AND s.language = ANY(").push_bind(languages.clone()).push(")");
+    }
+
+    if !tags.is_empty() {
+        let clause = r#"
+            AND (
+              SELECT COUNT(DISTINCT ft.name)
+                FROM snippets_extension.snippet_tags fst
+                JOIN snippets_extension.tags ft
+                  ON ft.id = fst.tag_id
+               WHERE fst.snippet_id = s.id
+                 AND ft.name = ANY("#;
+        // Duplicate names in the list mustn't make `all` unsatisfiable.
+        let required = match tag_match {
+            TagMatch::Any => 1,
+            TagMatch::All => tags.iter().collect::<std::collections::HashSet<_>>().len() as i64,
+        };
+        count_qb.push(clause).push_bind(tags.clone()).push(")) >= ").push_bind(required);
+        data_qb.push(clause).push_bind(tags.clone()).push(")) >= ").push_bind(required);
+    }
+
+    if let Some(username) = owner.as_deref().map(str::trim).filter(|u| !u.is_empty()) {
+        let clause = " AND s.owner_id = (SELECT u.id FROM users u WHERE u.username = ";
+        count_qb.push(clause).push_bind(username.to_string()).push(")");
+        data_qb.push(clause).push_bind(username.to_string()).push(")");
+    }
+
+    if let Some(min_stars) = min_stars.filter(|&n| n > 0) {
+        let clause = " AND (SELECT COUNT(*) FROM snippets_extension.snippet_stars fss WHERE fss.snippet_id = s.id) >= ";
+        count_qb.push(clause).push_bind(min_stars);
+        data_qb.push(clause).push_bind(min_stars);
+    }
+
+    for (column, op, bound) in [
+        ("s.created_at", " >= ", created_after),
+        ("s.created_at", " < ", created_before),
+        ("s.updated_at", " >= ", updated_after),
+        ("s.updated_at", " < ", updated_before),
+    ] {
+        if let Some(bound) = bound {
+            count_qb.push(" AND ").push(column).push(op).push_bind(bound);
+            data_qb.push(" AND ").push(column).push(op).push_bind(bound);
         }
-        _ => {}
     }
     
+    // `<%` is pg_trgm's word similarity operator; its threshold is set per transaction below.
     if let Some(t) = &title {
         let pattern = format!("%{}%", t);
-        let clause  = if has_where { " AND title ILIKE " } else { " WHERE title ILIKE " };
-        count_qb.push(clause).push_bind(pattern.clone());
-        data_qb.push(clause).push_bind(pattern);
+        if fuzzy_title.is_some() {
+            count_qb.push(" AND (title ILIKE ").push_bind(pattern.clone()).push(" OR ").push_bind(t.clone()).push(" <% title)");
+            data_qb.push(" AND (s.title ILIKE ").push_bind(pattern).push(" OR ").push_bind(t.clone()).push(" <% s.title)");
+        } else {
+            count_qb.push(" AND title ILIKE ").push_bind(pattern.clone());
+            data_qb.push(" AND title ILIKE ").push_bind(pattern);
+        }
     }
 
-     data_qb
-        .push(" GROUP BY s.id, s.title, s.description, s.code, s.language, s.created_at")
-        .push(" ORDER BY s.created_at DESC")
-        .push(" LIMIT ").push_bind(per_page as i64)
-        .push(" OFFSET ").push_bind(offset as i64);
+    // Matches against the weighted title/tags/description/code vector kept by the schema triggers.
+    if let Some(q) = &query {
+        count_qb.push(" AND s.search_vector @@ websearch_to_tsquery('english', ").push_bind(q.clone()).push(")");
+        data_qb.push(" AND s.search_vector @@ websearch_to_tsquery('english', ").push_bind(q.clone()).push(")");
+    }
 
+    // Private collections can't be used to probe which public snippets they hold.
+    if let Some(collection_id) = collection {
+        let clause = r#"
+            AND EXISTS (
+              SELECT 1
+                FROM snippets_extension.collection_snippets cs
+                JOIN snippets_extension.collections c
+                  ON c.id = cs.collection_id
+               WHERE cs.snippet_id = s.id
+                 AND c.visibility <> 'private'
+                 AND cs.collection_id = "#;
+        count_qb.push(clause).push_bind(collection_id).push(")");
+        data_qb.push(clause).push_bind(collection_id).push(")");
+    }
 
-    // --- Execute COUNT ---
-    let (total_records,): (i64,) = count_qb
-        .build_query_as()
-        .fetch_one(&app_data.db)
+    if let Some(cursor) = &cursor {
+        push_keyset(&mut data_qb, &keys, cursor);
+    }
+
+    data_qb.push(" GROUP BY s.id, s.title, s.description, s.code, s.language, s.visibility, s.created_at");
+    push_order_by(&mut data_qb, &keys, cursor.as_ref().map_or(CursorDirection::Next, |c| c.dir));
+    data_qb.push(" LIMIT ").push_bind(per_page as i64 + 1);
+    if offset > 0 {
+        data_qb.push(" OFFSET ").push_bind(offset as i64);
+    }
+
+    let mut tx = app_data.db.begin().await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    if fuzzy_title.is_some() {
+        let threshold = similarity.unwrap_or(DEFAULT_FUZZY_SIMILARITY).clamp(0.1, 1.0);
+        sqlx::query!(
+            "SELECT set_config('pg_trgm.word_similarity_threshold', $1, true)",
+            threshold.to_string(),
+        )
+        .fetch_one(&mut *tx)
         .await
         .map_err(actix_web::error::ErrorInternalServerError)?;
+    }
+
+    // --- Execute COUNT ---
+    let total_records = if include_total {
+        let (total,): (i64,) = count_qb
+            .build_query_as()
+            .fetch_one(&mut *tx)
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+        Some(total)
+    } else {
+        None
+    };
 
     // --- Execute DATA fetch ---
-    let records: Vec<SnippetData> = data_qb
+    let records: Vec<PageRecord> = data_qb
         .build_query_as()
-        .fetch_all(&app_data.db)
+        .fetch_all(&mut *tx)
         .await
         .map_err(actix_web::error::ErrorInternalServerError)?;
 
-    let total_pages = ((total_records as f64) / (per_page as f64)).ceil() as u32;
+    tx.commit().await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let (records, next_cursor, prev_cursor) =
+        paginate(records, per_page as usize, &keys, cursor.as_ref(), offset > 0, |r| &r.cursor_key);
+
+    let did_you_mean = if records.is_empty() && cursor.is_none() && offset == 0 {
+        let single_language = (languages.len() == 1).then(|| languages[0].as_str());
+        suggest_alternatives(&app_data.db, query.as_deref().or(title.as_deref()), single_language)
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?
+    } else {
+        None
+    };
+
+    let total_pages = total_records.map(|total| ((total as f64) / (per_page as f64)).ceil() as u32);
 
     Ok(HttpResponse::Ok().json(PageResponse {
         total_records,
         total_pages,
         current_page,
         records,
+        next_cursor,
+        prev_cursor,
+        did_you_mean,
     }))
 }
 
+/// Closest public title or tag to the search text, and closest language in use, by trigram similarity.
+async fn suggest_alternatives(
+    db: &sqlx::PgPool,
+    text: Option<&str>,
+    language: Option<&str>,
+) -> Result<Option<DidYouMean>, sqlx::Error> {
+    let mut suggestion = DidYouMean::default();
+
+    if let Some(text) = text.map(str::trim).filter(|t| !t.is_empty()) {
+        // `%` uses the default 0.3 similarity threshold and the trigram indexes.
+        suggestion.query = sqlx::query_scalar!(
+            r#"
+            SELECT term AS "term!"
+            FROM (
+                SELECT s.title AS term, similarity(s.title, $1) AS score
+                  FROM snippets_extension.snippets s
+                 WHERE s.visibility = 'public'
+                   AND s.title % $1
+                UNION ALL
+                SELECT t.name, similarity(t.name, $1)
+                  FROM snippets_extension.tags t
+                 WHERE t.name % $1
+                   AND EXISTS (
+                     SELECT 1
+                       FROM snippets_extension.snippet_tags st
+                       JOIN snippets_extension.snippets s ON s.id = st.snippet_id
+                      WHERE st.tag_id = t.id
+                        AND s.visibility = 'public'
+                   )
+            ) candidates
+            WHERE lower(term) <> lower($1)
+            ORDER BY score DESC, term
+            LIMIT 1
+            "#,
+            text,
+        )
+        .fetch_optional(db)
+        .await?;
+    }
+
+    // Language names are short, so a lower bar than the default threshold is needed.
+    if let Some(language) = language.filter(|l| !l.is_empty()) {
+        suggestion.language = sqlx::query_scalar!(
+            r#"
+            SELECT language AS "language!"
+              FROM snippets_extension.snippets
+             WHERE visibility = 'public'
+               AND language <> $1
+             GROUP BY language
+            HAVING similarity(language, $1) >= 0.1
+             ORDER BY similarity(language, $1) DESC, language
+             LIMIT 1
+            "#,
+            language,
+        )
+        .fetch_optional(db)
+        .await?;
+    }
+
+    Ok((suggestion.query.is_some() || suggestion.language.is_some()).then_some(suggestion))
+}
+
 
 #[derive(Deserialize)]
 pub struct IdsParams {
@@ -441,6 +1095,7 @@ pub async fn get_snippets_by_ids(
             language
         FROM snippets_extension.snippets 
         WHERE id = ANY($1)
+          AND visibility <> 'private'
         "#, 
         &ids[..] 
     )
@@ -452,7 +1107,84 @@ pub async fn get_snippets_by_ids(
 }
 
 
-#[post("/{snippetId}/star")]
+/// Single snippet by ID, for share links. Works for public and unlisted snippets.
+#[get("/{snippetId}")]
+pub async fn get_snippet(
+    app_data: web::Data<AppState>,
+    path:     web::Path<Uuid>,
+) -> actix_web::Result<impl Responder> {
+    let snippet_id = path.into_inner();
+
+    let snippet = sqlx::query_as!(
+        SnippetData,
+        r#"
+        SELECT
+            s.id,
+            s.title,
+            s.description,
+            s.code,
+            s.language,
+            s.visibility AS "visibility: SnippetVisibility",
+            s.prefixes,
+            s.scope,
+            (SELECT COUNT(*) FROM snippets_extension.snippet_stars ss WHERE ss.snippet_id = s.id) AS "stars!: i64",
+            COALESCE(
+                (SELECT array_agg(t.name)
+                   FROM snippets_extension.snippet_tags st
+                   JOIN snippets_extension.tags t ON t.id = st.tag_id
+                  WHERE st.snippet_id = s.id),
+                ARRAY[]::TEXT[]
+            ) AS "tags!: Vec<String>",
+            s.forked_from,
+            (SELECT COUNT(*) FROM snippets_extension.snippets f WHERE f.forked_from = s.id AND f.visibility = 'public') AS "forks!: i64",
+            -- only reported while the fork's owner can still see the upstream
+            COALESCE(
+              (SELECT up.updated_at > s.forked_at
+                 FROM snippets_extension.snippets up
+                WHERE up.id = s.forked_from
+                  AND (up.visibility <> 'private' OR up.owner_id = s.owner_id)),
+              FALSE
+            ) AS "upstream_changed!"
+        FROM snippets_extension.snippets s
+        WHERE s.id = $1
+          AND s.visibility <> 'private'
+        "#,
+        snippet_id
+    )
+    .fetch_optional(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    match snippet {
+        Some(snippet) => {
+            let syntax = placeholders::analyze(snippet.code.as_deref().unwrap_or_default());
+            Ok(HttpResponse::Ok().json(serde_json::json!({ "snippet": snippet, "syntax": syntax })))
+        }
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ValidateSnippetRequest {
+    pub code: String,
+}
+
+/// Checks a snippet body without saving it, so editors can preview its
+/// tabstops and variables and flag broken syntax as it's typed.
+#[post("/validate")]
+pub async fn validate_snippet(json_data: web::Json<ValidateSnippetRequest>) -> impl Responder {
+    // nothing larger can be saved on any plan
+    let max_bytes = Entitlements::for_plan(SubscriptionPlan::Pro).max_snippet_bytes;
+    if json_data.code.len() > max_bytes {
+        return HttpResponse::PayloadTooLarge().json(serde_json::json!({
+            "error": format!("Snippets can be at most {max_bytes} bytes")
+        }));
+    }
+    HttpResponse::Ok().json(placeholders::analyze(&json_data.code))
+}
+
+
+#[post("")]
 pub async fn star_snippet(
     app_data: web::Data<AppState>,
     path: web::Path<Uuid>,
@@ -461,6 +1193,28 @@ pub async fn star_snippet(
     let snippet_id = path.into_inner();
     let user_id = user_data.id;
 
+    // Private snippets can only be starred by their owner; others get a 404
+    // rather than learning the snippet exists.
+    let visible = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1
+              FROM snippets_extension.snippets
+             WHERE id = $2
+               AND (visibility <> 'private' OR owner_id = $1)
+        ) AS "exists!"
+        "#,
+        user_id,
+        snippet_id
+    )
+    .fetch_one(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    if !visible {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
     sqlx::query!(
         r#"
         INSERT INTO snippets_extension.snippet_stars (user_id, snippet_id)
@@ -477,7 +1231,7 @@ pub async fn star_snippet(
     Ok(HttpResponse::Ok().finish())
 }
 
-#[delete("/{snippetId}/star")]
+#[delete("")]
 pub async fn unstar_snippet(
     app_data: web::Data<AppState>,
     path: web::Path<Uuid>,
@@ -500,4 +1254,163 @@ pub async fn unstar_snippet(
     .map_err(actix_web::error::ErrorInternalServerError)?;
 
     Ok(HttpResponse::Ok().finish())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CREATED: &str = "2026-01-02 03:04:05.123456+00";
+    const ID: &str = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+
+    fn keys() -> Vec<SortKey> {
+        vec![SortKey::Stars, SortKey::Created, SortKey::Id]
+    }
+
+    fn cursor(dir: CursorDirection, key: &[&str]) -> Cursor {
+        Cursor { sort: sort_signature(&keys()), dir, key: key.iter().map(|k| k.to_string()).collect() }
+    }
+
+    fn sql(build: impl FnOnce(&mut QueryBuilder<'_, Postgres>)) -> String {
+        let mut qb = QueryBuilder::new("");
+        build(&mut qb);
+        qb.sql().to_string()
+    }
+
+    #[test]
+    fn cursors_must_match_the_ordering() {
+        let keys = keys();
+        assert!(matches!(parse_cursor(None, &keys), Ok(None)));
+        assert!(matches!(parse_cursor(Some(""), &keys), Ok(None)));
+
+        let valid = cursor(CursorDirection::Next, &["3", CREATED, ID]);
+        let parsed = parse_cursor(Some(&valid.encode()), &keys).unwrap().unwrap();
+        assert_eq!(parsed.key, valid.key);
+
+        // made for another ordering, or for the right one with a key missing
+        let other = Cursor { sort: "updated,created,id".to_string(), ..valid.clone() };
+        assert!(parse_cursor(Some(&other.encode()), &keys).is_err());
+        let short = cursor(CursorDirection::Next, &[CREATED, ID]);
+        assert!(parse_cursor(Some(&short.encode()), &keys).is_err());
+        assert!(parse_cursor(Some("garbage"), &keys).is_err());
+    }
+
+    #[test]
+    fn tampered_key_values_are_rejected_before_the_query() {
+        let keys = keys();
+        for key in [
+            ["3.5", CREATED, ID],
+            ["three", CREATED, ID],
+            ["3", "yesterday", ID],
+            ["3", "2026-01-02", ID],
+            ["3", CREATED, "not-a-uuid"],
+            ["3", CREATED, "'); DROP TABLE users; --"],
+        ] {
+            let tampered = cursor(CursorDirection::Next, &key);
+            assert!(parse_cursor(Some(&tampered.encode()), &keys).is_err(), "{key:?}");
+        }
+    }
+
+    #[test]
+    fn key_values_accept_what_postgres_prints() {
+        assert!(SortKey::Created.accepts("2026-01-02 03:04:05+00"));
+        assert!(SortKey::Updated.accepts("2026-01-02 03:04:05.5+05:30"));
+        assert!(SortKey::Rank(String::new()).accepts("0.0607927"));
+        assert!(SortKey::Similarity(String::new()).accepts("1"));
+        assert!(SortKey::Position(Uuid::nil()).accepts("-4"));
+        assert!(!SortKey::Position(Uuid::nil()).accepts("99999999999"));
+        assert!(SortKey::Id.accepts(ID));
+    }
+
+    #[test]
+    fn keyset_compares_every_key_as_a_row() {
+        let keys = keys();
+        let next = sql(|qb| push_keyset(qb, &keys, &cursor(CursorDirection::Next, &["3", CREATED, ID])));
+        assert_eq!(
+            next,
+            " AND ((SELECT COUNT(*) FROM snippets_extension.snippet_stars ks WHERE ks.snippet_id = s.id), s.created_at, s.id) \
+             < (CAST($1 AS BIGINT), CAST($2 AS TIMESTAMPTZ), CAST($3 AS UUID))"
+        );
+        let prev = sql(|qb| push_keyset(qb, &keys[1..], &cursor(CursorDirection::Prev, &[CREATED, ID])));
+        assert_eq!(prev, " AND (s.created_at, s.id) > (CAST($1 AS TIMESTAMPTZ), CAST($2 AS UUID))");
+    }
+
+    #[test]
+    fn order_follows_the_direction_and_ends_with_the_id() {
+        let keys = [SortKey::Updated, SortKey::Created, SortKey::Id];
+        assert_eq!(
+            sql(|qb| push_order_by(qb, &keys, CursorDirection::Next)),
+            " ORDER BY s.updated_at DESC, s.created_at DESC, s.id DESC"
+        );
+        assert_eq!(
+            sql(|qb| push_order_by(qb, &keys, CursorDirection::Prev)),
+            " ORDER BY s.updated_at ASC, s.created_at ASC, s.id ASC"
+        );
+        let position = sql(|qb| SortKey::Position(Uuid::nil()).push_expr(qb));
+        assert!(position.starts_with("-(SELECT kc.position"), "{position}");
+    }
+
+    /// Rows whose cursor key is just their number.
+    fn rows(numbers: impl IntoIterator<Item = u32>) -> Vec<Vec<String>> {
+        numbers.into_iter().map(|n| vec![n.to_string()]).collect()
+    }
+
+    /// The page's rows and the keys in its next and prev cursors.
+    type Page = (Vec<Vec<String>>, Option<Vec<String>>, Option<Vec<String>>);
+
+    fn page(fetched: Vec<Vec<String>>, cursor: Option<&Cursor>, skipped: bool) -> Page {
+        let keys = [SortKey::Id];
+        let (rows, next, prev) = paginate(fetched, 3, &keys, cursor, skipped, |r| r);
+        let key = |c: Option<String>| {
+            c.map(|c| {
+                let c = Cursor::decode(&c).unwrap();
+                assert_eq!(c.sort, "id");
+                c.key
+            })
+        };
+        (rows, key(next), key(prev))
+    }
+
+    #[test]
+    fn first_page_only_points_forward() {
+        let (page_rows, next, prev) = page(rows([9, 8, 7, 6]), None, false);
+        assert_eq!(page_rows, rows([9, 8, 7]));
+        assert_eq!((next, prev), (Some(vec!["7".to_string()]), None));
+
+        let (page_rows, next, prev) = page(rows([9, 8]), None, false);
+        assert_eq!(page_rows, rows([9, 8]));
+        assert_eq!((next, prev), (None, None));
+
+        // a later offset page has rows before it
+        let (_, next, prev) = page(rows([5, 4, 3]), None, true);
+        assert_eq!((next, prev), (None, Some(vec!["5".to_string()])));
+    }
+
+    #[test]
+    fn next_pages_point_back_and_forward_while_rows_remain() {
+        let after = Cursor { sort: "id".to_string(), dir: CursorDirection::Next, key: vec!["7".to_string()] };
+        let (page_rows, next, prev) = page(rows([6, 5, 4, 3]), Some(&after), false);
+        assert_eq!(page_rows, rows([6, 5, 4]));
+        assert_eq!((next, prev), (Some(vec!["4".to_string()]), Some(vec!["6".to_string()])));
+
+        let (page_rows, next, prev) = page(rows([2, 1]), Some(&after), false);
+        assert_eq!(page_rows, rows([2, 1]));
+        assert_eq!((next, prev), (None, Some(vec!["2".to_string()])));
+    }
+
+    #[test]
+    fn prev_pages_are_flipped_back_into_listing_order() {
+        let before = Cursor { sort: "id".to_string(), dir: CursorDirection::Prev, key: vec!["4".to_string()] };
+        // fetched nearest-first, ascending
+        let (page_rows, next, prev) = page(rows([5, 6, 7, 8]), Some(&before), false);
+        assert_eq!(page_rows, rows([7, 6, 5]));
+        assert_eq!((next, prev), (Some(vec!["5".to_string()]), Some(vec!["7".to_string()])));
+
+        let (page_rows, next, prev) = page(rows([5, 6]), Some(&before), false);
+        assert_eq!(page_rows, rows([6, 5]));
+        assert_eq!((next, prev), (Some(vec!["5".to_string()]), None));
+
+        let (page_rows, next, prev) = page(Vec::new(), Some(&before), false);
+        assert!(page_rows.is_empty());
+        assert_eq!((next, prev), (None, None));
+    }
+}
@@ -0,0 +1,255 @@
+use actix_web::{get, http::header::{ContentDisposition, DispositionParam, DispositionType}, post, web, HttpResponse, Responder};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    handlers::{
+        snippet_handler::{count_owned_snippets, split_list},
+        snippet_revision_handler::record_revision,
+    },
+    models::{Entitlements, SnippetVisibility, UserData},
+    snippet_formats::{
+        parse_jetbrains_templates, parse_sublime_snippet, parse_textmate_snippet, parse_vscode_snippets,
+        to_jetbrains_templates, to_sublime_snippets, to_textmate_snippets, to_vscode_snippets, ExportFormat,
+        placeholders, PortableSnippet,
+    },
+    AppState,
+};
+
+/// Most snippets a single import may create.
+const MAX_IMPORT_SNIPPETS: usize = 500;
+
+#[derive(Deserialize)]
+pub struct ImportParams {
+    /// Name of the uploaded file, which picks the format: `.sublime-snippet`,
+    /// `.tmSnippet`, `.xml` (JetBrains live templates) or VS Code JSON. For the
+    /// latter, `<language>.json` sets the language of every snippet.
+    pub filename:   Option<String>,
+    /// Overrides the language taken from the file name or each snippet's scope.
+    pub language:   Option<String>,
+    #[serde(default)]
+    pub visibility: SnippetVisibility,
+}
+
+/// Creates one snippet per entry of a snippets file sent as the request body.
+/// Nothing is imported if any body has snippet syntax errors; the response
+/// lists the diagnostics of each imported snippet.
+#[post("/import")]
+pub async fn import_snippets(
+    app_data: web::Data<AppState>,
+    params: web::Query<ImportParams>,
+    body: web::Bytes,
+    user_data: web::ReqData<UserData>,
+    entitlements: Entitlements,
+) -> actix_web::Result<impl Responder> {
+    let user_id = user_data.id;
+    let ImportParams { filename, language, visibility } = params.into_inner();
+
+    let Ok(text) = std::str::from_utf8(&body) else {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "File must be UTF-8" })));
+    };
+
+    let filename = filename.as_deref().and_then(|name| name.rsplit('/').next()).unwrap_or_default();
+    let language = language.filter(|l| !l.trim().is_empty());
+
+    let parsed = if let Some(stem) = filename.strip_suffix(".sublime-snippet") {
+        parse_sublime_snippet(text, stem).map(|snippet| vec![snippet])
+    } else if let Some(stem) = filename.strip_suffix(".tmSnippet") {
+        parse_textmate_snippet(text, stem).map(|snippet| vec![snippet])
+    } else if filename.ends_with(".xml") {
+        parse_jetbrains_templates(text)
+    } else {
+        // `rust.json` is a language file; `*.code-snippets` files name languages per snippet.
+        let file_language = filename.strip_suffix(".json").filter(|stem| !stem.is_empty());
+        parse_vscode_snippets(text, language.as_deref().or(file_language))
+    };
+    let parsed = parsed.map(|snippets| match &language {
+        Some(language) => snippets
+            .into_iter()
+            .map(|snippet| PortableSnippet { language: language.clone(), ..snippet })
+            .collect(),
+        None => snippets,
+    });
+
+    let snippets = match parsed {
+        Ok(snippets) if snippets.is_empty() => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "No snippets found" })));
+        }
+        Ok(snippets) if snippets.len() > MAX_IMPORT_SNIPPETS => {
+            return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("At most {MAX_IMPORT_SNIPPETS} snippets can be imported at once")
+            })));
+        }
+        Ok(snippets) => snippets,
+        Err(error) => return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": error }))),
+    };
+
+    for snippet in &snippets {
+        entitlements.check_snippet_content(&snippet.code, &[])?;
+    }
+
+    // Same check as creating a snippet by hand; one broken body rejects the file.
+    let syntaxes: Vec<_> = snippets.iter().map(|snippet| placeholders::analyze(&snippet.code)).collect();
+    let invalid: Vec<_> = snippets
+        .iter()
+        .zip(&syntaxes)
+        .enumerate()
+        .filter(|(_, (_, syntax))| syntax.has_errors())
+        .map(|(index, (snippet, syntax))| serde_json::json!({
+            "index": index,
+            "title": snippet.title,
+            "diagnostics": syntax.diagnostics,
+        }))
+        .collect();
+    if !invalid.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Invalid snippet syntax",
+            "snippets": invalid,
+        })));
+    }
+
+    let mut tx = app_data.db.begin().await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let owned = count_owned_snippets(&mut tx, user_id).await.map_err(actix_web::error::ErrorInternalServerError)?;
+    entitlements.check_new_snippets(owned, snippets.len() as i64)?;
+
+    let mut ids = Vec::with_capacity(snippets.len());
+    let mut imported = Vec::with_capacity(snippets.len());
+    for (snippet, syntax) in snippets.into_iter().zip(syntaxes) {
+        let id = sqlx::query_scalar!(
+            r#"
+            INSERT INTO snippets_extension.snippets
+                (owner_id, title, description, code, language, visibility, prefixes, scope)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING id
+            "#,
+            user_id,
+            snippet.title,
+            snippet.description,
+            snippet.code,
+            snippet.language,
+            visibility as SnippetVisibility,
+            &snippet.prefixes,
+            snippet.scope,
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+        record_revision(&mut tx, id, user_id, None, entitlements.max_revisions)
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+        ids.push(id);
+        imported.push(serde_json::json!({ "id": id, "title": snippet.title, "diagnostics": syntax.diagnostics }));
+    }
+
+    tx.commit().await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "imported": ids.len(), "ids": ids, "snippets": imported })))
+}
+
+#[derive(Deserialize)]
+pub struct ExportParams {
+    pub collection: Option<Uuid>,
+    /// Comma-separated tag names; snippets with any of them are exported.
+    pub tags:       Option<String>,
+    #[serde(default)]
+    pub format:     ExportFormat,
+}
+
+/// Exports a user's snippets, optionally limited to a collection or tags.
+/// Other users' snippets are limited to public ones.
+///
+/// VS Code exports download as a `.code-snippets` file. Other formats come back
+/// as JSON listing the files to write and a warning for each snippet feature
+/// the format can't express.
+#[get("/{userId}/snippets/export")]
+pub async fn export_snippets(
+    app_data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    params: web::Query<ExportParams>,
+    user_data: web::ReqData<UserData>,
+) -> actix_web::Result<impl Responder> {
+    let owner_id = path.into_inner();
+    let ExportParams { collection, tags, format } = params.into_inner();
+    let tags = split_list(tags);
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT s.title, s.description, s.code, s.language, s.prefixes, s.scope
+        FROM snippets_extension.snippets s
+        WHERE s.owner_id = $1
+          AND ($1 = $2 OR s.visibility = 'public')
+          AND (
+            $3::UUID IS NULL
+            OR EXISTS (
+              SELECT 1
+                FROM snippets_extension.collection_snippets cs
+                JOIN snippets_extension.collections c
+                  ON c.id = cs.collection_id
+               WHERE cs.collection_id = $3
+                 AND cs.snippet_id = s.id
+                 AND ($1 = $2 OR c.visibility <> 'private')
+            )
+          )
+          AND (
+            cardinality($4::TEXT[]) = 0
+            OR EXISTS (
+              SELECT 1
+                FROM snippets_extension.snippet_tags st
+                JOIN snippets_extension.tags t
+                  ON t.id = st.tag_id
+               WHERE st.snippet_id = s.id
+                 AND t.name = ANY($4)
+            )
+          )
+        ORDER BY
+          (SELECT cs.position FROM snippets_extension.collection_snippets cs WHERE cs.collection_id = $3 AND cs.snippet_id = s.id),
+          s.created_at DESC
+        "#,
+        owner_id,
+        user_data.id,
+        collection,
+        &tags,
+    )
+    .fetch_all(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let snippets: Vec<PortableSnippet> = rows
+        .into_iter()
+        .map(|row| PortableSnippet {
+            title: row.title,
+            description: row.description,
+            code: row.code.unwrap_or_default(),
+            language: row.language,
+            prefixes: row.prefixes,
+            scope: row.scope,
+        })
+        .collect();
+
+    let export = match format {
+        ExportFormat::VsCode => None,
+        ExportFormat::JetBrains => Some(to_jetbrains_templates(&snippets)),
+        ExportFormat::Sublime => Some(to_sublime_snippets(&snippets)),
+        ExportFormat::TextMate => Some(to_textmate_snippets(&snippets)),
+    };
+    if let Some(export) = export {
+        return Ok(HttpResponse::Ok().json(serde_json::json!({
+            "format": format,
+            "files": export.files,
+            "warnings": export.warnings,
+        })));
+    }
+
+    let file = serde_json::to_string_pretty(&to_vscode_snippets(&snippets))
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("snippets.code-snippets".to_string())],
+        })
+        .body(file))
+}
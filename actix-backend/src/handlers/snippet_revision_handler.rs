@@ -0,0 +1,346 @@
+use actix_web::{get, post, web, HttpResponse, Responder};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use sqlx::{prelude::FromRow, Pool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    handlers::snippet_handler::invalid_syntax,
+    models::{Entitlements, SnippetVisibility, UserData},
+    snippet_formats::placeholders,
+    AppState,
+};
+
+/// Replaces the snippet's tags with `tags`, creating any tag names that don't exist yet.
+pub async fn set_snippet_tags(
+    tx: &mut Transaction<'_, Postgres>,
+    snippet_id: Uuid,
+    tags: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"
+        WITH new_tags AS (
+            INSERT INTO snippets_extension.tags(name)
+            SELECT unnest($1::text[])
+            ON CONFLICT (name) DO NOTHING
+            RETURNING id
+        ),
+        all_tags AS (
+            -- tags inserted above aren't visible to a plain SELECT in the same statement
+            SELECT id FROM new_tags
+            UNION
+            SELECT id
+              FROM snippets_extension.tags
+             WHERE name = ANY($1::text[])
+        ),
+        deleted AS (
+            DELETE FROM snippets_extension.snippet_tags st
+             WHERE st.snippet_id = $2
+               AND st.tag_id NOT IN (SELECT id FROM all_tags)
+        ),
+        inserted AS (
+            INSERT INTO snippets_extension.snippet_tags(snippet_id, tag_id)
+            SELECT $2, id FROM all_tags
+            ON CONFLICT DO NOTHING
+        )
+        SELECT 1 as unused;
+        "#,
+        tags,
+        snippet_id,
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Snapshots the snippet's current state as its next revision and prunes revisions
+/// beyond the newest `keep`. Call it in the same transaction as the change it records.
+pub async fn record_revision(
+    tx: &mut Transaction<'_, Postgres>,
+    snippet_id: Uuid,
+    author_id: Uuid,
+    reverted_from: Option<i32>,
+    keep: Option<i32>,
+) -> Result<i32, sqlx::Error> {
+    // Two saves racing would both read the same MAX(revision). Holding the snippet
+    // row makes the second wait, and its INSERT then sees the first one's revision.
+    sqlx::query!(
+        "SELECT id FROM snippets_extension.snippets WHERE id = $1 FOR UPDATE",
+        snippet_id
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    let revision = sqlx::query_scalar!(
+        r#"
+        INSERT INTO snippets_extension.snippet_revisions
+            (snippet_id, revision, title, description, code, language, tags, prefixes, scope, visibility, author_id, reverted_from)
+        SELECT
+            s.id,
+            COALESCE((SELECT MAX(r.revision) FROM snippets_extension.snippet_revisions r WHERE r.snippet_id = s.id), 0) + 1,
+            s.title,
+            s.description,
+            s.code,
+            s.language,
+            COALESCE(
+                (SELECT array_agg(t.name ORDER BY t.name)
+                   FROM snippets_extension.snippet_tags st
+                   JOIN snippets_extension.tags t ON t.id = st.tag_id
+                  WHERE st.snippet_id = s.id),
+                ARRAY[]::TEXT[]
+            ),
+            s.prefixes,
+            s.scope,
+            s.visibility,
+            $2,
+            $3
+        FROM snippets_extension.snippets s
+        WHERE s.id = $1
+        RETURNING revision
+        "#,
+        snippet_id,
+        author_id,
+        reverted_from
+    )
+    .fetch_one(&mut **tx)
+    .await?;
+
+    if let Some(keep) = keep {
+        sqlx::query!(
+            r#"
+            DELETE FROM snippets_extension.snippet_revisions
+             WHERE snippet_id = $1
+               AND revision <= $2
+            "#,
+            snippet_id,
+            revision - keep
+        )
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(revision)
+}
+
+async fn owns_snippet(db: &Pool<Postgres>, snippet_id: Uuid, user_id: Uuid) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM snippets_extension.snippets WHERE id = $1 AND owner_id = $2
+        ) AS "exists!"
+        "#,
+        snippet_id,
+        user_id
+    )
+    .fetch_one(db)
+    .await
+}
+
+#[derive(Serialize, FromRow)]
+pub struct RevisionSummary {
+    pub revision:      i32,
+    pub title:         String,
+    pub language:      String,
+    pub reverted_from: Option<i32>,
+    pub created_at:    DateTime<Utc>,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct RevisionData {
+    pub revision:      i32,
+    pub title:         String,
+    pub description:   Option<String>,
+    pub code:          Option<String>,
+    pub language:      String,
+    pub tags:          Vec<String>,
+    pub prefixes:      Vec<String>,
+    pub scope:         Option<String>,
+    pub visibility:    SnippetVisibility,
+    pub reverted_from: Option<i32>,
+    pub created_at:    DateTime<Utc>,
+}
+
+async fn fetch_revision(db: &Pool<Postgres>, snippet_id: Uuid, revision: i32) -> Result<Option<RevisionData>, sqlx::Error> {
+    sqlx::query_as!(
+        RevisionData,
+        r#"
+        SELECT
+            revision, title, description, code, language, tags, prefixes, scope,
+            visibility AS "visibility: SnippetVisibility",
+            reverted_from, created_at
+          FROM snippets_extension.snippet_revisions
+         WHERE snippet_id = $1
+           AND revision = $2
+        "#,
+        snippet_id,
+        revision
+    )
+    .fetch_optional(db)
+    .await
+}
+
+// Revisions are private to the owner, so everything below 404s for anyone else.
+
+#[get("/{userId}/snippets/{snippetId}/revisions")]
+pub async fn list_revisions(
+    app_data: web::Data<AppState>,
+    path: web::Path<(Uuid, Uuid)>,
+    user_data: web::ReqData<UserData>,
+) -> actix_web::Result<impl Responder> {
+    let (user_id, snippet_id) = path.into_inner();
+
+    if user_id != user_data.id || !owns_snippet(&app_data.db, snippet_id, user_id).await.map_err(actix_web::error::ErrorInternalServerError)? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let revisions = sqlx::query_as!(
+        RevisionSummary,
+        r#"
+        SELECT revision, title, language, reverted_from, created_at
+          FROM snippets_extension.snippet_revisions
+         WHERE snippet_id = $1
+         ORDER BY revision DESC
+        "#,
+        snippet_id
+    )
+    .fetch_all(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "revisions": revisions })))
+}
+
+#[derive(Deserialize)]
+pub struct DiffParams {
+    pub from: i32,
+    pub to:   i32,
+}
+
+/// Unified diff of the code between two revisions.
+#[get("/{userId}/snippets/{snippetId}/revisions/diff")]
+pub async fn diff_revisions(
+    app_data: web::Data<AppState>,
+    path: web::Path<(Uuid, Uuid)>,
+    params: web::Query<DiffParams>,
+    user_data: web::ReqData<UserData>,
+) -> actix_web::Result<impl Responder> {
+    let (user_id, snippet_id) = path.into_inner();
+
+    if user_id != user_data.id || !owns_snippet(&app_data.db, snippet_id, user_id).await.map_err(actix_web::error::ErrorInternalServerError)? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let from = fetch_revision(&app_data.db, snippet_id, params.from).await.map_err(actix_web::error::ErrorInternalServerError)?;
+    let to = fetch_revision(&app_data.db, snippet_id, params.to).await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let (Some(from), Some(to)) = (from, to) else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "Revision not found" })));
+    };
+
+    let old_code = from.code.as_deref().unwrap_or_default();
+    let new_code = to.code.as_deref().unwrap_or_default();
+    let diff = TextDiff::from_lines(old_code, new_code)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("revision {}", from.revision), &format!("revision {}", to.revision))
+        .to_string();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "from": from.revision,
+        "to":   to.revision,
+        "diff": diff,
+    })))
+}
+
+#[get("/{userId}/snippets/{snippetId}/revisions/{revision}")]
+pub async fn get_revision(
+    app_data: web::Data<AppState>,
+    path: web::Path<(Uuid, Uuid, i32)>,
+    user_data: web::ReqData<UserData>,
+) -> actix_web::Result<impl Responder> {
+    let (user_id, snippet_id, revision) = path.into_inner();
+
+    if user_id != user_data.id || !owns_snippet(&app_data.db, snippet_id, user_id).await.map_err(actix_web::error::ErrorInternalServerError)? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    match fetch_revision(&app_data.db, snippet_id, revision).await.map_err(actix_web::error::ErrorInternalServerError)? {
+        Some(revision) => Ok(HttpResponse::Ok().json(serde_json::json!({ "revision": revision }))),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// Restores an old revision. The restore is recorded as a new revision rather than
+/// discarding the ones after it.
+#[post("/{snippetId}/revisions/{revision}/revert")]
+pub async fn revert_snippet(
+    app_data: web::Data<AppState>,
+    path: web::Path<(Uuid, i32)>,
+    user_data: web::ReqData<UserData>,
+    entitlements: Entitlements,
+) -> actix_web::Result<impl Responder> {
+    let (snippet_id, revision) = path.into_inner();
+    let user_id = user_data.id;
+
+    if !owns_snippet(&app_data.db, snippet_id, user_id).await.map_err(actix_web::error::ErrorInternalServerError)? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let Some(target) = fetch_revision(&app_data.db, snippet_id, revision).await.map_err(actix_web::error::ErrorInternalServerError)? else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({ "error": "Revision not found" })));
+    };
+
+    // The revision may predate a downgrade, so it has to fit the current plan again.
+    entitlements.check_snippet_content(target.code.as_deref().unwrap_or_default(), &target.tags)?;
+    // and to what the parser accepts now
+    let syntax = placeholders::analyze(target.code.as_deref().unwrap_or_default());
+    if syntax.has_errors() {
+        return Ok(invalid_syntax(syntax));
+    }
+
+    let mut tx = app_data.db.begin().await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    sqlx::query!(
+        r#"
+        UPDATE snippets_extension.snippets
+        SET
+            title       = $1,
+            description = $2,
+            code        = $3,
+            language    = $4,
+            visibility  = $7,
+            prefixes    = $8,
+            scope       = $9,
+            updated_at  = NOW()
+        WHERE
+            id       = $5
+            AND owner_id = $6
+        "#,
+        target.title,
+        target.description,
+        target.code,
+        target.language,
+        snippet_id,
+        user_id,
+        target.visibility as SnippetVisibility,
+        &target.prefixes,
+        target.scope,
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    set_snippet_tags(&mut tx, snippet_id, &target.tags)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let new_revision = record_revision(&mut tx, snippet_id, user_id, Some(target.revision), entitlements.max_revisions)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    tx.commit().await.map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "revision": new_revision, "diagnostics": syntax.diagnostics })))
+}
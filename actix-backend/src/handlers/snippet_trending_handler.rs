@@ -0,0 +1,106 @@
+use actix_web::{get, web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use sqlx::{prelude::FromRow, QueryBuilder};
+
+use crate::{
+    handlers::snippet_handler::{split_list, SnippetData},
+    models::TrendingWindow,
+    AppState,
+};
+
+#[derive(Deserialize)]
+pub struct TrendingParams {
+    #[serde(default)]
+    pub window:   TrendingWindow,
+    /// One language or a comma-separated list.
+    pub language: Option<String>,
+    pub limit:    Option<u32>,
+}
+
+#[derive(Serialize, FromRow)]
+pub struct TrendingRecord {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub snippet:      SnippetData,
+    pub score:        f64,
+    /// Stars received inside the window.
+    pub recent_stars: i64,
+}
+
+/// Public snippets ranked by the scores the trending job last materialized.
+#[get("/trending")]
+pub async fn get_trending_snippets(
+    app_data: web::Data<AppState>,
+    params:   web::Query<TrendingParams>,
+) -> actix_web::Result<impl Responder> {
+    let TrendingParams { window, language, limit } = params.into_inner();
+    let languages = split_list(language);
+    let limit = limit.unwrap_or(20).clamp(1, 100);
+
+    let mut qb = QueryBuilder::new(
+        r#"
+        SELECT
+            s.id,
+            s.title,
+            s.description,
+            s.code,
+            s.language,
+            s.visibility,
+            s.prefixes,
+            s.scope,
+            (SELECT COUNT(*) FROM snippets_extension.snippet_stars ss WHERE ss.snippet_id = s.id) AS stars,
+            COALESCE(
+              (SELECT array_agg(t.name ORDER BY t.name)
+                 FROM snippets_extension.snippet_tags st
+                 JOIN snippets_extension.tags t
+                   ON t.id = st.tag_id
+                WHERE st.snippet_id = s.id),
+              ARRAY[]::TEXT[]
+            ) AS tags,
+            s.forked_from,
+            (SELECT COUNT(*) FROM snippets_extension.snippets f WHERE f.forked_from = s.id AND f.visibility = 'public') AS forks,
+            -- only reported while the fork's owner can still see the upstream
+            COALESCE(
+              (SELECT up.updated_at > s.forked_at
+                 FROM snippets_extension.snippets up
+                WHERE up.id = s.forked_from
+                  AND (up.visibility <> 'private' OR up.owner_id = s.owner_id)),
+              FALSE
+            ) AS upstream_changed,
+            tr.score,
+            tr.recent_stars
+        FROM snippets_extension.snippet_trending tr
+        JOIN snippets_extension.snippets s
+          ON s.id = tr.snippet_id
+        -- scores can be a few minutes old, so re-check visibility
+        WHERE s.visibility = 'public'
+          AND tr.time_window = "#
+    );
+    qb.push_bind(window);
+
+    if !languages.is_empty() {
+        qb.push(" AND s.language = ANY(").push_bind(languages).push(")");
+    }
+
+    qb.push(" ORDER BY tr.score DESC, s.id DESC LIMIT ").push_bind(limit as i64);
+
+    let records: Vec<TrendingRecord> = qb
+        .build_query_as()
+        .fetch_all(&app_data.db)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    let computed_at = sqlx::query_scalar!(
+        "SELECT MAX(computed_at) FROM snippets_extension.snippet_trending WHERE time_window = $1",
+        window as TrendingWindow,
+    )
+    .fetch_one(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "window": window,
+        "computed_at": computed_at,
+        "records": records,
+    })))
+}
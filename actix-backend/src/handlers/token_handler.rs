@@ -0,0 +1,149 @@
+use actix_web::{delete, get, post, web, HttpResponse, Responder};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::FromRow;
+use uuid::Uuid;
+
+use crate::{
+    models::{UserData, GRANTABLE_SCOPES, PAT_PREFIX},
+    utils::{generate_token, hash_token},
+    AppState,
+};
+
+#[derive(Deserialize, Serialize, FromRow)]
+pub struct AccessTokenData {
+    pub id:           Uuid,
+    pub name:         String,
+    pub token_prefix: String,
+    pub scopes:       Vec<String>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub expires_at:   Option<DateTime<Utc>>,
+    pub created_at:   DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateTokenRequest {
+    pub name:            String,
+    pub scopes:          Vec<String>,
+    pub expires_in_days: Option<u32>,
+}
+
+/// Longest lifetime a token can be given, about ten years.
+const MAX_TOKEN_DAYS: u32 = 3650;
+
+#[post("")]
+pub async fn create_token(
+    app_data: web::Data<AppState>,
+    user_data: web::ReqData<UserData>,
+    data_json: web::Json<CreateTokenRequest>,
+) -> actix_web::Result<impl Responder> {
+    let CreateTokenRequest { name, mut scopes, expires_in_days } = data_json.into_inner();
+
+    if name.trim().is_empty() || scopes.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "Missing required fields" })));
+    }
+    if let Some(scope) = scopes.iter().find(|s| !GRANTABLE_SCOPES.contains(&s.as_str())) {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Unknown scope {}", scope)
+        })));
+    }
+    scopes.sort();
+    scopes.dedup();
+
+    let token = format!("{}{}", PAT_PREFIX, generate_token());
+    let token_prefix = token[..PAT_PREFIX.len() + 8].to_string();
+    let expires_at = match expires_in_days {
+        None => None,
+        Some(days) => {
+            let expires_at = Some(days)
+                .filter(|days| (1..=MAX_TOKEN_DAYS).contains(days))
+                .and_then(|days| Utc::now().checked_add_signed(ChronoDuration::days(days.into())));
+            let Some(expires_at) = expires_at else {
+                return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+                    "error": format!("expires_in_days must be between 1 and {MAX_TOKEN_DAYS}")
+                })));
+            };
+            Some(expires_at)
+        }
+    };
+
+    let record = sqlx::query_as!(
+        AccessTokenData,
+        r#"
+        INSERT INTO personal_access_tokens (user_id, name, token_hash, token_prefix, scopes, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, name, token_prefix, scopes, last_used_at, expires_at, created_at
+        "#,
+        user_data.id,
+        name.trim(),
+        hash_token(&token),
+        token_prefix,
+        &scopes,
+        expires_at
+    )
+    .fetch_one(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    // The plain token is only ever returned here.
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "token": token,
+        "record": record,
+    })))
+}
+
+#[get("")]
+pub async fn list_tokens(
+    app_data: web::Data<AppState>,
+    user_data: web::ReqData<UserData>,
+) -> actix_web::Result<impl Responder> {
+    let tokens = sqlx::query_as!(
+        AccessTokenData,
+        r#"
+        SELECT id, name, token_prefix, scopes, last_used_at, expires_at, created_at
+        FROM personal_access_tokens
+        WHERE user_id = $1
+          AND revoked = FALSE
+        ORDER BY created_at DESC
+        "#,
+        user_data.id
+    )
+    .fetch_all(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "tokens": tokens })))
+}
+
+#[delete("/{tokenId}")]
+pub async fn revoke_token(
+    app_data: web::Data<AppState>,
+    path: web::Path<Uuid>,
+    user_data: web::ReqData<UserData>,
+) -> actix_web::Result<impl Responder> {
+    let token_id = path.into_inner();
+
+    let rec = sqlx::query!(
+        r#"
+        UPDATE personal_access_tokens
+           SET revoked = TRUE
+         WHERE id      = $1
+           AND user_id = $2
+           AND revoked = FALSE
+        RETURNING id
+        "#,
+        token_id,
+        user_data.id
+    )
+    .fetch_optional(&app_data.db)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    if rec.is_some() {
+        Ok(HttpResponse::Ok().json(serde_json::json!({ "error": null })))
+    } else {
+        Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("No token found with id {}", token_id)
+        })))
+    }
+}
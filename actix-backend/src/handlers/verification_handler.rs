@@ -0,0 +1,143 @@
+use actix_web::{post, web, HttpResponse, Responder};
+use chrono::{Duration as ChronoDuration, Utc};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    mailer::Mail,
+    models::UserData,
+    utils::{generate_token, hash_token},
+    AppState,
+};
+
+/// Issues a fresh verification token for `user_id` and mails the link to `email`.
+/// Any earlier unused tokens for the user stop working.
+pub async fn send_verification_email(
+    app_state: &AppState,
+    user_id: Uuid,
+    email: &str,
+) -> Result<(), String> {
+    let token = generate_token();
+    let expires_at = Utc::now() + ChronoDuration::hours(24);
+
+    sqlx::query!(
+        r#"
+        WITH cleared AS (
+            DELETE FROM email_verification_tokens
+             WHERE user_id = $1
+        )
+        INSERT INTO email_verification_tokens (user_id, token_hash, expires_at)
+        VALUES ($1, $2, $3)
+        "#,
+        user_id,
+        hash_token(&token),
+        expires_at
+    )
+    .execute(&app_state.db)
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let mail = Mail {
+        to: email.to_string(),
+        subject: "Verify your Principium email".to_string(),
+        body: format!(
+            "Confirm your email address by opening the link below:\n\n{}/verify-email?token={}\n\nThe link expires in 24 hours.",
+            app_state.app_url, token
+        ),
+    };
+
+    app_state.mailer.send(&mail).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[post("")]
+pub async fn verify_email(
+    app_state: web::Data<AppState>,
+    verify_json: web::Json<VerifyEmailRequest>,
+) -> impl Responder {
+    let req = verify_json.into_inner();
+    if req.token.is_empty() {
+        return HttpResponse::BadRequest().json(serde_json::json!({ "error": "Missing required fields" }));
+    }
+
+    let res = sqlx::query!(
+        r#"
+        WITH used AS (
+            UPDATE email_verification_tokens
+               SET used_at = CURRENT_TIMESTAMP
+             WHERE token_hash = $1
+               AND used_at IS NULL
+               AND expires_at > CURRENT_TIMESTAMP
+            RETURNING user_id
+        )
+        UPDATE users
+           SET email_verified = TRUE
+         WHERE id IN (SELECT user_id FROM used)
+        RETURNING id
+        "#,
+        hash_token(&req.token)
+    )
+    .fetch_optional(&app_state.db)
+    .await;
+
+    match res {
+        Ok(Some(_)) => HttpResponse::Ok().json(serde_json::json!({ "error": null })),
+        Ok(None) => HttpResponse::BadRequest()
+            .json(serde_json::json!({ "error": "Invalid or expired verification token" })),
+        Err(_) => HttpResponse::InternalServerError()
+            .json(serde_json::json!({ "error": "Server error" })),
+    }
+}
+
+#[post("/resend")]
+pub async fn resend_verification(
+    app_state: web::Data<AppState>,
+    user_data: web::ReqData<UserData>,
+) -> impl Responder {
+    let row = match sqlx::query!(
+        r#"
+        SELECT
+          u.email,
+          COALESCE(u.email_verified, FALSE) AS "email_verified!",
+          EXISTS (
+            SELECT 1
+              FROM email_verification_tokens t
+             WHERE t.user_id = u.id
+               AND t.created_at > CURRENT_TIMESTAMP - INTERVAL '1 minute'
+          ) AS "recently_sent!"
+        FROM users u
+        WHERE u.id = $1
+        "#,
+        user_data.id
+    )
+    .fetch_one(&app_state.db)
+    .await
+    {
+        Ok(r) => r,
+        Err(_) => {
+            return HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": "DB error fetching user data" }));
+        }
+    };
+
+    if row.email_verified {
+        return HttpResponse::Conflict().json(serde_json::json!({ "error": "Email already verified" }));
+    }
+    if row.recently_sent {
+        return HttpResponse::TooManyRequests()
+            .json(serde_json::json!({ "error": "Verification email sent recently, try again later" }));
+    }
+
+    match send_verification_email(&app_state, user_data.id, &row.email).await {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({ "error": null })),
+        Err(e) => {
+            log::error!("Failed to send verification email: {}", e);
+            HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": "Failed to send verification email" }))
+        }
+    }
+}
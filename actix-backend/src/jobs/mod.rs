@@ -0,0 +1,2 @@
+mod trending;
+pub use trending::spawn_trending_refresh;
@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+
+use crate::models::TrendingWindow;
+
+/// How fast a star's weight fades. Same exponent Hacker News uses for its front page.
+const GRAVITY: f64 = 1.8;
+
+/// Arbitrary advisory lock key so only one server instance rebuilds scores at a time.
+const REFRESH_LOCK_KEY: i64 = 0x7472_656e_6469_6e67;
+
+const DEFAULT_REFRESH_SECONDS: u64 = 300;
+
+/// Rebuilds `snippet_trending` for every window.
+///
+/// Each star inside the window adds `1 / (age + 2) ^ GRAVITY`, where age is in
+/// hours for the day window and stretched proportionally for longer ones (7
+/// hours per unit for a week, 30 for a month), so every window decays on the
+/// same curve relative to its length. Returns `false` when another instance
+/// held the lock and nothing was done.
+pub async fn refresh_trending(db: &PgPool) -> Result<bool, sqlx::Error> {
+    let mut tx = db.begin().await?;
+
+    let locked = sqlx::query_scalar!(
+        r#"SELECT pg_try_advisory_xact_lock($1) AS "locked!""#,
+        REFRESH_LOCK_KEY,
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if !locked {
+        return Ok(false);
+    }
+
+    sqlx::query!("DELETE FROM snippets_extension.snippet_trending")
+        .execute(&mut *tx)
+        .await?;
+
+    for window in TrendingWindow::ALL {
+        sqlx::query!(
+            r#"
+            INSERT INTO snippets_extension.snippet_trending (snippet_id, time_window, score, recent_stars)
+            SELECT
+              ss.snippet_id,
+              $1,
+              SUM(power(EXTRACT(EPOCH FROM CURRENT_TIMESTAMP - ss.starred_at)::FLOAT8 / 3600.0 / $3::FLOAT8 + 2.0, -$4::FLOAT8)),
+              COUNT(*)
+            FROM snippets_extension.snippet_stars ss
+            JOIN snippets_extension.snippets s
+              ON s.id = ss.snippet_id
+            WHERE s.visibility = 'public'
+              AND ss.starred_at > CURRENT_TIMESTAMP - make_interval(hours => $2)
+            GROUP BY ss.snippet_id
+            "#,
+            window as TrendingWindow,
+            window.hours(),
+            window.hours() as f64 / 24.0,
+            GRAVITY,
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Refreshes scores on startup and then every `TRENDING_REFRESH_SECONDS` (default 300).
+pub fn spawn_trending_refresh(db: PgPool) {
+    let every = std::env::var("TRENDING_REFRESH_SECONDS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|&secs| secs > 0)
+        .unwrap_or(DEFAULT_REFRESH_SECONDS);
+
+    actix_web::rt::spawn(async move {
+        let mut ticker = actix_web::rt::time::interval(Duration::from_secs(every));
+        loop {
+            ticker.tick().await;
+            if let Err(e) = refresh_trending(&db).await {
+                log::error!("Failed to refresh trending scores: {e}");
+            }
+        }
+    });
+}
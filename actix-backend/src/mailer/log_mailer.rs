@@ -0,0 +1,35 @@
+use std::path::PathBuf;
+
+use futures_util::future::BoxFuture;
+use uuid::Uuid;
+
+use super::{Mail, Mailer};
+
+/// Local development mailer: logs every message and, when an outbox
+/// directory is configured, also writes it there as a text file.
+pub struct LogMailer {
+    outbox: Option<PathBuf>,
+}
+
+impl LogMailer {
+    pub fn new(outbox: Option<PathBuf>) -> Self {
+        Self { outbox }
+    }
+}
+
+impl Mailer for LogMailer {
+    fn send<'a>(&'a self, mail: &'a Mail) -> BoxFuture<'a, Result<(), String>> {
+        Box::pin(async move {
+            log::info!("Mail to {} | {}\n{}", mail.to, mail.subject, mail.body);
+
+            if let Some(dir) = &self.outbox {
+                std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+                let path = dir.join(format!("{}-{}.txt", chrono::Utc::now().timestamp(), Uuid::new_v4()));
+                let contents = format!("To: {}\nSubject: {}\n\n{}\n", mail.to, mail.subject, mail.body);
+                std::fs::write(path, contents).map_err(|e| e.to_string())?;
+            }
+
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,34 @@
+use futures_util::future::BoxFuture;
+
+mod log_mailer;
+pub use log_mailer::LogMailer;
+
+mod smtp_mailer;
+pub use smtp_mailer::SmtpMailer;
+
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+pub trait Mailer: Send + Sync {
+    fn send<'a>(&'a self, mail: &'a Mail) -> BoxFuture<'a, Result<(), String>>;
+}
+
+/// Picks a backend from `MAILER` (`smtp` or `log`). There is no default: a
+/// deployment that forgot to configure SMTP would otherwise quietly write
+/// password reset links to disk instead of sending them.
+pub fn from_env() -> Box<dyn Mailer> {
+    let from = std::env::var("MAIL_FROM").unwrap_or_else(|_| "Principium <no-reply@localhost>".to_string());
+
+    match std::env::var("MAILER").as_deref() {
+        Ok("smtp") => {
+            let url = std::env::var("SMTP_URL").expect("SMTP_URL must be set when MAILER=smtp");
+            Box::new(SmtpMailer::new(&url, &from).expect("Invalid SMTP_URL"))
+        }
+        Ok("log") => Box::new(LogMailer::new(std::env::var("MAIL_OUTBOX_DIR").ok().map(Into::into))),
+        _ => panic!("MAILER must be set to smtp (with SMTP_URL) or log (to write mail to MAIL_OUTBOX_DIR in development)"),
+    }
+}
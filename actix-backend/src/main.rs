@@ -2,19 +2,35 @@ use actix_cors::Cors;
 use actix_web::{middleware::Logger, web::{self, Data}, App, HttpServer};
 use sqlx::{postgres::PgPoolOptions, Pool, Postgres};
 
-use crate::middleware::jwt_middleware::VerifyJWT;
+use crate::{
+    billing::BillingProvider,
+    mailer::Mailer,
+    middleware::{jwt_middleware::VerifyJWT, rate_limit_middleware::RateLimit},
+    rate_limit::RateLimitStore,
+    utils::JwtKeys,
+};
 
+mod billing;
 mod handlers;
+mod jobs;
+mod mailer;
 mod models;
 mod utils;
 
 mod middleware;
+mod rate_limit;
 mod routes;
+mod snippet_formats;
 
 pub struct AppState {
     db: Pool<Postgres>,
-    jwt_access_secret: String,
+    jwt_keys: JwtKeys,
     jwt_refresh_secret: String,
+    mailer: Box<dyn Mailer>,
+    rate_limit_store: Box<dyn RateLimitStore>,
+    billing: Option<Box<dyn BillingProvider>>,
+    stripe_webhook_secret: Option<String>,
+    app_url: String,
 }
 
 #[actix_web::main]
@@ -40,16 +56,24 @@ async fn main () -> std::io::Result<()> {
     let port = std::env::var("PORT").unwrap().parse::<u16>().unwrap();
     let host = std::env::var("HOST").unwrap();
 
-    let jwt_access_secret = std::env::var("JWT_ACCESS_SECRET").unwrap();
     let jwt_refresh_secret = std::env::var("JWT_REFRESH_SECRET").unwrap();
+    let app_url = std::env::var("APP_URL").unwrap_or_else(|_| "http://localhost:3000".to_string());
 
     let app_data = Data::new(AppState {
         db: pool.clone(),
-        jwt_access_secret: jwt_access_secret.clone(),
+        jwt_keys: JwtKeys::from_env(),
         jwt_refresh_secret: jwt_refresh_secret.clone(),
+        mailer: mailer::from_env(),
+        rate_limit_store: rate_limit::from_env(pool.clone()),
+        billing: billing::from_env(pool.clone()),
+        stripe_webhook_secret: std::env::var("STRIPE_WEBHOOK_SECRET").ok(),
+        app_url,
     });
 
+    jobs::spawn_trending_refresh(pool.clone());
+
     let jwt_middleware = VerifyJWT::new(app_data.clone());
+    let rate_limit = RateLimit::new(app_data.clone());
 
     HttpServer::new(move || {
         let cors = Cors::default()
@@ -63,10 +87,12 @@ async fn main () -> std::io::Result<()> {
             .app_data(app_data.clone()) 
             .wrap(Logger::default())
             .wrap(cors)
+            .service(handlers::jwks_handler::jwks)
             .service(
                 web::scope("/api")
-                    .configure(|cfg| routes::auth_routes::config(cfg))
-                    .configure(|cfg| routes::snippet_routes::config(cfg, jwt_middleware.clone()))
+                    .configure(|cfg| routes::auth_routes::config(cfg, jwt_middleware.clone()))
+                    .configure(|cfg| routes::billing_routes::config(cfg, jwt_middleware.clone()))
+                    .configure(|cfg| routes::snippet_routes::config(cfg, jwt_middleware.clone(), rate_limit.clone()))
             ) 
     })
     .bind((host, port))? 
@@ -1,19 +1,35 @@
 use std::{ future::{ready, Ready}, rc::Rc};
 
-use actix_web::{dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform}, error::ErrorUnauthorized, web, Error, HttpMessage};
+use actix_web::{dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform}, error::{ErrorForbidden, ErrorUnauthorized}, web, Error, HttpMessage};
 use futures_util::future::LocalBoxFuture;
-use jsonwebtoken::{decode, DecodingKey, Validation};
 
-use crate::{models::{Claims, SubscriptionData}, AppState};
+use sqlx::{Pool, Postgres};
+
+use crate::{models::{Claims, SubscriptionData, UserData, PAT_PREFIX}, utils::hash_token, AppState};
 
 #[derive(Clone)]
 pub struct VerifyJWT {
     app_data: web::Data<AppState>,
+    require_verified_email: bool,
+    required_scope: Option<&'static str>,
 }
 
 impl VerifyJWT {
     pub fn new(app_data: web::Data<AppState>) -> Self {
-        Self { app_data }
+        Self { app_data, require_verified_email: false, required_scope: None }
+    }
+
+    /// Rejects users who haven't confirmed their email address yet.
+    pub fn require_verified_email(mut self) -> Self {
+        self.require_verified_email = true;
+        self
+    }
+
+    /// Lets personal access tokens through when they carry `scope`.
+    /// Routes without a scope only accept access JWTs.
+    pub fn require_scope(mut self, scope: &'static str) -> Self {
+        self.required_scope = Some(scope);
+        self
     }
 }
 
@@ -33,6 +49,8 @@ where
         ready(Ok(VerifyJWTMiddleware {
             service: Rc::new(service),
             app_data: self.app_data.clone(),
+            require_verified_email: self.require_verified_email,
+            required_scope: self.required_scope,
         }))
     }
 }
@@ -40,6 +58,8 @@ where
 pub struct VerifyJWTMiddleware<S> {
     service: Rc<S>,
     app_data: web::Data<AppState>,
+    require_verified_email: bool,
+    required_scope: Option<&'static str>,
 }
 
 impl<S, B> Service<ServiceRequest> for VerifyJWTMiddleware<S>
@@ -58,44 +78,56 @@ where
         let auth_header = req.headers()
             .get("authorization")
             .and_then(|h| h.to_str().ok())
-            .and_then(|s| {
-                if s.starts_with("Bearer ") {
-                    Some(s[7..].to_string())
-                } else {
-                    None
-                }
-            });
+            .and_then(|s| s.strip_prefix("Bearer "))
+            .map(str::to_string);
 
         if let Some(token) = auth_header {
-            match decode::<Claims>(
-                &token, 
-                &DecodingKey::from_secret(self.app_data.jwt_access_secret.as_bytes()), 
-                &Validation::default()
-            ) {
+            if token.starts_with(PAT_PREFIX) {
+                let pool = self.app_data.db.clone();
+                let svc = self.service.clone();
+                let require_verified_email = self.require_verified_email;
+                let required_scope = self.required_scope;
+
+                return Box::pin(async move {
+                    let scope = required_scope
+                        .ok_or_else(|| ErrorForbidden("Personal access tokens can't be used for this route"))?;
+
+                    let pat = sqlx::query!(
+                        r#"
+                        UPDATE personal_access_tokens
+                           SET last_used_at = CURRENT_TIMESTAMP
+                         WHERE token_hash = $1
+                           AND revoked    = FALSE
+                           AND (expires_at IS NULL OR expires_at > CURRENT_TIMESTAMP)
+                        RETURNING user_id, scopes
+                        "#,
+                        hash_token(&token)
+                    )
+                    .fetch_optional(&pool)
+                    .await
+                    .map_err(actix_web::error::ErrorInternalServerError)?
+                    .ok_or_else(|| ErrorUnauthorized("Invalid or expired token"))?;
+
+                    if !pat.scopes.iter().any(|s| s == scope) {
+                        return Err(ErrorForbidden(format!("Token is missing the {} scope", scope)));
+                    }
+
+                    attach_user(&pool, &req, UserData { id: pat.user_id }, require_verified_email).await?;
+
+                    let fut = svc.call(req);
+                    fut.await
+                });
+            }
+
+            match self.app_data.jwt_keys.verify::<Claims>(&token) {
                 Ok(data) => {
                     let pool = self.app_data.db.clone(); 
                     let user = data.claims.user.clone();
                     let svc = self.service.clone();
+                    let require_verified_email = self.require_verified_email;
 
                     Box::pin(async move { 
-                        req.extensions_mut().insert(user.clone());
-                        let sub = sqlx::query_as!(
-                            SubscriptionData,
-                            r#"
-                            SELECT 
-                                plan AS "plan: crate::models::SubscriptionPlan", 
-                                status AS "status: crate::models::SubscriptionStatus", 
-                                ends_at
-                            FROM subscriptions
-                            WHERE user_id = $1
-                            "#,
-                            user.id
-                        )
-                        .fetch_one(&pool)
-                        .await
-                        .map_err(actix_web::error::ErrorInternalServerError)?;
-
-                        req.extensions_mut().insert(sub);
+                        attach_user(&pool, &req, user, require_verified_email).await?;
                         
                         let fut = svc.call(req);
                         fut.await 
@@ -113,4 +145,47 @@ where
             })
         }
     }
-}
\ No newline at end of file
+}
+
+/// Stores the authenticated user and their subscription on the request.
+async fn attach_user(
+    pool: &Pool<Postgres>,
+    req: &ServiceRequest,
+    user: UserData,
+    require_verified_email: bool,
+) -> Result<(), Error> {
+    req.extensions_mut().insert(user.clone());
+    let sub = sqlx::query_as!(
+        SubscriptionData,
+        r#"
+        SELECT 
+            plan AS "plan: crate::models::SubscriptionPlan", 
+            status AS "status: crate::models::SubscriptionStatus", 
+            ends_at
+        FROM subscriptions
+        WHERE user_id = $1
+        "#,
+        user.id
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(actix_web::error::ErrorInternalServerError)?;
+
+    req.extensions_mut().insert(sub);
+
+    if require_verified_email {
+        let verified = sqlx::query_scalar!(
+            r#"SELECT COALESCE(email_verified, FALSE) AS "verified!" FROM users WHERE id = $1"#,
+            user.id
+        )
+        .fetch_one(pool)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+
+        if !verified {
+            return Err(ErrorForbidden("Email address not verified"));
+        }
+    }
+
+    Ok(())
+}
@@ -1 +1,2 @@
-pub mod jwt_middleware;
\ No newline at end of file
+pub mod jwt_middleware;
+pub mod rate_limit_middleware;
\ No newline at end of file
@@ -0,0 +1,117 @@
+use std::{ future::{ready, Ready}, rc::Rc};
+
+use actix_web::{dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform}, error::InternalError, http::header::{HeaderName, HeaderValue}, web, Error, HttpMessage, HttpResponse};
+use chrono::Utc;
+use futures_util::future::LocalBoxFuture;
+
+use crate::{models::{SubscriptionData, SubscriptionPlan, UserData}, rate_limit::{RateLimitDecision, RateLimitPolicy}, AppState};
+
+const ANONYMOUS_LIMIT: RateLimitPolicy = RateLimitPolicy::per_minute(30);
+const FREE_LIMIT: RateLimitPolicy = RateLimitPolicy::per_minute(60);
+const PRO_LIMIT: RateLimitPolicy = RateLimitPolicy::per_minute(600);
+
+/// Token-bucket rate limiting. Requests that went through `VerifyJWT` are
+/// limited per user according to their plan; everything else per IP.
+/// Must be wrapped *inside* `VerifyJWT` so the user is already known.
+#[derive(Clone)]
+pub struct RateLimit {
+    app_data: web::Data<AppState>,
+}
+
+impl RateLimit {
+    pub fn new(app_data: web::Data<AppState>) -> Self {
+        Self { app_data }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            app_data: self.app_data.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    app_data: web::Data<AppState>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where 
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static ,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let (key, policy) = {
+            let extensions = req.extensions();
+            match (extensions.get::<UserData>(), extensions.get::<SubscriptionData>()) {
+                (Some(user), Some(sub)) => {
+                    let policy = match sub.effective_plan(Utc::now()) {
+                        SubscriptionPlan::Free => FREE_LIMIT,
+                        SubscriptionPlan::Pro => PRO_LIMIT,
+                    };
+                    (format!("user:{}", user.id), policy)
+                }
+                _ => {
+                    let ip = req.connection_info().realip_remote_addr().unwrap_or("unknown").to_string();
+                    (format!("ip:{}", ip), ANONYMOUS_LIMIT)
+                }
+            }
+        };
+
+        let app_data = self.app_data.clone();
+        let svc = self.service.clone();
+
+        Box::pin(async move {
+            let decision = app_data.rate_limit_store
+                .take(&key, policy)
+                .await
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+
+            if !decision.allowed {
+                let mut res = HttpResponse::TooManyRequests();
+                for (name, value) in rate_limit_headers(&decision) {
+                    res.insert_header((name, value));
+                }
+                res.insert_header(("Retry-After", decision.retry_after.to_string()));
+                let res = res.json(serde_json::json!({ "error": "Rate limit exceeded" }));
+                return Err(InternalError::from_response("Rate limit exceeded", res).into());
+            }
+
+            let mut res = svc.call(req).await?;
+            for (name, value) in rate_limit_headers(&decision) {
+                res.headers_mut().insert(name, value);
+            }
+            Ok(res)
+        })
+    }
+}
+
+/// `RateLimit-*` headers from the IETF rate limit fields draft.
+fn rate_limit_headers(decision: &RateLimitDecision) -> [(HeaderName, HeaderValue); 3] {
+    [
+        (HeaderName::from_static("ratelimit-limit"), HeaderValue::from(decision.limit)),
+        (HeaderName::from_static("ratelimit-remaining"), HeaderValue::from(decision.remaining)),
+        (HeaderName::from_static("ratelimit-reset"), HeaderValue::from(decision.reset)),
+    ]
+}
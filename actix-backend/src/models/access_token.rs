@@ -0,0 +1,9 @@
+/// Personal access tokens look like `pat_<64 hex chars>` so they can be told
+/// apart from access JWTs in the `Authorization` header.
+pub const PAT_PREFIX: &str = "pat_";
+
+pub const SCOPE_SNIPPETS_READ: &str = "snippets:read";
+pub const SCOPE_SNIPPETS_WRITE: &str = "snippets:write";
+
+/// Scopes a user may put on a personal access token.
+pub const GRANTABLE_SCOPES: &[&str] = &[SCOPE_SNIPPETS_READ, SCOPE_SNIPPETS_WRITE];
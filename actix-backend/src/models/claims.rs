@@ -5,6 +5,7 @@ use uuid::Uuid;
 pub struct Claims {
     pub user: UserData,
     pub exp: usize,
+    pub jti: Uuid,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
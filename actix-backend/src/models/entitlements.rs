@@ -0,0 +1,199 @@
+use std::{fmt, future::{ready, Ready}};
+
+use actix_web::{dev::Payload, http::StatusCode, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+use chrono::Utc;
+use serde::Serialize;
+
+use super::{SubscriptionData, SubscriptionPlan};
+
+/// What a plan allows. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entitlements {
+    pub plan: SubscriptionPlan,
+    pub max_snippets: Option<i64>,
+    pub max_snippet_bytes: usize,
+    pub max_tags_per_snippet: usize,
+    /// Older revisions beyond this many are pruned on save.
+    pub max_revisions: Option<i32>,
+}
+
+const FREE: Entitlements = Entitlements {
+    plan: SubscriptionPlan::Free,
+    max_snippets: Some(50),
+    max_snippet_bytes: 16 * 1024,
+    max_tags_per_snippet: 5,
+    max_revisions: Some(10),
+};
+
+const PRO: Entitlements = Entitlements {
+    plan: SubscriptionPlan::Pro,
+    max_snippets: None,
+    max_snippet_bytes: 256 * 1024,
+    max_tags_per_snippet: 20,
+    max_revisions: None,
+};
+
+impl Entitlements {
+    pub fn for_plan(plan: SubscriptionPlan) -> Self {
+        match plan {
+            SubscriptionPlan::Free => FREE,
+            SubscriptionPlan::Pro => PRO,
+        }
+    }
+
+    pub fn for_subscription(subscription: &SubscriptionData) -> Self {
+        Self::for_plan(subscription.effective_plan(Utc::now()))
+    }
+
+    /// Checks whether the user may own one more snippet, given how many they own now.
+    pub fn check_snippet_count(&self, owned: i64) -> Result<(), QuotaExceeded> {
+        self.check_new_snippets(owned, 1)
+    }
+
+    /// Checks whether the user may add `adding` snippets at once, e.g. from an import.
+    pub fn check_new_snippets(&self, owned: i64, adding: i64) -> Result<(), QuotaExceeded> {
+        match self.max_snippets {
+            Some(max) if owned + adding > max => Err(self.exceeded(QuotaKind::Snippets, max as usize, |e| {
+                e.max_snippets.is_none_or(|m| m >= owned + adding)
+            })),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_snippet_content(&self, code: &str, tags: &[String]) -> Result<(), QuotaExceeded> {
+        if code.len() > self.max_snippet_bytes {
+            let max = self.max_snippet_bytes;
+            return Err(self.exceeded(QuotaKind::SnippetSize, max, |e| e.max_snippet_bytes >= code.len()));
+        }
+        if tags.len() > self.max_tags_per_snippet {
+            let max = self.max_tags_per_snippet;
+            return Err(self.exceeded(QuotaKind::TagsPerSnippet, max, |e| e.max_tags_per_snippet >= tags.len()));
+        }
+        Ok(())
+    }
+
+    /// `upgrade_fits` tells whether Pro would allow the request, which decides between 402 and 403.
+    fn exceeded(&self, kind: QuotaKind, limit: usize, upgrade_fits: impl Fn(&Entitlements) -> bool) -> QuotaExceeded {
+        QuotaExceeded {
+            kind,
+            limit,
+            plan: self.plan,
+            upgrade_available: self.plan != SubscriptionPlan::Pro && upgrade_fits(&PRO),
+        }
+    }
+}
+
+/// Built from the `SubscriptionData` that `VerifyJWT` puts on the request,
+/// so only usable on routes wrapped by it.
+impl FromRequest for Entitlements {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            req.extensions()
+                .get::<SubscriptionData>()
+                .map(Entitlements::for_subscription)
+                .ok_or_else(|| actix_web::error::ErrorInternalServerError("Missing subscription data")),
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaKind {
+    Snippets,
+    SnippetSize,
+    TagsPerSnippet,
+}
+
+#[derive(Debug)]
+pub struct QuotaExceeded {
+    pub kind: QuotaKind,
+    pub limit: usize,
+    pub plan: SubscriptionPlan,
+    pub upgrade_available: bool,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            QuotaKind::Snippets => write!(f, "Snippet limit of {} reached", self.limit),
+            QuotaKind::SnippetSize => write!(f, "Snippet is larger than {} bytes", self.limit),
+            QuotaKind::TagsPerSnippet => write!(f, "Snippets can have at most {} tags", self.limit),
+        }
+    }
+}
+
+/// `402` when upgrading would lift the limit, `403` otherwise.
+impl ResponseError for QuotaExceeded {
+    fn status_code(&self) -> StatusCode {
+        if self.upgrade_available {
+            StatusCode::PAYMENT_REQUIRED
+        } else {
+            StatusCode::FORBIDDEN
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "error": self.to_string(),
+            "quota": self.kind,
+            "limit": self.limit,
+            "plan": self.plan,
+            "upgradeAvailable": self.upgrade_available,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("tag{i}")).collect()
+    }
+
+    #[test]
+    fn free_snippet_count_stops_at_the_limit() {
+        assert!(FREE.check_snippet_count(49).is_ok());
+        let err = FREE.check_snippet_count(50).unwrap_err();
+        assert_eq!((err.limit, err.upgrade_available), (50, true));
+        assert_eq!(err.status_code(), StatusCode::PAYMENT_REQUIRED);
+    }
+
+    #[test]
+    fn imports_count_every_new_snippet() {
+        assert!(FREE.check_new_snippets(40, 10).is_ok());
+        assert!(FREE.check_new_snippets(40, 11).is_err());
+        assert!(FREE.check_new_snippets(0, 51).is_err());
+        assert!(PRO.check_new_snippets(1_000_000, 500).is_ok());
+    }
+
+    #[test]
+    fn content_limits_are_inclusive() {
+        let at_limit = "x".repeat(FREE.max_snippet_bytes);
+        assert!(FREE.check_snippet_content(&at_limit, &tags(5)).is_ok());
+
+        let err = FREE.check_snippet_content(&format!("{at_limit}x"), &[]).unwrap_err();
+        assert!(matches!(err.kind, QuotaKind::SnippetSize));
+        assert!(err.upgrade_available);
+
+        let err = FREE.check_snippet_content("", &tags(6)).unwrap_err();
+        assert!(matches!(err.kind, QuotaKind::TagsPerSnippet));
+        assert_eq!(err.limit, 5);
+    }
+
+    #[test]
+    fn forbidden_when_upgrading_would_not_help() {
+        // too big even for Pro
+        let err = FREE.check_snippet_content(&"x".repeat(PRO.max_snippet_bytes + 1), &[]).unwrap_err();
+        assert!(!err.upgrade_available);
+        assert_eq!(err.status_code(), StatusCode::FORBIDDEN);
+
+        let err = PRO.check_snippet_content("", &tags(21)).unwrap_err();
+        assert_eq!((err.limit, err.upgrade_available), (20, false));
+        assert!(PRO.check_snippet_content("", &tags(20)).is_ok());
+    }
+}
@@ -1,5 +1,17 @@
+mod access_token;
+pub use access_token::{GRANTABLE_SCOPES, PAT_PREFIX, SCOPE_SNIPPETS_READ, SCOPE_SNIPPETS_WRITE};
+
 mod claims;
 pub use claims::{Claims,UserData};
 
+mod entitlements;
+pub use entitlements::Entitlements;
+
+mod snippet_visibility;
+pub use snippet_visibility::SnippetVisibility;
+
 mod subscription;
-pub use subscription::{SubscriptionData, SubscriptionPlan, SubscriptionStatus};
\ No newline at end of file
+pub use subscription::{SubscriptionData, SubscriptionPlan, SubscriptionStatus};
+
+mod trending_window;
+pub use trending_window::TrendingWindow;
\ No newline at end of file
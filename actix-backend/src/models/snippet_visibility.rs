@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+/// Who can read a snippet. `Unlisted` snippets can be opened by anyone who has the
+/// ID (share links) but never appear in listings or search.
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[sqlx(type_name = "snippet_visibility", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum SnippetVisibility {
+    #[default]
+    Private,
+    Unlisted,
+    Public,
+}
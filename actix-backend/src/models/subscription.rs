@@ -1,14 +1,15 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-#[derive(sqlx::Type, Serialize, Deserialize, Debug )]
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[sqlx(type_name = "subscription_plan", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
 pub enum SubscriptionPlan {
     Free,
     Pro
 }
 
-#[derive(sqlx::Type, Serialize, Deserialize, Debug)]
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 #[sqlx(type_name = "subscription_status", rename_all = "snake_case")]
 pub enum SubscriptionStatus {
     Active,
@@ -23,4 +24,75 @@ pub struct SubscriptionData {
     pub plan: SubscriptionPlan,
     pub status: SubscriptionStatus,
     pub ends_at: Option<DateTime<Utc>>,
-}
\ No newline at end of file
+}
+
+impl SubscriptionData {
+    /// The plan the user is actually entitled to right now.
+    ///
+    /// - `PastDue` keeps Pro while the payment is being retried.
+    /// - `Canceled` keeps Pro until the paid period (`ends_at`) is over.
+    /// - `Unpaid` and `Incomplete` fall back to Free.
+    pub fn effective_plan(&self, now: DateTime<Utc>) -> SubscriptionPlan {
+        match (self.plan, self.status) {
+            (SubscriptionPlan::Free, _) => SubscriptionPlan::Free,
+            (SubscriptionPlan::Pro, SubscriptionStatus::Active | SubscriptionStatus::PastDue) => SubscriptionPlan::Pro,
+            (SubscriptionPlan::Pro, SubscriptionStatus::Canceled) => match self.ends_at {
+                Some(ends_at) if ends_at > now => SubscriptionPlan::Pro,
+                _ => SubscriptionPlan::Free,
+            },
+            (SubscriptionPlan::Pro, SubscriptionStatus::Unpaid | SubscriptionStatus::Incomplete) => SubscriptionPlan::Free,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn plan(plan: SubscriptionPlan, status: SubscriptionStatus, ends_at: Option<DateTime<Utc>>) -> SubscriptionPlan {
+        SubscriptionData { plan, status, ends_at }.effective_plan(Utc::now())
+    }
+
+    #[test]
+    fn free_stays_free_whatever_the_status() {
+        let statuses = [
+            SubscriptionStatus::Active,
+            SubscriptionStatus::Canceled,
+            SubscriptionStatus::Incomplete,
+            SubscriptionStatus::PastDue,
+            SubscriptionStatus::Unpaid,
+        ];
+        for status in statuses {
+            assert_eq!(plan(SubscriptionPlan::Free, status, None), SubscriptionPlan::Free, "{status:?}");
+        }
+    }
+
+    #[test]
+    fn pro_is_kept_while_active_or_retrying_payment() {
+        assert_eq!(plan(SubscriptionPlan::Pro, SubscriptionStatus::Active, None), SubscriptionPlan::Pro);
+        assert_eq!(plan(SubscriptionPlan::Pro, SubscriptionStatus::PastDue, None), SubscriptionPlan::Pro);
+    }
+
+    #[test]
+    fn pro_is_lost_when_unpaid_or_incomplete() {
+        let later = Some(Utc::now() + Duration::days(10));
+        assert_eq!(plan(SubscriptionPlan::Pro, SubscriptionStatus::Unpaid, later), SubscriptionPlan::Free);
+        assert_eq!(plan(SubscriptionPlan::Pro, SubscriptionStatus::Incomplete, later), SubscriptionPlan::Free);
+    }
+
+    #[test]
+    fn canceled_pro_lasts_until_the_paid_period_ends() {
+        let now = Utc::now();
+        let subscription = |ends_at| SubscriptionData {
+            plan: SubscriptionPlan::Pro,
+            status: SubscriptionStatus::Canceled,
+            ends_at,
+        };
+        let ending = subscription(Some(now + Duration::hours(1)));
+        assert_eq!(ending.effective_plan(now), SubscriptionPlan::Pro);
+        assert_eq!(ending.effective_plan(now + Duration::hours(1)), SubscriptionPlan::Free);
+        assert_eq!(subscription(None).effective_plan(now), SubscriptionPlan::Free);
+    }
+}
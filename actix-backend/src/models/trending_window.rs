@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+/// How far back the trending job looks at stars.
+#[derive(sqlx::Type, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[sqlx(type_name = "trending_window", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum TrendingWindow {
+    Day,
+    #[default]
+    Week,
+    Month,
+}
+
+impl TrendingWindow {
+    pub const ALL: [TrendingWindow; 3] = [TrendingWindow::Day, TrendingWindow::Week, TrendingWindow::Month];
+
+    pub fn hours(self) -> i32 {
+        match self {
+            TrendingWindow::Day   => 24,
+            TrendingWindow::Week  => 24 * 7,
+            TrendingWindow::Month => 24 * 30,
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::{collections::HashMap, sync::Mutex, time::{Duration, Instant}};
+
+use futures_util::future::BoxFuture;
+
+use super::{RateLimitDecision, RateLimitPolicy, RateLimitStore};
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+    /// How long this bucket takes to refill, which depends on who uses it.
+    period: Duration,
+}
+
+/// Buckets kept in process memory. Fine for a single instance.
+pub struct MemoryStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self { buckets: Mutex::new(HashMap::new()) }
+    }
+}
+
+impl RateLimitStore for MemoryStore {
+    fn take<'a>(&'a self, key: &'a str, policy: RateLimitPolicy) -> BoxFuture<'a, Result<RateLimitDecision, String>> {
+        Box::pin(async move {
+            let now = Instant::now();
+            let capacity = policy.capacity as f64;
+            let mut buckets = self.buckets.lock().map_err(|e| e.to_string())?;
+
+            // Drop buckets that have refilled completely; they hold no information.
+            if buckets.len() > 10_000 {
+                buckets.retain(|_, b| now.duration_since(b.updated_at) < b.period);
+            }
+
+            let bucket = buckets
+                .entry(key.to_string())
+                .or_insert(Bucket { tokens: capacity, updated_at: now, period: policy.period });
+
+            let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * policy.refill_per_second()).min(capacity);
+            bucket.updated_at = now;
+
+            let allowed = bucket.tokens >= 1.0;
+            if allowed {
+                bucket.tokens -= 1.0;
+            }
+
+            Ok(RateLimitDecision::from_tokens(allowed, bucket.tokens, policy))
+        })
+    }
+}
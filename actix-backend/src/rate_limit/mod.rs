@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use futures_util::future::BoxFuture;
+use sqlx::{Pool, Postgres};
+
+mod memory_store;
+pub use memory_store::MemoryStore;
+
+mod postgres_store;
+pub use postgres_store::PostgresStore;
+
+/// Token bucket size and how long an empty bucket takes to fill back up.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitPolicy {
+    pub capacity: u32,
+    pub period: Duration,
+}
+
+impl RateLimitPolicy {
+    pub const fn per_minute(capacity: u32) -> Self {
+        Self { capacity, period: Duration::from_secs(60) }
+    }
+
+    pub const fn per_hour(capacity: u32) -> Self {
+        Self { capacity, period: Duration::from_secs(60 * 60) }
+    }
+
+    pub fn refill_per_second(&self) -> f64 {
+        self.capacity as f64 / self.period.as_secs_f64()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: u32,
+    pub remaining: u32,
+    /// Seconds until the bucket is full again.
+    pub reset: u64,
+    /// Seconds until the next request would be let through.
+    pub retry_after: u64,
+}
+
+impl RateLimitDecision {
+    /// Builds the decision from the bucket's level after this request was counted.
+    pub fn from_tokens(allowed: bool, tokens: f64, policy: RateLimitPolicy) -> Self {
+        let rate = policy.refill_per_second();
+        Self {
+            allowed,
+            limit: policy.capacity,
+            remaining: tokens.max(0.0).floor() as u32,
+            reset: ((policy.capacity as f64 - tokens) / rate).max(0.0).ceil() as u64,
+            retry_after: ((1.0 - tokens) / rate).max(0.0).ceil() as u64,
+        }
+    }
+}
+
+pub trait RateLimitStore: Send + Sync {
+    /// Takes one token from the bucket at `key`, refilling it first.
+    fn take<'a>(&'a self, key: &'a str, policy: RateLimitPolicy) -> BoxFuture<'a, Result<RateLimitDecision, String>>;
+}
+
+/// Picks a backend from `RATE_LIMIT_STORE` (`memory` or `postgres`, defaults to `memory`).
+/// Use `postgres` when running more than one instance so they share buckets.
+pub fn from_env(pool: Pool<Postgres>) -> Box<dyn RateLimitStore> {
+    match std::env::var("RATE_LIMIT_STORE").as_deref() {
+        Ok("postgres") => Box::new(PostgresStore::new(pool)),
+        _ => Box::new(MemoryStore::new()),
+    }
+}
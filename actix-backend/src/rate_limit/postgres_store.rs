@@ -0,0 +1,47 @@
+use futures_util::future::BoxFuture;
+use sqlx::{Pool, Postgres};
+
+use super::{RateLimitDecision, RateLimitPolicy, RateLimitStore};
+
+/// Buckets stored in `rate_limit_buckets`, shared by every instance.
+pub struct PostgresStore {
+    db: Pool<Postgres>,
+}
+
+impl PostgresStore {
+    pub fn new(db: Pool<Postgres>) -> Self {
+        Self { db }
+    }
+}
+
+impl RateLimitStore for PostgresStore {
+    fn take<'a>(&'a self, key: &'a str, policy: RateLimitPolicy) -> BoxFuture<'a, Result<RateLimitDecision, String>> {
+        Box::pin(async move {
+            let capacity = policy.capacity as f64;
+
+            // Refill and take in a single statement so concurrent requests can't both
+            // spend the last token.
+            let row = sqlx::query!(
+                r#"
+                INSERT INTO rate_limit_buckets AS b (key, tokens, allowed, updated_at)
+                VALUES ($1, $2::FLOAT8 - 1, TRUE, CURRENT_TIMESTAMP)
+                ON CONFLICT (key) DO UPDATE
+                   SET allowed = LEAST($2, b.tokens + EXTRACT(EPOCH FROM CURRENT_TIMESTAMP - b.updated_at)::FLOAT8 * $3) >= 1,
+                       tokens  = LEAST($2, b.tokens + EXTRACT(EPOCH FROM CURRENT_TIMESTAMP - b.updated_at)::FLOAT8 * $3)
+                                 - CASE WHEN LEAST($2, b.tokens + EXTRACT(EPOCH FROM CURRENT_TIMESTAMP - b.updated_at)::FLOAT8 * $3) >= 1
+                                        THEN 1 ELSE 0 END,
+                       updated_at = CURRENT_TIMESTAMP
+                RETURNING tokens, allowed
+                "#,
+                key,
+                capacity,
+                policy.refill_per_second()
+            )
+            .fetch_one(&self.db)
+            .await
+            .map_err(|e| e.to_string())?;
+
+            Ok(RateLimitDecision::from_tokens(row.allowed, row.tokens, policy))
+        })
+    }
+}
@@ -1,13 +1,64 @@
 use actix_web::web;
 
-use crate::handlers::auth_handler;
+use crate::{handlers::{auth_handler, device_handler, mfa_handler, password_handler, session_handler, token_handler, verification_handler}, middleware::jwt_middleware::VerifyJWT};
 
-pub fn config(config: &mut web::ServiceConfig) {
+pub fn config(config: &mut web::ServiceConfig, jwt_middleware: VerifyJWT) {
     config.service(
         web::scope("/v1/auth")
         .service(auth_handler::register)
         .service(auth_handler::login)
         .service(auth_handler::logout)
         .service(auth_handler::refresh)
+        .service(password_handler::forgot_password)
+        .service(password_handler::reset_password)
+        .service(
+            web::scope("/change-password")
+            .service(password_handler::change_password)
+            .wrap(jwt_middleware.clone())
+        )
+        .service(
+            web::scope("/verify-email")
+            .service(verification_handler::verify_email)
+            .service(
+                web::scope("")
+                .service(verification_handler::resend_verification)
+                .wrap(jwt_middleware.clone())
+            )
+        )
+        .service(
+            web::scope("/device")
+            .service(device_handler::device_code)
+            .service(device_handler::device_token)
+            .service(
+                web::scope("")
+                .service(device_handler::approve_device)
+                .wrap(jwt_middleware.clone())
+            )
+        )
+        .service(
+            web::scope("/mfa")
+            .service(mfa_handler::verify_mfa)
+            .service(
+                web::scope("")
+                .service(mfa_handler::enroll)
+                .service(mfa_handler::confirm)
+                .service(mfa_handler::disable)
+                .wrap(jwt_middleware.clone())
+            )
+        )
+        .service(
+            web::scope("/tokens")
+            .service(token_handler::create_token)
+            .service(token_handler::list_tokens)
+            .service(token_handler::revoke_token)
+            .wrap(jwt_middleware.clone())
+        )
+        .service(
+            web::scope("/sessions")
+            .service(session_handler::list_sessions)
+            .service(session_handler::revoke_other_sessions)
+            .service(session_handler::revoke_session)
+            .wrap(jwt_middleware)
+        )
     );
 }
\ No newline at end of file
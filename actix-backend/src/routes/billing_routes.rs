@@ -0,0 +1,19 @@
+use actix_web::web;
+
+use crate::{billing, handlers::billing_handler, middleware::jwt_middleware::VerifyJWT};
+
+pub fn config(config: &mut web::ServiceConfig, jwt_middleware: VerifyJWT) {
+    let mut account = web::scope("")
+        .service(billing_handler::checkout)
+        .service(billing_handler::portal);
+
+    if billing::uses_fake_provider() {
+        account = account.service(billing_handler::fake_event);
+    }
+
+    config.service(
+        web::scope("/v1/billing")
+        .service(billing_handler::webhook)
+        .service(account.wrap(jwt_middleware))
+    );
+}
\ No newline at end of file
@@ -1,2 +1,3 @@
 pub mod auth_routes;
+pub mod billing_routes;
 pub mod snippet_routes;
\ No newline at end of file
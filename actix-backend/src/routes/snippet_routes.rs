@@ -1,25 +1,87 @@
 use actix_web::web;
 
-use crate::{handlers::snippet_handler, middleware::jwt_middleware::VerifyJWT};
+use crate::{
+    handlers::{
+        collection_handler, snippet_fork_handler, snippet_handler, snippet_import_export_handler,
+        snippet_revision_handler, snippet_trending_handler,
+    },
+    middleware::{jwt_middleware::VerifyJWT, rate_limit_middleware::RateLimit},
+    models::{SCOPE_SNIPPETS_READ, SCOPE_SNIPPETS_WRITE},
+};
 
-pub fn config(config: &mut web::ServiceConfig, jwt_middleware: VerifyJWT) {
+// `RateLimit` is wrapped before `VerifyJWT` so it runs after it and can see the user's plan.
+pub fn config(config: &mut web::ServiceConfig, jwt_middleware: VerifyJWT, rate_limit: RateLimit) {
     config.service(
         web::scope("/v1/users")
-        .service(snippet_handler::create_snippet)
-        .service(snippet_handler::get_user_snippet)
-        .service(snippet_handler::get_user_snippets)
-        .service(snippet_handler::update_snippet)
-        .service(snippet_handler::delete_snippet)
-        .wrap(jwt_middleware.clone())
+        .service(
+            web::scope("/snippets")
+            .service(snippet_handler::create_snippet)
+            .service(snippet_handler::update_snippet)
+            .service(snippet_handler::delete_snippet)
+            .service(snippet_revision_handler::revert_snippet)
+            .service(snippet_import_export_handler::import_snippets)
+            // snippet files are read as raw bytes, whose default cap is 256 KB
+            .app_data(web::PayloadConfig::new(4 * 1024 * 1024))
+            .wrap(rate_limit.clone())
+            .wrap(jwt_middleware.clone().require_verified_email().require_scope(SCOPE_SNIPPETS_WRITE))
+        )
+        .service(
+            web::scope("/collections")
+            .service(collection_handler::create_collection)
+            // before the `/{collectionId}` routes so "order" isn't taken for an ID
+            .service(collection_handler::reorder_collections)
+            .service(collection_handler::update_collection)
+            .service(collection_handler::delete_collection)
+            .service(collection_handler::add_snippet)
+            .service(collection_handler::reorder_snippets)
+            .service(collection_handler::remove_snippet)
+            .wrap(rate_limit.clone())
+            .wrap(jwt_middleware.clone().require_verified_email().require_scope(SCOPE_SNIPPETS_WRITE))
+        )
+        .service(
+            web::scope("")
+            // before `get_user_snippet` so "export" isn't taken for an ID
+            .service(snippet_import_export_handler::export_snippets)
+            .service(snippet_handler::get_user_snippet)
+            .service(snippet_handler::get_user_snippets)
+            .service(snippet_revision_handler::list_revisions)
+            // before `get_revision`, whose `{revision}` would otherwise try to match "diff"
+            .service(snippet_revision_handler::diff_revisions)
+            .service(snippet_revision_handler::get_revision)
+            .service(collection_handler::get_user_collections)
+            .wrap(rate_limit.clone())
+            .wrap(jwt_middleware.clone().require_scope(SCOPE_SNIPPETS_READ))
+        )
     ).service(
         web::scope("/v1/snippets")
-        .service(snippet_handler::get_page_snippets)
-        .service(snippet_handler::get_snippets_by_ids)
         .service(
-            web::scope("")
+            web::scope("/{snippetId}/star")
             .service(snippet_handler::star_snippet)
             .service(snippet_handler::unstar_snippet)
-            .wrap(jwt_middleware)
+            .wrap(rate_limit.clone())
+            .wrap(jwt_middleware.clone().require_scope(SCOPE_SNIPPETS_WRITE))
+        )
+        .service(
+            web::scope("/{snippetId}/fork")
+            .service(snippet_fork_handler::fork_snippet)
+            .wrap(rate_limit.clone())
+            .wrap(jwt_middleware.require_verified_email().require_scope(SCOPE_SNIPPETS_WRITE))
         )
+        .service(
+            web::scope("")
+            .service(snippet_handler::get_page_snippets)
+            .service(snippet_handler::get_snippets_by_ids)
+            .service(snippet_trending_handler::get_trending_snippets)
+            .service(snippet_handler::validate_snippet)
+            // after `/batch` and `/trending` so they aren't taken for an ID
+            .service(snippet_handler::get_snippet)
+            .service(snippet_fork_handler::list_forks)
+            .wrap(rate_limit.clone())
+        )
+    ).service(
+        web::scope("/v1/collections")
+        .service(collection_handler::get_page_collections)
+        .service(collection_handler::get_collection)
+        .wrap(rate_limit)
     );
 }
\ No newline at end of file
@@ -0,0 +1,513 @@
+use super::{
+    placeholders::{parse_snippet, plain_text, to_snippet_string, Node},
+    slug, unique_names,
+    xml::{elements, escape_attribute},
+    Export, ExportFile, ExportWarning, PortableSnippet, PLAINTEXT,
+};
+
+/// Template group, and the name of the exported file, shown in the IDE settings.
+const GROUP: &str = "Principium";
+
+/// VS Code language IDs and the JetBrains live template contexts for them.
+const CONTEXTS: &[(&str, &str)] = &[
+    ("css", "CSS"),
+    ("go", "GO"),
+    ("html", "HTML"),
+    ("java", "JAVA_CODE"),
+    ("javascript", "JAVA_SCRIPT"),
+    ("kotlin", "KOTLIN"),
+    ("php", "PHP"),
+    ("python", "Python"),
+    ("rust", "RUST_FILE"),
+    ("shellscript", "SHELL_SCRIPT"),
+    ("sql", "SQL"),
+    ("typescript", "TypeScript"),
+    ("xml", "XML"),
+];
+
+/// Context for file types no other context covers.
+const OTHER_CONTEXT: &str = "OTHER";
+
+/// Snippet variables with a JetBrains expression that computes the same value.
+const EXPRESSIONS: &[(&str, &str)] = &[
+    ("CLIPBOARD", "clipboard()"),
+    ("CURRENT_DATE", "date(\"dd\")"),
+    ("CURRENT_DAY_NAME", "date(\"EEEE\")"),
+    ("CURRENT_DAY_NAME_SHORT", "date(\"EEE\")"),
+    ("CURRENT_HOUR", "time(\"HH\")"),
+    ("CURRENT_MINUTE", "time(\"mm\")"),
+    ("CURRENT_MONTH", "date(\"MM\")"),
+    ("CURRENT_MONTH_NAME", "date(\"MMMM\")"),
+    ("CURRENT_MONTH_NAME_SHORT", "date(\"MMM\")"),
+    ("CURRENT_SECOND", "time(\"ss\")"),
+    ("CURRENT_YEAR", "date(\"yyyy\")"),
+    ("CURRENT_YEAR_SHORT", "date(\"yy\")"),
+    ("TM_FILENAME", "fileName()"),
+    ("TM_FILENAME_BASE", "fileNameWithoutExtension()"),
+    ("TM_FILEPATH", "filePath()"),
+    ("TM_LINE_NUMBER", "lineNumber()"),
+    ("UUID", "groovyScript(\"UUID.randomUUID().toString()\")"),
+];
+
+struct Variable {
+    name:          String,
+    expression:    String,
+    default_value: String,
+    /// Tabstop index for variables the cursor visits; `None` for computed ones.
+    stop:          Option<u32>,
+}
+
+/// Builds the template value and variable list for one snippet.
+struct Template<'a> {
+    snippet:   &'a PortableSnippet,
+    value:     String,
+    variables: Vec<Variable>,
+}
+
+impl Template<'_> {
+    fn push_nodes(&mut self, nodes: &[Node], warnings: &mut Vec<ExportWarning>) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => self.value.push_str(&text.replace('$', "$$")),
+                Node::Tabstop { index, transform } => {
+                    if transform.is_some() {
+                        warnings.push(self.warning(format!("the transform on ${index} is dropped")));
+                    }
+                    self.push_stop(*index, None, None);
+                }
+                Node::Placeholder { index, default } => {
+                    if default.iter().any(|n| !matches!(n, Node::Text(_))) {
+                        warnings.push(self.warning(format!(
+                            "placeholders nested in ${{{index}}} are flattened into its default text"
+                        )));
+                    }
+                    let default = plain_text(default);
+                    if *index == 0 {
+                        warnings.push(self.warning(
+                            "$END$ can't have a default; the text of ${0} is inserted before it".to_string(),
+                        ));
+                        self.value.push_str(&default.replace('$', "$$"));
+                    }
+                    self.push_stop(*index, None, Some(string_literal(&default)));
+                }
+                Node::Choice { index, options } => {
+                    let options: Vec<String> = options.iter().map(|o| string_literal(o)).collect();
+                    self.push_stop(*index, Some(format!("enum({})", options.join(", "))), None);
+                }
+                Node::Variable { name, default, transform } => {
+                    if name == "TM_SELECTED_TEXT" || name == "SELECTION" {
+                        self.value.push_str("$SELECTION$");
+                        continue;
+                    }
+                    let Some((_, expression)) = EXPRESSIONS.iter().find(|(n, _)| n == name) else {
+                        warnings.push(self.warning(format!(
+                            "JetBrains IDEs have no ${name} variable; its default is inserted instead"
+                        )));
+                        self.push_nodes(default.as_deref().unwrap_or_default(), warnings);
+                        continue;
+                    };
+                    if transform.is_some() {
+                        warnings.push(self.warning(format!("the transform on ${name} is dropped")));
+                    }
+                    self.value.push_str(&format!("${name}$"));
+                    if !self.variables.iter().any(|v| &v.name == name) {
+                        self.variables.push(Variable {
+                            name: name.clone(),
+                            expression: expression.to_string(),
+                            default_value: String::new(),
+                            stop: None,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes a tabstop reference, defining its variable on first use. Later
+    /// uses are mirrors and only fill in what the first left empty.
+    fn push_stop(&mut self, index: u32, expression: Option<String>, default_value: Option<String>) {
+        if index == 0 {
+            self.value.push_str("$END$");
+            return;
+        }
+        let name = format!("VAR{index}");
+        self.value.push_str(&format!("${name}$"));
+        match self.variables.iter_mut().find(|v| v.name == name) {
+            Some(variable) => {
+                if variable.expression.is_empty() {
+                    variable.expression = expression.unwrap_or_default();
+                }
+                if variable.default_value.is_empty() {
+                    variable.default_value = default_value.unwrap_or_default();
+                }
+            }
+            None => self.variables.push(Variable {
+                name,
+                expression: expression.unwrap_or_default(),
+                default_value: default_value.unwrap_or_default(),
+                stop: Some(index),
+            }),
+        }
+    }
+
+    fn warning(&self, message: String) -> ExportWarning {
+        ExportWarning::new(self.snippet, message)
+    }
+}
+
+/// A JetBrains template expression string literal.
+fn string_literal(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The text of a string literal, or `None` for any other expression.
+fn parse_string_literal(expression: &str) -> Option<String> {
+    let inner = expression.trim().strip_prefix('"')?.strip_suffix('"')?;
+    let mut text = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.extend(chars.next()),
+            '"' => return None,
+            c => text.push(c),
+        }
+    }
+    Some(text)
+}
+
+/// The options of an `enum("a", "b")` expression.
+fn parse_enum(expression: &str) -> Option<Vec<String>> {
+    let args = expression.trim().strip_prefix("enum(")?.strip_suffix(')')?;
+    let mut options = Vec::new();
+    let mut rest = args.trim();
+    while !rest.is_empty() {
+        let end = rest
+            .char_indices()
+            .skip(1)
+            .scan(false, |escaped, (i, c)| {
+                let closes = c == '"' && !*escaped;
+                *escaped = c == '\\' && !*escaped;
+                Some((i, closes))
+            })
+            .find(|(_, closes)| *closes)?
+            .0;
+        options.push(parse_string_literal(&rest[..=end])?);
+        rest = rest[end + 1..].trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+    }
+    Some(options)
+}
+
+/// A single live template set file holding every snippet. Templates are
+/// named by their prefix, or the title when they have none.
+pub fn to_jetbrains_templates(snippets: &[PortableSnippet]) -> Export {
+    let mut export = Export::default();
+    let abbreviations: Vec<String> = snippets
+        .iter()
+        .map(|snippet| {
+            snippet
+                .single_prefix("JetBrains live templates", &mut export.warnings)
+                .map_or_else(|| slug(&snippet.title), str::to_string)
+        })
+        .collect();
+    let abbreviations = unique_names(abbreviations.iter().map(String::as_str), |base, n| format!("{base}{n}"));
+
+    let mut file = format!("<templateSet group=\"{GROUP}\">\n");
+    for (snippet, abbreviation) in snippets.iter().zip(abbreviations) {
+        let mut template = Template { snippet, value: String::new(), variables: Vec::new() };
+        template.push_nodes(&parse_snippet(&snippet.code), &mut export.warnings);
+
+        // the cursor visits variables in the order they're listed
+        let Template { value, mut variables, .. } = template;
+        variables.sort_by_key(|v| v.stop.unwrap_or(u32::MAX));
+
+        file.push_str(&format!(
+            "  <template name=\"{}\" value=\"{}\" description=\"{}\" toReformat=\"false\" toShortenFQNames=\"true\">\n",
+            escape_attribute(&abbreviation),
+            escape_attribute(&value),
+            escape_attribute(&snippet.title),
+        ));
+        for variable in &variables {
+            file.push_str(&format!(
+                "    <variable name=\"{}\" expression=\"{}\" defaultValue=\"{}\" alwaysStopAt=\"{}\" />\n",
+                variable.name,
+                escape_attribute(&variable.expression),
+                escape_attribute(&variable.default_value),
+                variable.stop.is_some(),
+            ));
+        }
+
+        let mut contexts = Vec::new();
+        for language in snippet.languages() {
+            match CONTEXTS.iter().find(|(id, _)| *id == language) {
+                Some((_, context)) => contexts.push(*context),
+                None => export.warnings.push(ExportWarning::new(
+                    snippet,
+                    format!("JetBrains IDEs have no context for {language}; it is available in other file types"),
+                )),
+            }
+        }
+        if contexts.is_empty() {
+            contexts.push(OTHER_CONTEXT);
+        }
+        contexts.dedup();
+        file.push_str("    <context>\n");
+        for context in contexts {
+            file.push_str(&format!("      <option name=\"{context}\" value=\"true\" />\n"));
+        }
+        file.push_str("    </context>\n  </template>\n");
+    }
+    file.push_str("</templateSet>\n");
+
+    export.files.push(ExportFile { name: format!("{GROUP}.xml"), content: file });
+    export
+}
+
+/// Reads a live template set file, turning template variables back into
+/// tabstops, choices and snippet variables.
+pub fn parse_jetbrains_templates(text: &str) -> Result<Vec<PortableSnippet>, String> {
+    let elements = elements(text)?;
+    if elements.first().is_none_or(|e| e.name != "templateSet") {
+        return Err("Not a JetBrains template set: expected a <templateSet> element".to_string());
+    }
+
+    let mut snippets = Vec::new();
+    let mut i = 0;
+    while i < elements.len() {
+        let template = &elements[i];
+        i += 1;
+        if template.name != "template" {
+            continue;
+        }
+        // everything up to the next template belongs to this one
+        let children: Vec<_> = elements[i..].iter().take_while(|e| e.depth > template.depth).collect();
+        i += children.len();
+
+        let attribute = |name: &str| template.attributes.get(name).cloned().unwrap_or_default();
+        let abbreviation = attribute("name");
+        let description = attribute("description");
+
+        // computed variables become snippet variables; the rest are tabstops numbered in order
+        let mut stops = 0;
+        let mut variables: Vec<(String, Node)> = children
+            .iter()
+            .filter(|e| e.name == "variable")
+            .map(|e| {
+                let name = e.attributes.get("name").cloned().unwrap_or_default();
+                let expression = e.attributes.get("expression").map(String::as_str).unwrap_or_default();
+                let default_value = e.attributes.get("defaultValue").map(String::as_str).unwrap_or_default();
+                if let Some((variable, _)) = EXPRESSIONS.iter().find(|(_, e)| *e == expression.trim()) {
+                    return (name, Node::Variable { name: variable.to_string(), default: None, transform: None });
+                }
+                stops += 1;
+                let node = match (parse_enum(expression), parse_string_literal(default_value)) {
+                    (Some(options), _) => Node::Choice { index: stops, options },
+                    (None, Some(default)) if !default.is_empty() => {
+                        Node::Placeholder { index: stops, default: vec![Node::Text(default)] }
+                    }
+                    _ => Node::Tabstop { index: stops, transform: None },
+                };
+                (name, node)
+            })
+            .collect();
+
+        let code = to_snippet_string(&template_nodes(&attribute("value"), &mut variables, stops));
+
+        let languages: Vec<&str> = children
+            .iter()
+            .filter(|e| e.name == "option" && e.attributes.get("value").is_some_and(|v| v == "true"))
+            .filter_map(|e| CONTEXTS.iter().find(|(_, c)| Some(*c) == e.attributes.get("name").map(String::as_str)))
+            .map(|(id, _)| *id)
+            .collect();
+        let (language, scope) = match languages.as_slice() {
+            [] => (PLAINTEXT.to_string(), None),
+            [language] => (language.to_string(), None),
+            [first, ..] => (first.to_string(), Some(languages.join(","))),
+        };
+
+        snippets.push(PortableSnippet {
+            title: if description.trim().is_empty() { abbreviation.clone() } else { description },
+            description: None,
+            code,
+            language,
+            prefixes: Some(abbreviation).filter(|a| !a.is_empty()).into_iter().collect(),
+            scope,
+        });
+    }
+    Ok(snippets)
+}
+
+/// Splits a template value into snippet nodes. The first use of a tabstop
+/// variable carries its default; later uses mirror it. `stops` is the number
+/// of tabstops `variables` already holds.
+fn template_nodes(value: &str, variables: &mut Vec<(String, Node)>, mut stops: u32) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    let mut text = String::new();
+    let mut seen = Vec::new();
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('$') else {
+            text.push('$');
+            break;
+        };
+        let name = &rest[..end];
+        rest = &rest[end + 1..];
+
+        let node = match name {
+            "" => {
+                text.push('$');
+                continue;
+            }
+            "END" => Node::Tabstop { index: 0, transform: None },
+            "SELECTION" => Node::Variable { name: "TM_SELECTED_TEXT".to_string(), default: None, transform: None },
+            name => match variables.iter().find(|(n, _)| n == name) {
+                Some((_, node)) => match node {
+                    Node::Placeholder { index, .. } | Node::Choice { index, .. } if seen.contains(index) => {
+                        Node::Tabstop { index: *index, transform: None }
+                    }
+                    node => node.clone(),
+                },
+                // undefined variables are still input fields
+                None => {
+                    stops += 1;
+                    let node = Node::Tabstop { index: stops, transform: None };
+                    variables.push((name.to_string(), node.clone()));
+                    node
+                }
+            },
+        };
+        if let Node::Placeholder { index, .. } | Node::Choice { index, .. } | Node::Tabstop { index, .. } = &node {
+            seen.push(*index);
+        }
+        if !text.is_empty() {
+            nodes.push(Node::Text(std::mem::take(&mut text)));
+        }
+        nodes.push(node);
+    }
+    text.push_str(rest);
+    if !text.is_empty() {
+        nodes.push(Node::Text(text));
+    }
+    nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snippet(code: &str) -> PortableSnippet {
+        PortableSnippet {
+            title: "Function".to_string(),
+            description: None,
+            code: code.to_string(),
+            language: "rust".to_string(),
+            prefixes: vec!["fn".to_string()],
+            scope: None,
+        }
+    }
+
+    /// `(name, expression, default, stop)` of each template variable.
+    type Variables = Vec<(String, String, String, Option<u32>)>;
+
+    /// The template value, its variables and the warning messages.
+    fn convert(code: &str) -> (String, Variables, Vec<String>) {
+        let snippet = snippet(code);
+        let mut warnings = Vec::new();
+        let mut template = Template { snippet: &snippet, value: String::new(), variables: Vec::new() };
+        template.push_nodes(&parse_snippet(code), &mut warnings);
+        let variables = template
+            .variables
+            .into_iter()
+            .map(|v| (v.name, v.expression, v.default_value, v.stop))
+            .collect();
+        (template.value, variables, warnings.into_iter().map(|w| w.message).collect())
+    }
+
+    #[test]
+    fn dollar_signs_are_doubled() {
+        let (value, variables, warnings) = convert(r"cost: \$5 and $$");
+        assert_eq!(value, "cost: $$5 and $$$$");
+        assert!(variables.is_empty());
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn tabstops_become_variables_and_final_stop_becomes_end() {
+        let (value, variables, warnings) = convert("fn ${1:name}($2) {\n\t$0\n} $1");
+        assert_eq!(value, "fn $VAR1$($VAR2$) {\n\t$END$\n} $VAR1$");
+        assert_eq!(
+            variables,
+            vec![
+                ("VAR1".to_string(), String::new(), "\"name\"".to_string(), Some(1)),
+                ("VAR2".to_string(), String::new(), String::new(), Some(2)),
+            ]
+        );
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn choices_and_variables_become_expressions() {
+        let (value, variables, warnings) = convert("${1|a,\"b\"|} $TM_FILENAME $TM_SELECTED_TEXT");
+        assert_eq!(value, "$VAR1$ $TM_FILENAME$ $SELECTION$");
+        assert_eq!(variables[0].1, r#"enum("a", "\"b\"")"#);
+        assert_eq!(variables[1], ("TM_FILENAME".to_string(), "fileName()".to_string(), String::new(), None));
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn inexpressible_constructs_warn() {
+        let (value, _, warnings) = convert("${1/(.*)/${1:/upcase}/}");
+        assert_eq!(value, "$VAR1$");
+        assert_eq!(warnings, vec!["the transform on $1 is dropped"]);
+
+        let (value, variables, warnings) = convert("${1:a ${2:b} c}");
+        assert_eq!(value, "$VAR1$");
+        assert_eq!(variables[0].2, "\"a b c\"");
+        assert_eq!(warnings, vec!["placeholders nested in ${1} are flattened into its default text"]);
+
+        let (value, _, warnings) = convert("${RANDOM:42}");
+        assert_eq!(value, "42");
+        assert_eq!(warnings, vec!["JetBrains IDEs have no $RANDOM variable; its default is inserted instead"]);
+
+        let (value, _, warnings) = convert("${0:done}");
+        assert_eq!(value, "done$END$");
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn export_round_trips() {
+        let snippets = [
+            snippet("fn ${1:name}(${2|a,b|}) {\n\t$0\n} // $TM_FILENAME & <$1>"),
+            PortableSnippet {
+                title: "Log".to_string(),
+                description: None,
+                code: "console.log(\"${1:value}\"); // costs \\$1".to_string(),
+                language: "javascript".to_string(),
+                prefixes: Vec::new(),
+                scope: Some("javascript,typescript".to_string()),
+            },
+        ];
+        let export = to_jetbrains_templates(&snippets);
+        assert!(export.warnings.is_empty());
+        assert_eq!(export.files.len(), 1);
+
+        let parsed = parse_jetbrains_templates(&export.files[0].content).unwrap();
+        assert_eq!(parsed.len(), 2);
+        for (original, parsed) in snippets.iter().zip(&parsed) {
+            assert_eq!(parsed.code, original.code);
+            assert_eq!(parsed.title, original.title);
+            assert_eq!(parsed.language, original.language);
+            assert_eq!(parsed.scope, original.scope);
+        }
+        assert_eq!(parsed[0].prefixes, vec!["fn"]);
+        assert_eq!(parsed[1].prefixes, vec!["log"]);
+    }
+
+    #[test]
+    fn rejects_other_documents() {
+        assert!(parse_jetbrains_templates("<snippet></snippet>").is_err());
+        assert!(parse_jetbrains_templates("").is_err());
+    }
+}
@@ -0,0 +1,117 @@
+/// Strips `//` and `/* */` comments and trailing commas, which VS Code allows
+/// in snippet files but `serde_json` doesn't.
+pub fn strip_jsonc(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut chars = input.chars().peekable();
+    let mut in_string = false;
+    // A comma that may turn out to be trailing is held back, along with the
+    // whitespace after it, until the next token shows whether to keep it.
+    let mut pending_comma = false;
+    let mut held = String::new();
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            if c == '\\' {
+                if let Some(escaped) = chars.next() {
+                    out.push(escaped);
+                }
+            } else if c == '"' {
+                in_string = false;
+            }
+            continue;
+        }
+
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.next_if(|&n| n != '\n').is_some() {}
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = '\0';
+                for n in chars.by_ref() {
+                    if prev == '*' && n == '/' {
+                        break;
+                    }
+                    prev = n;
+                }
+            }
+            ',' => {
+                if pending_comma {
+                    out.push(',');
+                }
+                out.push_str(&held);
+                held.clear();
+                pending_comma = true;
+            }
+            '}' | ']' => {
+                // The held comma was trailing; only the whitespace after it is kept.
+                pending_comma = false;
+                out.push_str(&held);
+                held.clear();
+                out.push(c);
+            }
+            c if c.is_whitespace() && pending_comma => held.push(c),
+            c if c.is_whitespace() => out.push(c),
+            c => {
+                if pending_comma {
+                    out.push(',');
+                    out.push_str(&held);
+                    held.clear();
+                    pending_comma = false;
+                }
+                in_string = c == '"';
+                out.push(c);
+            }
+        }
+    }
+    if pending_comma {
+        out.push(',');
+    }
+    out.push_str(&held);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> serde_json::Value {
+        serde_json::from_str(&strip_jsonc(input)).unwrap()
+    }
+
+    #[test]
+    fn trailing_commas_are_dropped() {
+        assert_eq!(strip_jsonc(r#"{"a": 1,}"#), r#"{"a": 1}"#);
+        assert_eq!(strip_jsonc("[1, 2 ,\n]"), "[1, 2 \n]");
+        assert_eq!(strip_jsonc(r#"{"a": [1,], "b": {"c": 2,},}"#), r#"{"a": [1], "b": {"c": 2}}"#);
+    }
+
+    #[test]
+    fn separating_commas_are_kept() {
+        assert_eq!(strip_jsonc("[1,\n  2, 3]"), "[1,\n  2, 3]");
+        assert_eq!(strip_jsonc("[1,,2]"), "[1,,2]");
+    }
+
+    #[test]
+    fn comments_are_stripped() {
+        let input = "{\n  // line\n  \"a\": 1, /* block, with a comma */\n  \"b\": 2 /* * */\n}";
+        assert_eq!(parse(input), serde_json::json!({"a": 1, "b": 2}));
+    }
+
+    #[test]
+    fn comment_after_a_trailing_comma() {
+        let input = "{\n  \"a\": [1, 2,], // trailing\n  \"b\": 3, /* also trailing */\n}";
+        assert_eq!(parse(input), serde_json::json!({"a": [1, 2], "b": 3}));
+    }
+
+    #[test]
+    fn strings_are_left_alone() {
+        let input = r#"{"url": "http://x.io/*y*/", "text": "a,]\" // not a comment,}",}"#;
+        assert_eq!(
+            parse(input),
+            serde_json::json!({"url": "http://x.io/*y*/", "text": "a,]\" // not a comment,}"})
+        );
+        assert_eq!(parse(r#"["back\\", 1,]"#), serde_json::json!(["back\\", 1]));
+    }
+}
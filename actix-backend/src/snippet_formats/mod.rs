@@ -0,0 +1,233 @@
+//! Conversions between stored snippets and editor snippet files.
+
+mod jetbrains;
+mod jsonc;
+pub mod placeholders;
+mod sublime;
+mod textmate;
+mod vscode;
+mod xml;
+
+use serde::{Deserialize, Serialize};
+
+pub use jetbrains::{parse_jetbrains_templates, to_jetbrains_templates};
+pub use sublime::{parse_sublime_snippet, to_sublime_snippets};
+pub use textmate::{parse_textmate_snippet, to_textmate_snippets};
+pub use vscode::{parse_vscode_snippets, to_vscode_snippets};
+
+/// The parts of a snippet that editor snippet files carry.
+#[derive(Debug, Clone)]
+pub struct PortableSnippet {
+    pub title:       String,
+    pub description: Option<String>,
+    pub code:        String,
+    pub language:    String,
+    pub prefixes:    Vec<String>,
+    pub scope:       Option<String>,
+}
+
+impl PortableSnippet {
+    /// Editor language IDs the snippet applies to; empty means every language.
+    fn languages(&self) -> Vec<&str> {
+        let languages: Vec<&str> = match self.scope.as_deref() {
+            Some(scope) => scope.split(',').map(str::trim).filter(|s| !s.is_empty()).collect(),
+            None => vec![self.language.as_str()],
+        };
+        languages.into_iter().filter(|&l| l != PLAINTEXT).collect()
+    }
+
+    /// Formats other than VS Code have a single trigger word.
+    fn single_prefix(&self, target: &str, warnings: &mut Vec<ExportWarning>) -> Option<&str> {
+        if self.prefixes.len() > 1 {
+            warnings.push(ExportWarning::new(
+                self,
+                format!("only one trigger fits in {target}; \"{}\" is used", self.prefixes[0]),
+            ));
+        }
+        self.prefixes.first().map(String::as_str)
+    }
+}
+
+const PLAINTEXT: &str = "plaintext";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    VsCode,
+    JetBrains,
+    Sublime,
+    TextMate,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportFile {
+    pub name:    String,
+    pub content: String,
+}
+
+/// Something in a snippet the target format can't express, and what was done instead.
+#[derive(Debug, Serialize)]
+pub struct ExportWarning {
+    pub snippet: String,
+    pub message: String,
+}
+
+impl ExportWarning {
+    fn new(snippet: &PortableSnippet, message: String) -> Self {
+        Self { snippet: snippet.title.clone(), message }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Export {
+    pub files:    Vec<ExportFile>,
+    pub warnings: Vec<ExportWarning>,
+}
+
+/// Turns names into unique ones, letting `suffix` rename the second and later repeats.
+fn unique_names<'a>(names: impl Iterator<Item = &'a str>, suffix: impl Fn(&str, usize) -> String) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    names
+        .map(|base| {
+            let mut name = base.to_string();
+            let mut n = 2;
+            while !seen.insert(name.clone()) {
+                name = suffix(base, n);
+                n += 1;
+            }
+            name
+        })
+        .collect()
+}
+
+/// File-name-safe form of a title: lowercase ASCII words joined by `-`.
+fn slug(title: &str) -> String {
+    let slug = title
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_ascii_lowercase)
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() { "snippet".to_string() } else { slug }
+}
+
+/// File names for one-file-per-snippet formats.
+fn file_names(snippets: &[PortableSnippet], extension: &str) -> Vec<String> {
+    let slugs: Vec<String> = snippets.iter().map(|s| slug(&s.title)).collect();
+    unique_names(slugs.iter().map(String::as_str), |base, n| format!("{base}-{n}"))
+        .into_iter()
+        .map(|name| format!("{name}.{extension}"))
+        .collect()
+}
+
+/// VS Code language IDs and the TextMate scopes Sublime Text and TextMate use for them.
+const TEXTMATE_SCOPES: &[(&str, &str)] = &[
+    ("c", "source.c"),
+    ("cpp", "source.c++"),
+    ("csharp", "source.cs"),
+    ("css", "source.css"),
+    ("go", "source.go"),
+    ("html", "text.html.basic"),
+    ("java", "source.java"),
+    ("javascript", "source.js"),
+    ("javascriptreact", "source.js.jsx"),
+    ("json", "source.json"),
+    ("kotlin", "source.kotlin"),
+    ("lua", "source.lua"),
+    ("markdown", "text.html.markdown"),
+    ("php", "source.php"),
+    ("python", "source.python"),
+    ("ruby", "source.ruby"),
+    ("rust", "source.rust"),
+    ("scss", "source.scss"),
+    ("shellscript", "source.shell"),
+    ("sql", "source.sql"),
+    ("swift", "source.swift"),
+    ("typescript", "source.ts"),
+    ("typescriptreact", "source.tsx"),
+    ("yaml", "source.yaml"),
+];
+
+fn textmate_scope(language: &str) -> String {
+    TEXTMATE_SCOPES
+        .iter()
+        .find(|(id, _)| *id == language)
+        .map(|(_, scope)| scope.to_string())
+        .unwrap_or_else(|| format!("source.{language}"))
+}
+
+/// `language` and `scope` for a TextMate selector such as `source.js, source.ts`.
+fn languages_from_textmate_scope(selector: &str) -> (String, Option<String>) {
+    let languages: Vec<String> = selector
+        .split(',')
+        .filter_map(|part| part.split_whitespace().next())
+        .map(|scope| {
+            TEXTMATE_SCOPES
+                .iter()
+                .find(|(_, s)| *s == scope)
+                .map(|(id, _)| id.to_string())
+                .unwrap_or_else(|| scope.trim_start_matches("source.").trim_start_matches("text.").to_string())
+        })
+        .collect();
+    match languages.as_slice() {
+        [] => (PLAINTEXT.to_string(), None),
+        [language] => (language.clone(), None),
+        [first, ..] => (first.clone(), Some(languages.join(","))),
+    }
+}
+
+/// Rewrites snippet syntax for a TextMate-style target, replacing what it can't express.
+struct TextMateTarget {
+    name:      &'static str,
+    choices:   bool,
+    variables: &'static [&'static str],
+}
+
+impl TextMateTarget {
+    fn adapt(&self, snippet: &PortableSnippet, warnings: &mut Vec<ExportWarning>) -> String {
+        let nodes = placeholders::parse_snippet(&snippet.code);
+        placeholders::to_snippet_string(&self.adapt_nodes(nodes, snippet, warnings))
+    }
+
+    fn adapt_nodes(
+        &self,
+        nodes: Vec<placeholders::Node>,
+        snippet: &PortableSnippet,
+        warnings: &mut Vec<ExportWarning>,
+    ) -> Vec<placeholders::Node> {
+        use placeholders::Node;
+
+        let mut adapted = Vec::with_capacity(nodes.len());
+        for node in nodes {
+            match node {
+                Node::Choice { index, options } if !self.choices => {
+                    warnings.push(ExportWarning::new(
+                        snippet,
+                        format!("{} has no choice lists; ${{{index}}} defaults to its first option", self.name),
+                    ));
+                    let first = options.into_iter().next().unwrap_or_default();
+                    adapted.push(Node::Placeholder { index, default: vec![Node::Text(first)] });
+                }
+                Node::Placeholder { index, default } => {
+                    let default = self.adapt_nodes(default, snippet, warnings);
+                    adapted.push(Node::Placeholder { index, default });
+                }
+                Node::Variable { name, default, transform } => {
+                    if self.variables.contains(&name.as_str()) {
+                        let default = default.map(|d| self.adapt_nodes(d, snippet, warnings));
+                        adapted.push(Node::Variable { name, default, transform });
+                    } else {
+                        warnings.push(ExportWarning::new(
+                            snippet,
+                            format!("{} has no ${name} variable; its default is inserted instead", self.name),
+                        ));
+                        adapted.extend(self.adapt_nodes(default.unwrap_or_default(), snippet, warnings));
+                    }
+                }
+                node => adapted.push(node),
+            }
+        }
+        adapted
+    }
+}
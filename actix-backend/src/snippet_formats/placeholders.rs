@@ -0,0 +1,811 @@
+//! The TextMate / VS Code snippet body grammar: tabstops, placeholders,
+//! choices, variables and regex transforms.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Text(String),
+    /// `$1`, `${1}` or `${1/regex/format/options}`. `$0` is the final cursor position.
+    Tabstop { index: u32, transform: Option<Transform> },
+    /// `${1:default}`; the default can itself hold placeholders.
+    Placeholder { index: u32, default: Vec<Node> },
+    /// `${1|one,two|}`.
+    Choice { index: u32, options: Vec<String> },
+    /// `$NAME`, `${NAME}`, `${NAME:default}` or `${NAME/regex/format/options}`.
+    Variable { name: String, default: Option<Vec<Node>>, transform: Option<Transform> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transform {
+    pub regex:   String,
+    pub format:  String,
+    pub options: String,
+}
+
+/// Variables VS Code resolves. Any other name is inserted as a placeholder
+/// holding the name itself.
+pub const KNOWN_VARIABLES: &[&str] = &[
+    "BLOCK_COMMENT_END",
+    "BLOCK_COMMENT_START",
+    "CLIPBOARD",
+    "CURRENT_DATE",
+    "CURRENT_DAY_NAME",
+    "CURRENT_DAY_NAME_SHORT",
+    "CURRENT_HOUR",
+    "CURRENT_MINUTE",
+    "CURRENT_MONTH",
+    "CURRENT_MONTH_NAME",
+    "CURRENT_MONTH_NAME_SHORT",
+    "CURRENT_SECOND",
+    "CURRENT_SECONDS_UNIX",
+    "CURRENT_TIMEZONE_OFFSET",
+    "CURRENT_YEAR",
+    "CURRENT_YEAR_SHORT",
+    "CURSOR_INDEX",
+    "CURSOR_NUMBER",
+    "LINE_COMMENT",
+    "RANDOM",
+    "RANDOM_HEX",
+    "RELATIVE_FILEPATH",
+    "SELECTION",
+    "TM_CURRENT_LINE",
+    "TM_CURRENT_WORD",
+    "TM_DIRECTORY",
+    "TM_DIRECTORY_BASE",
+    "TM_FILENAME",
+    "TM_FILENAME_BASE",
+    "TM_FILEPATH",
+    "TM_LINE_INDEX",
+    "TM_LINE_NUMBER",
+    "TM_SELECTED_TEXT",
+    "UUID",
+    "WORKSPACE_FOLDER",
+    "WORKSPACE_NAME",
+];
+
+/// Declared most serious first, so sorting puts errors ahead of warnings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Syntax that was clearly meant as a snippet construct but is broken.
+    Error,
+    /// Allowed, but editors may not read it the way it was meant, often
+    /// because it's shell or template syntax rather than a snippet construct.
+    Warning,
+}
+
+/// A problem in a snippet body. `line` and `column` count from 1, in characters.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line:     usize,
+    pub column:   usize,
+    pub message:  String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TabstopInfo {
+    pub index:       u32,
+    /// Default text of the first placeholder for this tabstop.
+    pub placeholder: Option<String>,
+    pub choices:     Option<Vec<String>>,
+    /// How many times the tabstop appears; the others mirror the first.
+    pub occurrences: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VariableInfo {
+    pub name:        String,
+    pub known:       bool,
+    pub default:     Option<String>,
+    pub occurrences: usize,
+}
+
+/// What a snippet body holds, for previews: its tabstops in the order the
+/// cursor visits them (`$0` last), its variables, and any diagnostics.
+#[derive(Debug, Serialize)]
+pub struct SnippetSyntax {
+    pub tabstops:    Vec<TabstopInfo>,
+    pub variables:   Vec<VariableInfo>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl SnippetSyntax {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|d| d.severity == Severity::Error)
+    }
+}
+
+/// Parses a snippet body. Anything that isn't valid snippet syntax is kept as
+/// literal text, the way editors treat it.
+pub fn parse_snippet(body: &str) -> Vec<Node> {
+    Parser::new(body).parse_nodes(false)
+}
+
+/// Parses a snippet body, reporting broken syntax and unknown variables.
+pub fn analyze(body: &str) -> SnippetSyntax {
+    let mut parser = Parser::new(body);
+    let nodes = parser.parse_nodes(false);
+
+    let mut outline = Outline::default();
+    outline.collect(&nodes);
+    let Outline { mut tabstops, variables, .. } = outline;
+    tabstops.sort_by_key(|t| if t.index == 0 { u32::MAX } else { t.index });
+
+    // offsets become line and column in one pass over the body
+    parser.diagnostics.sort_by_key(|(offset, ..)| *offset);
+    let mut diagnostics = Vec::with_capacity(parser.diagnostics.len());
+    let (mut line, mut column, mut pos) = (1, 1, 0);
+    for (offset, severity, message) in parser.diagnostics {
+        for &c in &parser.chars[pos..offset] {
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        pos = offset;
+        diagnostics.push(Diagnostic { severity, line, column, message });
+    }
+    // errors first, so capping the list never hides one
+    diagnostics.sort_by_key(|d| d.severity);
+    diagnostics.truncate(MAX_DIAGNOSTICS);
+
+    SnippetSyntax { tabstops, variables, diagnostics }
+}
+
+/// Tabstops and variables in first-seen order, with indexes to find them again.
+#[derive(Default)]
+struct Outline {
+    tabstops:       Vec<TabstopInfo>,
+    variables:      Vec<VariableInfo>,
+    tabstop_index:  HashMap<u32, usize>,
+    variable_index: HashMap<String, usize>,
+}
+
+impl Outline {
+    fn collect(&mut self, nodes: &[Node]) {
+        for node in nodes {
+            let (index, placeholder, choices) = match node {
+                Node::Text(_) => continue,
+                Node::Variable { name, default, .. } => {
+                    let text = default.as_deref().map(plain_text);
+                    match self.variable_index.get(name) {
+                        Some(&i) => {
+                            let variable = &mut self.variables[i];
+                            variable.occurrences += 1;
+                            variable.default = variable.default.take().or(text);
+                        }
+                        None => {
+                            self.variable_index.insert(name.clone(), self.variables.len());
+                            self.variables.push(VariableInfo {
+                                name: name.clone(),
+                                known: KNOWN_VARIABLES.contains(&name.as_str()),
+                                default: text,
+                                occurrences: 1,
+                            });
+                        }
+                    }
+                    if let Some(default) = default {
+                        self.collect(default);
+                    }
+                    continue;
+                }
+                Node::Tabstop { index, .. } => (*index, None, None),
+                Node::Placeholder { index, default } => {
+                    self.collect(default);
+                    (*index, Some(plain_text(default)), None)
+                }
+                Node::Choice { index, options } => (*index, None, Some(options.clone())),
+            };
+            match self.tabstop_index.get(&index) {
+                Some(&i) => {
+                    let tabstop = &mut self.tabstops[i];
+                    tabstop.occurrences += 1;
+                    if tabstop.placeholder.is_none() && tabstop.choices.is_none() {
+                        tabstop.placeholder = placeholder;
+                        tabstop.choices = choices;
+                    }
+                }
+                None => {
+                    self.tabstop_index.insert(index, self.tabstops.len());
+                    self.tabstops.push(TabstopInfo { index, placeholder, choices, occurrences: 1 });
+                }
+            }
+        }
+    }
+}
+
+/// Writes nodes back out in snippet syntax.
+pub fn to_snippet_string(nodes: &[Node]) -> String {
+    let mut out = String::new();
+    write_nodes(&mut out, nodes, false);
+    out
+}
+
+/// What the snippet expands to with every default accepted: placeholders
+/// become their defaults, choices their first option, variables their default.
+pub fn plain_text(nodes: &[Node]) -> String {
+    let mut out = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Tabstop { .. } => {}
+            Node::Placeholder { default, .. } => out.push_str(&plain_text(default)),
+            Node::Choice { options, .. } => out.push_str(options.first().map(String::as_str).unwrap_or_default()),
+            Node::Variable { default, .. } => out.push_str(&plain_text(default.as_deref().unwrap_or_default())),
+        }
+    }
+    out
+}
+
+const TRANSFORM_SHAPE: &str = "must have the form `/regex/format/options}`";
+
+/// Most diagnostics `analyze` returns.
+const MAX_DIAGNOSTICS: usize = 100;
+
+/// Placeholders nested deeper than this are kept as text, so hostile input
+/// can't exhaust the stack.
+const MAX_NESTING: usize = 32;
+
+struct Parser {
+    chars:       Vec<char>,
+    pos:         usize,
+    /// Placeholders and variable defaults currently open.
+    depth:       usize,
+    /// Whether nesting past `MAX_NESTING` has been reported.
+    too_deep:    bool,
+    /// Character offset, severity and message of each problem found.
+    diagnostics: Vec<(usize, Severity, String)>,
+}
+
+impl Parser {
+    fn new(body: &str) -> Self {
+        Self { chars: body.chars().collect(), pos: 0, depth: 0, too_deep: false, diagnostics: Vec::new() }
+    }
+
+    /// Text from `start` to the current position, as typed.
+    fn literal(&self, start: usize) -> Vec<Node> {
+        vec![Node::Text(self.chars[start..self.pos].iter().collect())]
+    }
+
+    /// Reports the construct starting at `start` as broken and keeps what was
+    /// read of it as text. Parsing carries on from here rather than going
+    /// back, so no part of the body is read twice.
+    fn fail(&mut self, start: usize, severity: Severity, message: String) -> Result<Node, Vec<Node>> {
+        self.diagnostics.push((start, severity, message));
+        Err(self.literal(start))
+    }
+
+    fn variable(&mut self, start: usize, name: String, default: Option<Vec<Node>>, transform: Option<Transform>) -> Result<Node, Vec<Node>> {
+        if !KNOWN_VARIABLES.contains(&name.as_str()) {
+            self.diagnostics.push((
+                start,
+                Severity::Warning,
+                format!("Unknown variable `{name}`; editors insert its name as a placeholder. Escape the `$` as `\\$` to keep it as text"),
+            ));
+        }
+        Ok(Node::Variable { name, default, transform })
+    }
+
+    /// Reads the default after `${1:` or `${NAME:`. When it's never closed the
+    /// opening is kept as text followed by the default's nodes, which is what
+    /// reading on from the `$` as text would give.
+    fn parse_default(&mut self, start: usize, opening: &str) -> Result<Vec<Node>, Vec<Node>> {
+        if self.depth >= MAX_NESTING {
+            if !self.too_deep {
+                self.too_deep = true;
+                self.diagnostics.push((
+                    start,
+                    Severity::Error,
+                    format!("Placeholders are nested more than {MAX_NESTING} levels deep"),
+                ));
+            }
+            return Err(self.literal(start));
+        }
+        let header = self.literal(start);
+        self.depth += 1;
+        let default = self.parse_nodes(true);
+        self.depth -= 1;
+        if self.eat('}') {
+            return Ok(default);
+        }
+        self.diagnostics.push((start, Severity::Warning, format!("`{opening}` is never closed with `}}`")));
+        Err(header.into_iter().chain(default).collect())
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let matched = self.peek() == Some(c);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    /// Reads nodes to the end of input, or up to an unescaped `}` when `nested`.
+    fn parse_nodes(&mut self, nested: bool) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        let mut text = String::new();
+        while let Some(c) = self.peek() {
+            match c {
+                '\\' if matches!(self.peek_at(1), Some('$' | '}' | '\\')) => {
+                    text.extend(self.peek_at(1));
+                    self.pos += 2;
+                }
+                '}' if nested => break,
+                '$' => {
+                    let parsed = match self.parse_dollar() {
+                        Ok(node) => vec![node],
+                        Err(fallback) => fallback,
+                    };
+                    for node in parsed {
+                        match node {
+                            Node::Text(t) => text.push_str(&t),
+                            node => {
+                                if !text.is_empty() {
+                                    nodes.push(Node::Text(std::mem::take(&mut text)));
+                                }
+                                nodes.push(node);
+                            }
+                        }
+                    }
+                }
+                c => {
+                    text.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        nodes
+    }
+
+    /// Reads the construct at a `$`, or returns what to keep instead when
+    /// there isn't a valid one.
+    fn parse_dollar(&mut self) -> Result<Node, Vec<Node>> {
+        let start = self.pos;
+        self.pos += 1;
+        if let Some(index) = self.parse_int() {
+            return Ok(Node::Tabstop { index, transform: None });
+        }
+        if let Some(name) = self.parse_name() {
+            return self.variable(start, name, None, None);
+        }
+        if !self.eat('{') {
+            return Err(self.literal(start));
+        }
+
+        if let Some(index) = self.parse_int() {
+            if self.eat('}') {
+                return Ok(Node::Tabstop { index, transform: None });
+            }
+            if self.eat(':') {
+                let default = self.parse_default(start, &format!("${{{index}:"))?;
+                return Ok(Node::Placeholder { index, default });
+            }
+            if self.eat('|') {
+                return match self.parse_choice() {
+                    Ok(options) => {
+                        if options.iter().any(String::is_empty) {
+                            self.diagnostics.push((start, Severity::Warning, format!("Choice `${index}` has an empty option")));
+                        }
+                        Ok(Node::Choice { index, options })
+                    }
+                    Err(problem) => self.fail(start, Severity::Error, format!("Choice `${index}` {problem}")),
+                };
+            }
+            // shell parameter expansion like `${1/a}` looks the same, so only warnings from here on
+            if self.peek() == Some('/') {
+                return match self.parse_transform() {
+                    Some(transform) => Ok(Node::Tabstop { index, transform: Some(transform) }),
+                    None => self.fail(start, Severity::Warning, format!("Transform on `${index}` {TRANSFORM_SHAPE}")),
+                };
+            }
+            return self.fail(
+                start,
+                Severity::Warning,
+                format!("Expected `}}`, `:`, `|` or `/` after `${{{index}`; it is inserted as typed"),
+            );
+        }
+
+        // `${` not followed by a name is left alone: it's common in shell and template code
+        let Some(name) = self.parse_name() else {
+            return Err(self.literal(start));
+        };
+        if self.eat('}') {
+            return self.variable(start, name, None, None);
+        }
+        if self.eat(':') {
+            let default = self.parse_default(start, &format!("${{{name}:"))?;
+            return self.variable(start, name, Some(default), None);
+        }
+        if self.peek() == Some('/') {
+            return match self.parse_transform() {
+                Some(transform) => self.variable(start, name, None, Some(transform)),
+                None => self.fail(start, Severity::Warning, format!("Transform on `${name}` {TRANSFORM_SHAPE}")),
+            };
+        }
+        // likely shell or template syntax such as `${name[0]}`
+        self.fail(
+            start,
+            Severity::Warning,
+            format!("`${{{name}` isn't snippet syntax and is inserted as typed; escape the `$` as `\\$` if that's intended"),
+        )
+    }
+
+    fn parse_int(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let digits: String = self.chars[start..self.pos].iter().collect();
+        match digits.parse() {
+            Ok(index) => Some(index),
+            Err(_) => {
+                self.pos = start;
+                None
+            }
+        }
+    }
+
+    fn parse_name(&mut self) -> Option<String> {
+        if !self.peek().is_some_and(|c| c == '_' || c.is_ascii_alphabetic()) {
+            return None;
+        }
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c == '_' || c.is_ascii_alphanumeric()) {
+            self.pos += 1;
+        }
+        Some(self.chars[start..self.pos].iter().collect())
+    }
+
+    /// Reads `a,b|}` after the opening `${1|`, or says what's wrong with it.
+    fn parse_choice(&mut self) -> Result<Vec<String>, &'static str> {
+        let mut options = Vec::new();
+        let mut current = String::new();
+        loop {
+            match self.peek().ok_or("is never closed with `|}`")? {
+                '\\' if matches!(self.peek_at(1), Some(',' | '|' | '\\')) => {
+                    current.extend(self.peek_at(1));
+                    self.pos += 2;
+                }
+                ',' => {
+                    options.push(std::mem::take(&mut current));
+                    self.pos += 1;
+                }
+                '|' => {
+                    self.pos += 1;
+                    if !self.eat('}') {
+                        return Err("must end with `|}`; escape a literal `|` as `\\|`");
+                    }
+                    options.push(current);
+                    return Ok(options);
+                }
+                c => {
+                    current.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    /// Reads `/regex/format/options}`, including the closing `}`.
+    fn parse_transform(&mut self) -> Option<Transform> {
+        self.pos += 1;
+        let regex = self.read_transform_part()?;
+        let format = self.read_transform_part()?;
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+        let options = self.chars[start..self.pos].iter().collect();
+        self.eat('}').then_some(Transform { regex, format, options })
+    }
+
+    /// Raw text up to the next unescaped `/` outside a `${1:/upcase}` style
+    /// format reference, which is consumed. Escapes are kept.
+    fn read_transform_part(&mut self) -> Option<String> {
+        let mut part = String::new();
+        let mut depth = 0;
+        loop {
+            match self.peek()? {
+                '\\' => {
+                    part.push('\\');
+                    part.extend(self.peek_at(1));
+                    self.pos += 2;
+                }
+                '/' if depth == 0 => {
+                    self.pos += 1;
+                    return Some(part);
+                }
+                '$' if self.peek_at(1) == Some('{') => {
+                    depth += 1;
+                    part.push_str("${");
+                    self.pos += 2;
+                }
+                '}' if depth > 0 => {
+                    depth -= 1;
+                    part.push('}');
+                    self.pos += 1;
+                }
+                c => {
+                    part.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+}
+
+fn write_nodes(out: &mut String, nodes: &[Node], nested: bool) {
+    for (i, node) in nodes.iter().enumerate() {
+        match node {
+            Node::Text(text) => {
+                // a trailing backslash would escape whatever follows it
+                let followed_by_syntax = nested || i + 1 < nodes.len();
+                write_text(out, text, nested, followed_by_syntax);
+            }
+            Node::Tabstop { index, transform: None } => {
+                let joins_next = matches!(nodes.get(i + 1), Some(Node::Text(t)) if t.starts_with(|c: char| c.is_ascii_digit()));
+                if joins_next {
+                    out.push_str(&format!("${{{index}}}"));
+                } else {
+                    out.push_str(&format!("${index}"));
+                }
+            }
+            Node::Tabstop { index, transform: Some(transform) } => {
+                out.push_str(&format!("${{{index}"));
+                write_transform(out, transform);
+                out.push('}');
+            }
+            Node::Placeholder { index, default } => {
+                out.push_str(&format!("${{{index}:"));
+                write_nodes(out, default, true);
+                out.push('}');
+            }
+            Node::Choice { index, options } => {
+                let options: Vec<String> = options
+                    .iter()
+                    .map(|o| o.replace('\\', "\\\\").replace(',', "\\,").replace('|', "\\|"))
+                    .collect();
+                out.push_str(&format!("${{{index}|{}|}}", options.join(",")));
+            }
+            Node::Variable { name, default: None, transform: None } => {
+                // braces keep a following letter or digit from joining the name
+                let joins_next = matches!(nodes.get(i + 1), Some(Node::Text(t)) if t.starts_with(|c: char| c == '_' || c.is_ascii_alphanumeric()));
+                if joins_next {
+                    out.push_str(&format!("${{{name}}}"));
+                } else {
+                    out.push_str(&format!("${name}"));
+                }
+            }
+            Node::Variable { name, default, transform } => {
+                out.push_str(&format!("${{{name}"));
+                if let Some(transform) = transform {
+                    write_transform(out, transform);
+                } else if let Some(default) = default {
+                    out.push(':');
+                    write_nodes(out, default, true);
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn write_text(out: &mut String, text: &str, nested: bool, followed_by_syntax: bool) {
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => out.push_str("\\$"),
+            '}' if nested => out.push_str("\\}"),
+            '\\' => {
+                let escapes_next = match chars.peek() {
+                    Some('$' | '\\') => true,
+                    Some('}') => nested,
+                    Some(_) => false,
+                    None => followed_by_syntax,
+                };
+                out.push_str(if escapes_next { "\\\\" } else { "\\" });
+            }
+            c => out.push(c),
+        }
+    }
+}
+
+fn write_transform(out: &mut String, transform: &Transform) {
+    out.push('/');
+    out.push_str(&transform.regex);
+    out.push('/');
+    out.push_str(&transform.format);
+    out.push('/');
+    out.push_str(&transform.options);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn errors(syntax: &SnippetSyntax) -> Vec<&str> {
+        syntax.diagnostics.iter().filter(|d| d.severity == Severity::Error).map(|d| d.message.as_str()).collect()
+    }
+
+    fn text(s: &str) -> Node {
+        Node::Text(s.to_string())
+    }
+
+    #[test]
+    fn unclosed_placeholder_is_kept_as_text_with_a_warning() {
+        let syntax = analyze("let x = ${1:value;");
+        assert!(!syntax.has_errors());
+        assert_eq!(syntax.diagnostics.len(), 1);
+        assert_eq!(syntax.diagnostics[0].severity, Severity::Warning);
+        assert_eq!(syntax.diagnostics[0].column, 9);
+        assert!(syntax.tabstops.is_empty());
+        assert_eq!(parse_snippet("let x = ${1:value;"), vec![text("let x = ${1:value;")]);
+
+        // a stray `}` outside any placeholder is plain text
+        assert_eq!(parse_snippet("a}b"), vec![text("a}b")]);
+        assert!(analyze("a}b").diagnostics.is_empty());
+    }
+
+    #[test]
+    fn bare_dollar_brace_is_left_alone() {
+        let syntax = analyze("echo ${ and $");
+        assert!(syntax.diagnostics.is_empty());
+        assert_eq!(parse_snippet("echo ${ and $"), vec![text("echo ${ and $")]);
+    }
+
+    #[test]
+    fn broken_choices_are_errors() {
+        for body in ["${1|a,b|", "${1|a,b", "${1|a|b|}"] {
+            let syntax = analyze(body);
+            assert_eq!(errors(&syntax).len(), 1, "{body}");
+            assert!(errors(&syntax)[0].starts_with("Choice `$1`"), "{body}");
+            assert_eq!(parse_snippet(body), vec![text(body)], "{body}");
+        }
+
+        let syntax = analyze("${1|a,,b|}");
+        assert!(!syntax.has_errors());
+        assert_eq!(syntax.diagnostics[0].message, "Choice `$1` has an empty option");
+    }
+
+    #[test]
+    fn choices_unescape_their_options() {
+        assert_eq!(
+            parse_snippet(r"${1|a\,b,c\|d|}"),
+            vec![Node::Choice { index: 1, options: vec!["a,b".to_string(), "c|d".to_string()] }]
+        );
+    }
+
+    #[test]
+    fn unknown_variables_are_warnings() {
+        let syntax = analyze("$TM_FILENAME $NOT_A_VARIABLE");
+        assert!(!syntax.has_errors());
+        assert_eq!(syntax.diagnostics.len(), 1);
+        assert_eq!(syntax.diagnostics[0].severity, Severity::Warning);
+        assert!(syntax.diagnostics[0].message.contains("NOT_A_VARIABLE"));
+
+        let known: Vec<_> = syntax.variables.iter().map(|v| (v.name.as_str(), v.known)).collect();
+        assert_eq!(known, vec![("TM_FILENAME", true), ("NOT_A_VARIABLE", false)]);
+    }
+
+    #[test]
+    fn shell_syntax_only_warns() {
+        for body in ["${1%.txt}", "${1,,}", "${arr[0]}", "${1/a}", "${1 + n}"] {
+            let syntax = analyze(body);
+            assert!(!syntax.has_errors(), "{body}");
+            assert!(!syntax.diagnostics.is_empty(), "{body}");
+        }
+    }
+
+    #[test]
+    fn diagnostics_have_line_and_column() {
+        let syntax = analyze("fn main() {\n    $NOPE\n\tlet é = ${1|a,b|;\n}");
+        let positions: Vec<_> = syntax.diagnostics.iter().map(|d| (d.severity, d.line, d.column)).collect();
+        // errors come first, whatever their position; columns count characters, not bytes
+        assert_eq!(positions, vec![(Severity::Error, 3, 10), (Severity::Warning, 2, 5)]);
+    }
+
+    #[test]
+    fn diagnostics_are_capped() {
+        let body = "$NOPE ".repeat(MAX_DIAGNOSTICS + 20) + "${1|a";
+        let syntax = analyze(&body);
+        assert_eq!(syntax.diagnostics.len(), MAX_DIAGNOSTICS);
+        assert_eq!(syntax.diagnostics[0].severity, Severity::Error);
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error_not_a_crash() {
+        let body = "${1:".repeat(10_000);
+        let syntax = analyze(&body);
+        assert_eq!(errors(&syntax).len(), 1);
+    }
+
+    #[test]
+    fn final_tabstop_sorts_last() {
+        let syntax = analyze("$0 ${2:b} $1 ${3|x,y|} $2");
+        let order: Vec<_> = syntax.tabstops.iter().map(|t| (t.index, t.occurrences)).collect();
+        assert_eq!(order, vec![(1, 1), (2, 2), (3, 1), (0, 1)]);
+        assert_eq!(syntax.tabstops[1].placeholder.as_deref(), Some("b"));
+        assert_eq!(syntax.tabstops[2].choices, Some(vec!["x".to_string(), "y".to_string()]));
+    }
+
+    #[test]
+    fn nested_placeholders() {
+        let nodes = parse_snippet("${1:outer ${2:inner $TM_FILENAME} end}");
+        assert_eq!(
+            nodes,
+            vec![Node::Placeholder {
+                index: 1,
+                default: vec![
+                    text("outer "),
+                    Node::Placeholder {
+                        index: 2,
+                        default: vec![
+                            text("inner "),
+                            Node::Variable { name: "TM_FILENAME".to_string(), default: None, transform: None },
+                        ],
+                    },
+                    text(" end"),
+                ],
+            }]
+        );
+
+        let syntax = analyze("${1:outer ${2:inner} end}");
+        assert_eq!(syntax.tabstops[0].placeholder.as_deref(), Some("outer inner end"));
+        assert_eq!(syntax.tabstops[1].placeholder.as_deref(), Some("inner"));
+        assert_eq!(plain_text(&nodes), "outer inner  end");
+    }
+
+    #[test]
+    fn transforms() {
+        assert_eq!(
+            parse_snippet("${1/(.*)/${1:/upcase}/g}"),
+            vec![Node::Tabstop {
+                index: 1,
+                transform: Some(Transform {
+                    regex: "(.*)".to_string(),
+                    format: "${1:/upcase}".to_string(),
+                    options: "g".to_string(),
+                }),
+            }]
+        );
+    }
+
+    #[test]
+    fn valid_bodies_round_trip() {
+        let bodies = [
+            "",
+            "plain text",
+            "fn ${1:name}(${2:args}) {\n\t$0\n}",
+            "${1|one,two,three|} $1",
+            r"${1|a\,b,c\|d,e\\f|}",
+            "$TM_FILENAME ${TM_FILENAME}_x ${CLIPBOARD:fallback}",
+            "${TM_FILENAME/(.*)\\..+$/$1/}",
+            "${1/(.*)/${1:/upcase}/g}",
+            "${1:outer ${2:inner ${3:deepest}}}",
+            "price: \\$5, brace: ${1:a\\}b}",
+            "${1}2 ${SELECTION}x",
+            "trailing \\",
+        ];
+        for body in bodies {
+            assert_eq!(to_snippet_string(&parse_snippet(body)), body, "{body:?}");
+            assert!(!analyze(body).has_errors(), "{body:?}");
+        }
+    }
+}
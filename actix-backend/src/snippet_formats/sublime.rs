@@ -0,0 +1,157 @@
+use super::{
+    file_names, languages_from_textmate_scope, textmate_scope,
+    xml::{cdata, elements, escape_text},
+    Export, ExportFile, PortableSnippet, TextMateTarget, PLAINTEXT,
+};
+
+const SUBLIME: TextMateTarget = TextMateTarget {
+    name: "Sublime Text",
+    choices: false,
+    variables: &[
+        "SELECTION",
+        "TM_CURRENT_LINE",
+        "TM_CURRENT_WORD",
+        "TM_DIRECTORY",
+        "TM_FILENAME",
+        "TM_FILEPATH",
+        "TM_FULLNAME",
+        "TM_LINE_INDEX",
+        "TM_LINE_NUMBER",
+        "TM_SELECTED_TEXT",
+        "TM_SOFT_TABS",
+        "TM_TAB_SIZE",
+    ],
+};
+
+/// One `.sublime-snippet` file per snippet.
+pub fn to_sublime_snippets(snippets: &[PortableSnippet]) -> Export {
+    let mut export = Export::default();
+    for (snippet, name) in snippets.iter().zip(file_names(snippets, "sublime-snippet")) {
+        let content = SUBLIME.adapt(snippet, &mut export.warnings);
+
+        let mut file = String::from("<snippet>\n");
+        // Sublime trims the blank lines around the CDATA when inserting
+        file.push_str(&format!("    <content>{}</content>\n", cdata(&format!("\n{content}\n"))));
+        if let Some(prefix) = snippet.single_prefix("Sublime Text", &mut export.warnings) {
+            file.push_str(&format!("    <tabTrigger>{}</tabTrigger>\n", escape_text(prefix)));
+        }
+        let scopes: Vec<String> = snippet.languages().into_iter().map(textmate_scope).collect();
+        if !scopes.is_empty() {
+            file.push_str(&format!("    <scope>{}</scope>\n", escape_text(&scopes.join(", "))));
+        }
+        file.push_str(&format!("    <description>{}</description>\n", escape_text(&snippet.title)));
+        file.push_str("</snippet>\n");
+
+        export.files.push(ExportFile { name, content: file });
+    }
+    export
+}
+
+/// Reads a `.sublime-snippet` file. `name` (the file name without extension)
+/// titles snippets that have neither a description nor a trigger.
+pub fn parse_sublime_snippet(text: &str, name: &str) -> Result<PortableSnippet, String> {
+    let elements = elements(text)?;
+    if elements.first().is_none_or(|e| e.name != "snippet") {
+        return Err("Not a Sublime Text snippet: expected a <snippet> element".to_string());
+    }
+    let field = |field: &str| {
+        elements
+            .iter()
+            .find(|e| e.depth == 1 && e.name == field)
+            .map(|e| e.text.clone())
+            .filter(|t| !t.trim().is_empty())
+    };
+
+    let content = field("content").ok_or("Sublime Text snippet has no <content>")?;
+    let code = content.strip_prefix('\n').unwrap_or(&content);
+    let code = code.strip_suffix('\n').unwrap_or(code).to_string();
+    let trigger = field("tabTrigger").map(|t| t.trim().to_string());
+    let (language, scope) = field("scope")
+        .map_or_else(|| (PLAINTEXT.to_string(), None), |s| languages_from_textmate_scope(&s));
+
+    Ok(PortableSnippet {
+        title: field("description")
+            .map(|d| d.trim().to_string())
+            .or_else(|| trigger.clone())
+            .unwrap_or_else(|| name.to_string()),
+        description: None,
+        code,
+        language,
+        prefixes: trigger.into_iter().collect(),
+        scope,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snippet(title: &str, code: &str) -> PortableSnippet {
+        PortableSnippet {
+            title: title.to_string(),
+            description: None,
+            code: code.to_string(),
+            language: "rust".to_string(),
+            prefixes: vec!["fn".to_string()],
+            scope: None,
+        }
+    }
+
+    #[test]
+    fn choices_become_their_first_option() {
+        let mut warnings = Vec::new();
+        let adapted = SUBLIME.adapt(&snippet("Choice", "${1|first,second|} ${2:${3|x,y|}}"), &mut warnings);
+        assert_eq!(adapted, "${1:first} ${2:${3:x}}");
+        let messages: Vec<_> = warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Sublime Text has no choice lists; ${1} defaults to its first option",
+                "Sublime Text has no choice lists; ${3} defaults to its first option",
+            ]
+        );
+    }
+
+    #[test]
+    fn unsupported_variables_fall_back_to_their_default() {
+        let mut warnings = Vec::new();
+        let adapted = SUBLIME.adapt(&snippet("Vars", "$TM_FILENAME ${CLIPBOARD:none} $UUID"), &mut warnings);
+        assert_eq!(adapted, "$TM_FILENAME none ");
+        assert_eq!(warnings.len(), 2);
+    }
+
+    #[test]
+    fn export_round_trips() {
+        let snippets = [
+            snippet("Function", "\nfn ${1:name}() {\n\t$0\n} // ]]> & <$TM_FILENAME>\n"),
+            PortableSnippet {
+                scope: Some("javascript,typescript".to_string()),
+                language: "javascript".to_string(),
+                ..snippet("Log", "console.log($1);")
+            },
+        ];
+        let export = to_sublime_snippets(&snippets);
+        assert!(export.warnings.is_empty());
+        assert_eq!(export.files.len(), 2);
+
+        for (original, file) in snippets.iter().zip(&export.files) {
+            assert!(file.name.ends_with(".sublime-snippet"));
+            let parsed = parse_sublime_snippet(&file.content, "ignored").unwrap();
+            assert_eq!(parsed.code, original.code);
+            assert_eq!(parsed.title, original.title);
+            assert_eq!(parsed.language, original.language);
+            assert_eq!(parsed.scope, original.scope);
+            assert_eq!(parsed.prefixes, original.prefixes);
+        }
+    }
+
+    #[test]
+    fn untitled_snippets_use_the_trigger_or_file_name() {
+        let with_trigger = "<snippet><content>x</content><tabTrigger>tr</tabTrigger></snippet>";
+        assert_eq!(parse_sublime_snippet(with_trigger, "file").unwrap().title, "tr");
+        let bare = "<snippet><content>x</content></snippet>";
+        let parsed = parse_sublime_snippet(bare, "file").unwrap();
+        assert_eq!((parsed.title.as_str(), parsed.language.as_str()), ("file", PLAINTEXT));
+        assert!(parse_sublime_snippet("<snippet></snippet>", "file").is_err());
+    }
+}
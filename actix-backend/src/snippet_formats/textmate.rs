@@ -0,0 +1,153 @@
+use uuid::Uuid;
+
+use super::{
+    file_names, languages_from_textmate_scope, textmate_scope,
+    xml::{elements, escape_text},
+    Export, ExportFile, PortableSnippet, TextMateTarget, PLAINTEXT,
+};
+
+const TEXTMATE: TextMateTarget = TextMateTarget {
+    name: "TextMate",
+    choices: true,
+    variables: &[
+        "TM_CURRENT_LINE",
+        "TM_CURRENT_WORD",
+        "TM_DIRECTORY",
+        "TM_FILENAME",
+        "TM_FILEPATH",
+        "TM_FULLNAME",
+        "TM_LINE_INDEX",
+        "TM_LINE_NUMBER",
+        "TM_PROJECT_DIRECTORY",
+        "TM_SCOPE",
+        "TM_SELECTED_TEXT",
+        "TM_SOFT_TABS",
+        "TM_TAB_SIZE",
+    ],
+};
+
+/// One `.tmSnippet` property list per snippet.
+pub fn to_textmate_snippets(snippets: &[PortableSnippet]) -> Export {
+    let mut export = Export::default();
+    for (snippet, name) in snippets.iter().zip(file_names(snippets, "tmSnippet")) {
+        let content = TEXTMATE.adapt(snippet, &mut export.warnings);
+
+        let mut entries = vec![("content", content), ("name", snippet.title.clone())];
+        let scopes: Vec<String> = snippet.languages().into_iter().map(textmate_scope).collect();
+        if !scopes.is_empty() {
+            entries.push(("scope", scopes.join(", ")));
+        }
+        if let Some(prefix) = snippet.single_prefix("TextMate", &mut export.warnings) {
+            entries.push(("tabTrigger", prefix.to_string()));
+        }
+        entries.push(("uuid", Uuid::new_v4().to_string().to_uppercase()));
+
+        let mut file = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" ",
+            "\"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n",
+            "<plist version=\"1.0\">\n<dict>\n",
+        ));
+        for (key, value) in entries {
+            file.push_str(&format!("\t<key>{key}</key>\n\t<string>{}</string>\n", escape_text(&value)));
+        }
+        file.push_str("</dict>\n</plist>\n");
+
+        export.files.push(ExportFile { name, content: file });
+    }
+    export
+}
+
+/// Reads a `.tmSnippet` property list. `name` (the file name without
+/// extension) titles snippets without a `name` key.
+pub fn parse_textmate_snippet(text: &str, name: &str) -> Result<PortableSnippet, String> {
+    let elements = elements(text)?;
+    let dict_depth = match elements.iter().position(|e| e.name == "plist") {
+        Some(plist) if elements.get(plist + 1).is_some_and(|e| e.name == "dict") => elements[plist + 1].depth + 1,
+        _ => return Err("Not a TextMate snippet: expected a <plist> with a <dict>".to_string()),
+    };
+
+    // the top-level dict alternates <key> and value elements
+    let entries: Vec<_> = elements.iter().filter(|e| e.depth == dict_depth).collect();
+    let field = |key: &str| {
+        entries
+            .windows(2)
+            .find(|pair| pair[0].name == "key" && pair[0].text == key && pair[1].name == "string")
+            .map(|pair| pair[1].text.clone())
+            .filter(|t| !t.trim().is_empty())
+    };
+
+    let code = field("content").ok_or("TextMate snippet has no content")?;
+    let trigger = field("tabTrigger").map(|t| t.trim().to_string());
+    let (language, scope) = field("scope")
+        .map_or_else(|| (PLAINTEXT.to_string(), None), |s| languages_from_textmate_scope(&s));
+
+    Ok(PortableSnippet {
+        title: field("name")
+            .map(|n| n.trim().to_string())
+            .or_else(|| trigger.clone())
+            .unwrap_or_else(|| name.to_string()),
+        description: None,
+        code,
+        language,
+        prefixes: trigger.into_iter().collect(),
+        scope,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snippet(title: &str, code: &str) -> PortableSnippet {
+        PortableSnippet {
+            title: title.to_string(),
+            description: None,
+            code: code.to_string(),
+            language: "rust".to_string(),
+            prefixes: vec!["fn".to_string()],
+            scope: None,
+        }
+    }
+
+    #[test]
+    fn choices_are_kept() {
+        let mut warnings = Vec::new();
+        let adapted = TEXTMATE.adapt(&snippet("Choice", "${1|a,b|} $TM_SCOPE $CURRENT_YEAR"), &mut warnings);
+        assert_eq!(adapted, "${1|a,b|} $TM_SCOPE ");
+        let messages: Vec<_> = warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(messages, vec!["TextMate has no $CURRENT_YEAR variable; its default is inserted instead"]);
+    }
+
+    #[test]
+    fn export_round_trips() {
+        let snippets = [
+            snippet("Function <&>", "fn ${1:name}(${2|a,b|}) {\n\t$0\n} // $TM_FILENAME\n"),
+            PortableSnippet {
+                scope: Some("javascript,typescript".to_string()),
+                language: "javascript".to_string(),
+                ..snippet("Log", "console.log($1);")
+            },
+        ];
+        let export = to_textmate_snippets(&snippets);
+        assert!(export.warnings.is_empty());
+        assert_eq!(export.files.len(), 2);
+
+        for (original, file) in snippets.iter().zip(&export.files) {
+            assert!(file.name.ends_with(".tmSnippet"));
+            let parsed = parse_textmate_snippet(&file.content, "ignored").unwrap();
+            assert_eq!(parsed.code, original.code);
+            assert_eq!(parsed.title, original.title);
+            assert_eq!(parsed.language, original.language);
+            assert_eq!(parsed.scope, original.scope);
+            assert_eq!(parsed.prefixes, original.prefixes);
+        }
+    }
+
+    #[test]
+    fn rejects_other_property_lists() {
+        assert!(parse_textmate_snippet("<plist><array/></plist>", "file").is_err());
+        let no_content = "<plist><dict><key>name</key><string>x</string></dict></plist>";
+        assert!(parse_textmate_snippet(no_content, "file").is_err());
+    }
+}
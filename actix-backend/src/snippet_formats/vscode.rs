@@ -0,0 +1,218 @@
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use super::{jsonc::strip_jsonc, unique_names, PortableSnippet, PLAINTEXT};
+
+/// VS Code accepts either a single string or an array for these fields.
+#[derive(Deserialize)]
+#[serde(untagged, expecting = "expected a string or an array of strings")]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl OneOrMany {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct VsCodeSnippet {
+    prefix: Option<OneOrMany>,
+    body: OneOrMany,
+    description: Option<OneOrMany>,
+    scope: Option<String>,
+}
+
+/// Parses a `.code-snippets` file or a `<language>.json` file.
+///
+/// `language` is the language of a `<language>.json` file. Without it, each
+/// snippet takes the first ID in its `scope`, or `plaintext` when it has none.
+pub fn parse_vscode_snippets(text: &str, language: Option<&str>) -> Result<Vec<PortableSnippet>, String> {
+    let entries: Map<String, Value> = serde_json::from_str(&strip_jsonc(text))
+        .map_err(|e| format!("Not a VS Code snippets file: {e}"))?;
+
+    entries
+        .into_iter()
+        .map(|(name, value)| {
+            let snippet: VsCodeSnippet = serde_json::from_value(value)
+                .map_err(|e| format!("Snippet \"{name}\": {e}"))?;
+
+            let scope = snippet
+                .scope
+                .map(|scope| scope.split(',').map(str::trim).filter(|s| !s.is_empty()).collect::<Vec<_>>().join(","))
+                .filter(|scope| !scope.is_empty());
+            let language = language
+                .map(str::to_string)
+                .or_else(|| scope.as_deref().and_then(|s| s.split(',').next()).map(str::to_string))
+                .unwrap_or_else(|| PLAINTEXT.to_string());
+
+            Ok(PortableSnippet {
+                title: name,
+                description: snippet.description.map(|d| d.into_vec().join("\n")),
+                code: snippet.body.into_vec().join("\n"),
+                language,
+                prefixes: snippet.prefix.map(OneOrMany::into_vec).unwrap_or_default(),
+                scope,
+            })
+        })
+        .collect()
+}
+
+/// Builds a `.code-snippets` file, keyed by title.
+pub fn to_vscode_snippets(snippets: &[PortableSnippet]) -> Value {
+    let names = unique_names(snippets.iter().map(|s| s.title.as_str()), |base, n| format!("{base} ({n})"));
+    let entries = snippets
+        .iter()
+        .zip(names)
+        .map(|(snippet, name)| {
+            let mut entry = Map::new();
+            match snippet.prefixes.as_slice() {
+                [] => {}
+                [prefix] => {
+                    entry.insert("prefix".into(), json!(prefix));
+                }
+                prefixes => {
+                    entry.insert("prefix".into(), json!(prefixes));
+                }
+            }
+            entry.insert("body".into(), json!(snippet.code.split('\n').collect::<Vec<_>>()));
+            if let Some(description) = snippet.description.as_deref().filter(|d| !d.is_empty()) {
+                entry.insert("description".into(), json!(description));
+            }
+            // unscoped snippets from a `.code-snippets` file apply to every language
+            let scope = snippet.scope.as_deref().unwrap_or(&snippet.language);
+            if scope != PLAINTEXT {
+                entry.insert("scope".into(), json!(scope));
+            }
+            (name, Value::Object(entry))
+        })
+        .collect::<Map<_, _>>();
+    Value::Object(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snippet(title: &str, language: &str, scope: Option<&str>) -> PortableSnippet {
+        PortableSnippet {
+            title: title.to_string(),
+            description: None,
+            code: "print($1)".to_string(),
+            language: language.to_string(),
+            prefixes: Vec::new(),
+            scope: scope.map(str::to_string),
+        }
+    }
+
+    fn by_title<'a>(snippets: &'a [PortableSnippet], title: &str) -> &'a PortableSnippet {
+        snippets.iter().find(|s| s.title == title).unwrap()
+    }
+
+    #[test]
+    fn fields_may_be_strings_or_arrays() {
+        let text = r#"{
+            // one of each
+            "One": { "prefix": "one", "body": "a $1", "description": "single" },
+            "Many": { "prefix": ["m", "many"], "body": ["a", "\tb", ""], "description": ["x", "y"], },
+            "Bare": { "body": [] },
+        }"#;
+        let snippets = parse_vscode_snippets(text, Some("python")).unwrap();
+        assert_eq!(snippets.len(), 3);
+
+        let one = by_title(&snippets, "One");
+        assert_eq!((one.code.as_str(), one.prefixes.clone()), ("a $1", vec!["one".to_string()]));
+        assert_eq!(one.description.as_deref(), Some("single"));
+
+        let many = by_title(&snippets, "Many");
+        assert_eq!(many.code, "a\n\tb\n");
+        assert_eq!(many.prefixes, vec!["m", "many"]);
+        assert_eq!(many.description.as_deref(), Some("x\ny"));
+
+        let bare = by_title(&snippets, "Bare");
+        assert_eq!((bare.code.as_str(), bare.prefixes.len()), ("", 0));
+        assert_eq!(bare.language, "python");
+    }
+
+    #[test]
+    fn malformed_snippets_are_named() {
+        let err = parse_vscode_snippets(r#"{"Broken": {"body": 3}}"#, None).unwrap_err();
+        assert!(err.starts_with("Snippet \"Broken\""), "{err}");
+        assert!(parse_vscode_snippets("[]", None).is_err());
+    }
+
+    #[test]
+    fn language_comes_from_the_file_then_the_scope() {
+        let text = r#"{
+            "Scoped": { "body": "x", "scope": " typescript , javascript ,," },
+            "Blank": { "body": "x", "scope": " " },
+            "Global": { "body": "x" }
+        }"#;
+        let snippets = parse_vscode_snippets(text, None).unwrap();
+        let scoped = by_title(&snippets, "Scoped");
+        assert_eq!(scoped.language, "typescript");
+        assert_eq!(scoped.scope.as_deref(), Some("typescript,javascript"));
+        for title in ["Blank", "Global"] {
+            let snippet = by_title(&snippets, title);
+            assert_eq!((snippet.language.as_str(), snippet.scope.as_deref()), (PLAINTEXT, None));
+        }
+
+        let snippets = parse_vscode_snippets(text, Some("rust")).unwrap();
+        assert!(snippets.iter().all(|s| s.language == "rust"));
+    }
+
+    #[test]
+    fn export_scopes_by_language_except_plaintext() {
+        let exported = to_vscode_snippets(&[
+            snippet("Rust", "rust", None),
+            snippet("Web", "javascript", Some("javascript,typescript")),
+            snippet("Text", PLAINTEXT, None),
+        ]);
+        assert_eq!(exported["Rust"]["scope"], "rust");
+        assert_eq!(exported["Web"]["scope"], "javascript,typescript");
+        assert!(exported["Text"].get("scope").is_none());
+    }
+
+    #[test]
+    fn export_renames_duplicate_titles() {
+        let exported = to_vscode_snippets(&[
+            snippet("Print", "python", None),
+            snippet("Print", "python", None),
+            snippet("Print (2)", "python", None),
+            snippet("Print", "python", None),
+        ]);
+        let mut names: Vec<_> = exported.as_object().unwrap().keys().cloned().collect();
+        names.sort();
+        assert_eq!(names, vec!["Print", "Print (2)", "Print (2) (2)", "Print (3)"]);
+    }
+
+    #[test]
+    fn export_round_trips() {
+        let mut many = snippet("Many", "rust", None);
+        many.code = "fn ${1:name}() {\n\t$0\n}\n".to_string();
+        many.prefixes = vec!["fn".to_string(), "func".to_string()];
+        many.description = Some("two\nlines".to_string());
+        let mut one = snippet("One", "javascript", Some("javascript,typescript"));
+        one.prefixes = vec!["log".to_string()];
+        let snippets = [many, one];
+
+        let exported = to_vscode_snippets(&snippets);
+        assert_eq!(exported["One"]["prefix"], "log");
+        assert_eq!(exported["Many"]["prefix"], json!(["fn", "func"]));
+        assert_eq!(exported["Many"]["body"], json!(["fn ${1:name}() {", "\t$0", "}", ""]));
+
+        let parsed = parse_vscode_snippets(&serde_json::to_string_pretty(&exported).unwrap(), None).unwrap();
+        for original in &snippets {
+            let parsed = by_title(&parsed, &original.title);
+            assert_eq!(parsed.code, original.code);
+            assert_eq!(parsed.prefixes, original.prefixes);
+            assert_eq!(parsed.description, original.description);
+            assert_eq!(parsed.language, original.language);
+        }
+    }
+}
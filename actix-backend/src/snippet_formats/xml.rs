@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use quick_xml::{escape::resolve_predefined_entity, events::Event, Reader, XmlVersion};
+
+pub fn escape_text(text: &str) -> String {
+    quick_xml::escape::escape(text).into_owned()
+}
+
+/// Like `escape_text`, but line breaks and tabs become character references
+/// so they survive attribute value normalization.
+pub fn escape_attribute(text: &str) -> String {
+    quick_xml::escape::escape(text)
+        .replace('\n', "&#10;")
+        .replace('\r', "&#13;")
+        .replace('\t', "&#9;")
+}
+
+/// Wraps text in CDATA, splitting any `]]>` it contains.
+pub fn cdata(text: &str) -> String {
+    format!("<![CDATA[{}]]>", text.replace("]]>", "]]]]><![CDATA[>"))
+}
+
+/// An element of the document, with its attributes and the text directly inside it.
+#[derive(Debug)]
+pub struct Element {
+    pub name:       String,
+    pub attributes: HashMap<String, String>,
+    pub text:       String,
+    /// Number of elements enclosing this one.
+    pub depth:      usize,
+}
+
+/// Flattens a document into its elements, in document order. Small snippet
+/// files don't need more than this.
+pub fn elements(xml: &str) -> Result<Vec<Element>, String> {
+    let mut reader = Reader::from_str(xml);
+    let mut elements: Vec<Element> = Vec::new();
+    // indices into `elements` of the currently open elements
+    let mut open: Vec<usize> = Vec::new();
+
+    loop {
+        let event = reader.read_event().map_err(|e| format!("Invalid XML: {e}"))?;
+        match event {
+            Event::Start(ref start) | Event::Empty(ref start) => {
+                let name = start.name().as_ref().to_string();
+                let mut attributes = HashMap::new();
+                for attribute in start.attributes() {
+                    let attribute = attribute.map_err(|e| format!("Invalid XML: {e}"))?;
+                    let key = attribute.key.as_ref().to_string();
+                    let value = attribute.normalized_value(XmlVersion::Implicit1_0).map_err(|e| format!("Invalid XML: {e}"))?;
+                    attributes.insert(key, value.into_owned());
+                }
+                elements.push(Element { name, attributes, text: String::new(), depth: open.len() });
+                if matches!(event, Event::Start(_)) {
+                    open.push(elements.len() - 1);
+                }
+            }
+            Event::End(_) => {
+                open.pop();
+            }
+            Event::Text(text) => push_text(&mut elements, &open, &text.xml10_content()),
+            Event::CData(data) => push_text(&mut elements, &open, &data.xml10_content()),
+            Event::GeneralRef(reference) => {
+                let resolved = match reference.resolve_char_ref() {
+                    Ok(Some(c)) => c.to_string(),
+                    _ => {
+                        let name = reference.xml10_content();
+                        resolve_predefined_entity(&name)
+                            .ok_or_else(|| format!("Invalid XML: unknown entity &{name};"))?
+                            .to_string()
+                    }
+                };
+                push_text(&mut elements, &open, &resolved);
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(elements)
+}
+
+fn push_text(elements: &mut [Element], open: &[usize], text: &str) {
+    if let Some(&current) = open.last() {
+        elements[current].text.push_str(text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cdata_splits_its_terminator() {
+        let text = "a ]]> b ]]]]> c";
+        let wrapped = cdata(text);
+        assert_eq!(wrapped.matches("]]>").count(), 3);
+
+        let parsed = elements(&format!("<content>{wrapped}</content>")).unwrap();
+        assert_eq!(parsed[0].text, text);
+    }
+
+    #[test]
+    fn attributes_keep_line_breaks_and_tabs() {
+        let value = "line one\n\tline \"two\" & <three>\r\n";
+        let escaped = escape_attribute(value);
+        assert!(!escaped.contains(['\n', '\r', '\t', '"', '<']));
+
+        let parsed = elements(&format!("<template value=\"{escaped}\" />")).unwrap();
+        assert_eq!(parsed[0].attributes["value"], value);
+    }
+
+    #[test]
+    fn elements_are_flattened_with_depth() {
+        let parsed = elements("<a><b x=\"1\">t&amp;&#65;</b><c/></a>").unwrap();
+        let shape: Vec<_> = parsed.iter().map(|e| (e.name.as_str(), e.depth, e.text.as_str())).collect();
+        assert_eq!(shape, vec![("a", 0, ""), ("b", 1, "t&A"), ("c", 1, "")]);
+        assert!(elements("<a>&nope;</a>").is_err());
+    }
+}
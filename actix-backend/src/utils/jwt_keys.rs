@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::{
+    pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey},
+    SigningKey, VerifyingKey,
+};
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    errors::{Error as JwtError, ErrorKind},
+    Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+struct VerificationKey {
+    decoding_key: DecodingKey,
+    /// Raw Ed25519 public key, base64url encoded as JWKS expects.
+    x: String,
+}
+
+/// Ed25519 keys used to sign and verify access tokens.
+///
+/// Keys are read from `JWT_KEYS_DIR`, one `<kid>.pem` file per key. Private
+/// keys (PKCS#8) can sign, public keys (SPKI) only verify. `JWT_ACTIVE_KID`
+/// picks the signing key; every key in the directory is accepted for
+/// verification and published on the JWKS endpoint, so a new key can be
+/// rolled out before it starts signing and an old one kept until its tokens
+/// have expired.
+///
+/// Without `JWT_KEYS_DIR` startup fails unless `JWT_DEV_KEY=1`, which signs
+/// with a key generated at startup for local development.
+pub struct JwtKeys {
+    active_kid: String,
+    encoding_key: EncodingKey,
+    verification: HashMap<String, VerificationKey>,
+}
+
+impl JwtKeys {
+    pub fn from_env() -> Self {
+        let Ok(dir) = std::env::var("JWT_KEYS_DIR") else {
+            // A throwaway key logs everyone out on restart and differs between
+            // replicas, so it has to be asked for rather than fallen back to.
+            if std::env::var("JWT_DEV_KEY").as_deref() != Ok("1") {
+                panic!("JWT_KEYS_DIR must be set (or JWT_DEV_KEY=1 to sign with a throwaway key in development)");
+            }
+            log::warn!("JWT_DEV_KEY=1, signing access tokens with a throwaway key");
+            let signing_key = SigningKey::generate(&mut rand::rngs::OsRng);
+            return Self::from_signing_keys(vec![("dev".to_string(), signing_key)], Vec::new(), "dev");
+        };
+
+        let mut private_keys = Vec::new();
+        let mut public_keys = Vec::new();
+
+        for entry in std::fs::read_dir(&dir).expect("JWT_KEYS_DIR must be a readable directory") {
+            let path = entry.expect("Failed to read JWT_KEYS_DIR entry").path();
+            if path.extension().and_then(|e| e.to_str()) != Some("pem") {
+                continue;
+            }
+            let kid = path.file_stem().and_then(|s| s.to_str()).expect("Key file names must be UTF-8").to_string();
+            let pem = std::fs::read_to_string(&path).expect("Failed to read key file");
+
+            if let Ok(key) = SigningKey::from_pkcs8_pem(&pem) {
+                private_keys.push((kid, key));
+            } else if let Ok(key) = VerifyingKey::from_public_key_pem(&pem) {
+                public_keys.push((kid, key));
+            } else {
+                panic!("{} is not an Ed25519 PKCS#8 private key or SPKI public key", path.display());
+            }
+        }
+
+        let active_kid = match std::env::var("JWT_ACTIVE_KID") {
+            Ok(kid) => kid,
+            Err(_) if private_keys.len() == 1 => private_keys[0].0.clone(),
+            Err(_) => panic!("JWT_ACTIVE_KID must be set when JWT_KEYS_DIR holds more than one private key"),
+        };
+
+        Self::from_signing_keys(private_keys, public_keys, &active_kid)
+    }
+
+    fn from_signing_keys(
+        private_keys: Vec<(String, SigningKey)>,
+        public_keys: Vec<(String, VerifyingKey)>,
+        active_kid: &str,
+    ) -> Self {
+        let active = private_keys
+            .iter()
+            .find(|(kid, _)| kid == active_kid)
+            .map(|(_, key)| key)
+            .unwrap_or_else(|| panic!("No private key found for JWT_ACTIVE_KID {}", active_kid));
+        let der = active.to_pkcs8_der().expect("Failed to encode signing key");
+        let encoding_key = EncodingKey::from_ed_der(der.as_bytes());
+
+        let verification = private_keys
+            .iter()
+            .map(|(kid, key)| (kid.clone(), key.verifying_key()))
+            .chain(public_keys)
+            .map(|(kid, key)| {
+                let x = URL_SAFE_NO_PAD.encode(key.as_bytes());
+                let decoding_key = DecodingKey::from_ed_components(&x).expect("Invalid Ed25519 public key");
+                (kid, VerificationKey { decoding_key, x })
+            })
+            .collect();
+
+        Self { active_kid: active_kid.to_string(), encoding_key, verification }
+    }
+
+    pub fn sign<T: Serialize>(&self, claims: &T) -> Result<String, JwtError> {
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.active_kid.clone());
+        encode(&header, claims, &self.encoding_key)
+    }
+
+    pub fn verify<T: DeserializeOwned>(&self, token: &str) -> Result<TokenData<T>, JwtError> {
+        let kid = decode_header(token)?.kid.ok_or_else(|| JwtError::from(ErrorKind::InvalidToken))?;
+        let key = self.verification.get(&kid).ok_or_else(|| JwtError::from(ErrorKind::InvalidToken))?;
+        decode(token, &key.decoding_key, &Validation::new(Algorithm::EdDSA))
+    }
+
+    /// Public half of every verification key, in JWKS (RFC 7517) form.
+    pub fn jwks(&self) -> serde_json::Value {
+        let keys: Vec<_> = self
+            .verification
+            .iter()
+            .map(|(kid, key)| serde_json::json!({
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "alg": "EdDSA",
+                "use": "sig",
+                "kid": kid,
+                "x": key.x,
+            }))
+            .collect();
+
+        serde_json::json!({ "keys": keys })
+    }
+}
@@ -0,0 +1,114 @@
+use sqlx::{Pool, Postgres};
+
+// Failures allowed before a key gets locked. IPs get more room because
+// several people can share one address behind NAT.
+const EMAIL_THRESHOLD: i32 = 5;
+const IP_THRESHOLD: i32 = 20;
+const MAX_LOCKOUT_MINUTES: i32 = 60;
+
+/// Normalized like account lookups, which compare `lower(email)` to the trimmed input.
+fn email_key(email: &str) -> String {
+    format!("email:{}", email.trim().to_lowercase())
+}
+
+fn ip_key(ip: &str) -> String {
+    format!("ip:{}", ip)
+}
+
+/// Seconds left on the longest active lockout for this email or IP, if any.
+pub async fn login_lockout(
+    pool: &Pool<Postgres>,
+    email: &str,
+    ip: Option<&str>,
+) -> Result<Option<i64>, sqlx::Error> {
+    let keys: Vec<String> = std::iter::once(email_key(email)).chain(ip.map(ip_key)).collect();
+
+    sqlx::query_scalar!(
+        r#"
+        SELECT CEIL(EXTRACT(EPOCH FROM MAX(locked_until) - CURRENT_TIMESTAMP))::BIGINT
+          FROM auth_throttles
+         WHERE key = ANY($1)
+           AND locked_until > CURRENT_TIMESTAMP
+        "#,
+        &keys
+    )
+    .fetch_one(pool)
+    .await
+}
+
+/// Counts a failed login against the email and the IP. Once a key passes its
+/// threshold it is locked for 1, 2, 4, ... minutes (capped at an hour) and the
+/// lockout goes into the audit log.
+pub async fn record_login_failure(
+    pool: &Pool<Postgres>,
+    email: &str,
+    ip: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let keys = std::iter::once((email_key(email), EMAIL_THRESHOLD))
+        .chain(ip.map(|ip| (ip_key(ip), IP_THRESHOLD)));
+
+    for (key, threshold) in keys {
+        // Failures are forgotten 15 minutes after the last one, or after the
+        // lockout ends if that's later, so lockouts keep growing up to the cap
+        // instead of resetting whenever one outlasts the quiet period.
+        let locked = sqlx::query!(
+            r#"
+            INSERT INTO auth_throttles (key, failures, last_failure_at)
+            VALUES ($1, 1, CURRENT_TIMESTAMP)
+            ON CONFLICT (key) DO UPDATE
+               SET failures = CASE
+                     WHEN GREATEST(auth_throttles.last_failure_at, auth_throttles.locked_until)
+                          < CURRENT_TIMESTAMP - INTERVAL '15 minutes' THEN 1
+                     ELSE auth_throttles.failures + 1
+                   END,
+                   last_failure_at = CURRENT_TIMESTAMP
+            RETURNING failures
+            "#,
+            key
+        )
+        .fetch_one(pool)
+        .await?;
+
+        if locked.failures < threshold {
+            continue;
+        }
+
+        let minutes = 2i32.saturating_pow((locked.failures - threshold) as u32).min(MAX_LOCKOUT_MINUTES);
+
+        sqlx::query!(
+            r#"
+            WITH locked AS (
+                UPDATE auth_throttles
+                   SET locked_until = CURRENT_TIMESTAMP + make_interval(mins => $2)
+                 WHERE key = $1
+            )
+            INSERT INTO auth_audit_log (user_id, event, subject, ip_address, details)
+            VALUES (
+                (SELECT id FROM users WHERE lower(email) = lower(trim($3)) LIMIT 1),
+                'login_lockout',
+                $1,
+                $4,
+                jsonb_build_object('failures', $5::INT, 'minutes', $2)
+            )
+            "#,
+            key,
+            minutes,
+            email,
+            ip,
+            locked.failures
+        )
+        .execute(pool)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// A successful login wipes the email's record. The IP's stays, so one valid
+/// account can't be used to reset the counter while guessing at others.
+pub async fn clear_login_failures(pool: &Pool<Postgres>, email: &str) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM auth_throttles WHERE key = $1", email_key(email))
+        .execute(pool)
+        .await
+        .map(|_| ())
+}
@@ -1,2 +1,19 @@
 mod auth;
-pub use auth::test_password;
\ No newline at end of file
+pub use auth::test_password;
+
+mod token;
+pub use token::{generate_token, hash_token};
+
+mod totp;
+pub use totp::{base32_encode, generate_totp_secret, provisioning_uri, verify_totp};
+
+mod jwt_keys;
+pub use jwt_keys::JwtKeys;
+
+mod login_throttle;
+pub use login_throttle::{clear_login_failures, login_lockout, record_login_failure};
+
+mod stripe_signature;
+pub use stripe_signature::verify_stripe_signature;
+mod cursor;
+pub use cursor::{Cursor, CursorDirection};
\ No newline at end of file
@@ -0,0 +1,117 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// How far the signed timestamp may be from now before the event is treated as a replay.
+const TOLERANCE_SECONDS: i64 = 300;
+
+/// Verifies a `Stripe-Signature` header (`t=<unix>,v1=<hex>[,v1=<hex>...]`):
+/// the HMAC-SHA256 of `"<t>.<payload>"` under the endpoint secret must match one `v1` entry.
+pub fn verify_stripe_signature(header: &str, payload: &[u8], secret: &str, now: i64) -> Result<(), &'static str> {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+
+    for part in header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", t)) => timestamp = t.parse::<i64>().ok(),
+            Some(("v1", sig)) => signatures.push(sig),
+            _ => {}
+        }
+    }
+
+    let timestamp = timestamp.ok_or("Missing timestamp")?;
+    if signatures.is_empty() {
+        return Err("Missing v1 signature");
+    }
+    if (now - timestamp).abs() > TOLERANCE_SECONDS {
+        return Err("Timestamp outside tolerance");
+    }
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(payload);
+
+    let matched = signatures.iter().any(|sig| {
+        decode_hex(sig).is_some_and(|sig| mac.clone().verify_slice(&sig).is_ok())
+    });
+
+    if matched { Ok(()) } else { Err("Signature mismatch") }
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "whsec_test_secret";
+    const PAYLOAD: &[u8] = br#"{"id":"evt_test","type":"invoice.payment_failed"}"#;
+    const NOW: i64 = 1_760_000_000;
+
+    fn sign(timestamp: i64, payload: &[u8], secret: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(format!("{timestamp}.").as_bytes());
+        mac.update(payload);
+        mac.finalize().into_bytes().iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    #[test]
+    fn accepts_a_valid_signature() {
+        let header = format!("t={NOW},v1={}", sign(NOW, PAYLOAD, SECRET));
+        assert_eq!(verify_stripe_signature(&header, PAYLOAD, SECRET, NOW), Ok(()));
+    }
+
+    #[test]
+    fn rejects_a_bad_signature() {
+        let wrong_secret = format!("t={NOW},v1={}", sign(NOW, PAYLOAD, "whsec_other"));
+        let wrong_payload = format!("t={NOW},v1={}", sign(NOW, b"{}", SECRET));
+        let not_hex = format!("t={NOW},v1=zz{}", &sign(NOW, PAYLOAD, SECRET)[2..]);
+
+        for header in [wrong_secret, wrong_payload, not_hex] {
+            assert_eq!(verify_stripe_signature(&header, PAYLOAD, SECRET, NOW), Err("Signature mismatch"), "{header}");
+        }
+    }
+
+    #[test]
+    fn rejects_timestamps_outside_tolerance() {
+        for timestamp in [NOW - TOLERANCE_SECONDS - 1, NOW + TOLERANCE_SECONDS + 1] {
+            let header = format!("t={timestamp},v1={}", sign(timestamp, PAYLOAD, SECRET));
+            assert_eq!(verify_stripe_signature(&header, PAYLOAD, SECRET, NOW), Err("Timestamp outside tolerance"));
+        }
+
+        let edge = NOW - TOLERANCE_SECONDS;
+        let header = format!("t={edge},v1={}", sign(edge, PAYLOAD, SECRET));
+        assert_eq!(verify_stripe_signature(&header, PAYLOAD, SECRET, NOW), Ok(()));
+    }
+
+    #[test]
+    fn accepts_any_matching_v1_entry() {
+        // Stripe sends one v1 entry per active secret while a secret is being rolled.
+        let old = sign(NOW, PAYLOAD, "whsec_old_secret");
+        let current = sign(NOW, PAYLOAD, SECRET);
+
+        let header = format!("t={NOW},v1={old},v1={current},v0=ignored");
+        assert_eq!(verify_stripe_signature(&header, PAYLOAD, SECRET, NOW), Ok(()));
+
+        let header = format!("t={NOW}, v1={current}, v1={old}");
+        assert_eq!(verify_stripe_signature(&header, PAYLOAD, SECRET, NOW), Ok(()));
+
+        let header = format!("t={NOW},v1={old},v1={old}");
+        assert_eq!(verify_stripe_signature(&header, PAYLOAD, SECRET, NOW), Err("Signature mismatch"));
+    }
+
+    #[test]
+    fn rejects_headers_missing_parts() {
+        let signature = sign(NOW, PAYLOAD, SECRET);
+        assert_eq!(verify_stripe_signature(&format!("v1={signature}"), PAYLOAD, SECRET, NOW), Err("Missing timestamp"));
+        assert_eq!(verify_stripe_signature(&format!("t={NOW}"), PAYLOAD, SECRET, NOW), Err("Missing v1 signature"));
+    }
+}
@@ -0,0 +1,155 @@
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+
+// RFC 6238 defaults, which is what every authenticator app assumes.
+const STEP_SECONDS: u64 = 30;
+const DIGITS: u32 = 6;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn generate_totp_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; 20];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+/// RFC 4648 base32 without padding, the format authenticator apps expect.
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    out
+}
+
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    binary % 10u32.pow(DIGITS)
+}
+
+/// Checks `code` against the current step and one step either side to allow
+/// for clock drift. Returns the matching step so callers can reject replays.
+pub fn verify_totp(secret: &[u8], code: &str, unix_time: u64) -> Option<u64> {
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let current = unix_time / STEP_SECONDS;
+
+    [current.saturating_sub(1), current, current + 1]
+        .into_iter()
+        .find(|&step| hotp(secret, step) == code)
+}
+
+/// `otpauth://` URI that authenticator apps turn into an account entry.
+pub fn provisioning_uri(secret: &[u8], account: &str, issuer: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        base32_encode(secret),
+        percent_encode(issuer),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The SHA-1 key from RFC 4226 appendix D and RFC 6238 appendix B.
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn hotp_matches_rfc4226_vectors() {
+        let expected = [755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489];
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(SECRET, counter as u64), code, "counter {counter}");
+        }
+    }
+
+    #[test]
+    fn verify_totp_matches_rfc6238_sha1_vectors() {
+        // RFC 6238 lists 8-digit codes; with 6 digits they keep their last six.
+        let vectors = [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ];
+        for (time, code) in vectors {
+            assert_eq!(verify_totp(SECRET, code, time), Some(time / STEP_SECONDS), "time {time}");
+        }
+    }
+
+    #[test]
+    fn verify_totp_allows_one_step_of_skew() {
+        // "050471" is the code for step 37037037 (t = 1111111111).
+        let step = 1111111111 / STEP_SECONDS;
+        let start = step * STEP_SECONDS;
+
+        assert_eq!(verify_totp(SECRET, "050471", start - 1), Some(step), "one step early");
+        assert_eq!(verify_totp(SECRET, "050471", start + STEP_SECONDS), Some(step), "one step late");
+        assert_eq!(verify_totp(SECRET, "050471", start - STEP_SECONDS - 1), None, "two steps early");
+        assert_eq!(verify_totp(SECRET, "050471", start + 2 * STEP_SECONDS), None, "two steps late");
+    }
+
+    #[test]
+    fn verify_totp_rejects_malformed_codes() {
+        for code in ["", "28708", "2870820", "28708a", " 287082", "+87082"] {
+            assert_eq!(verify_totp(SECRET, code, 59), None, "{code:?}");
+        }
+    }
+
+    #[test]
+    fn base32_encode_matches_rfc4648_vectors() {
+        let vectors = [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ];
+        for (input, encoded) in vectors {
+            assert_eq!(base32_encode(input.as_bytes()), encoded);
+        }
+    }
+}
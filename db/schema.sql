@@ -4,6 +4,7 @@ CREATE SCHEMA IF NOT EXISTS sync_flow;
 
 -- Enable pgcrypto for UUID generation
 CREATE EXTENSION IF NOT EXISTS "pgcrypto";
+CREATE EXTENSION IF NOT EXISTS "pg_trgm";
 
 -- ________________________________ Public ________________________________
 
@@ -27,6 +28,10 @@ CREATE TABLE users (
     updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
 
+-- Sign-in and password resets look accounts up case-insensitively, so
+-- addresses differing only in case can't belong to two accounts.
+CREATE UNIQUE INDEX users_email_lower_key ON users (lower(email));
+
 
 CREATE TABLE user_sessions (
   id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
@@ -45,7 +50,125 @@ CREATE INDEX idx_user_sessions_user_id ON user_sessions(user_id);
 CREATE INDEX idx_user_sessions_token ON user_sessions(refresh_token);
 CREATE UNIQUE INDEX uniq_user_sessions_user_device ON user_sessions(user_id, device_id);
 
+-- Refresh tokens that have already been swapped out by /auth/refresh.
+-- Seeing one of these again means the token leaked, so the session gets revoked,
+-- except for one reuse right after rotation (two tabs refreshing at once).
+CREATE TABLE user_session_rotated_tokens (
+  refresh_token TEXT PRIMARY KEY,
+  session_id UUID NOT NULL REFERENCES user_sessions(id) ON DELETE CASCADE,
+  rotated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  grace_used BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX idx_user_session_rotated_tokens_session ON user_session_rotated_tokens(session_id);
+CREATE INDEX idx_user_session_rotated_tokens_rotated_at ON user_session_rotated_tokens(rotated_at);
+
+
+
+CREATE TABLE email_verification_tokens (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  token_hash TEXT UNIQUE NOT NULL,
+  expires_at TIMESTAMPTZ NOT NULL,
+  used_at TIMESTAMPTZ,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
 
+CREATE INDEX idx_email_verification_tokens_user_id ON email_verification_tokens(user_id);
+
+CREATE TABLE password_reset_tokens (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  token_hash TEXT UNIQUE NOT NULL,
+  expires_at TIMESTAMPTZ NOT NULL,
+  used_at TIMESTAMPTZ,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_password_reset_tokens_user_id ON password_reset_tokens(user_id);
+
+-- TOTP second factor. A row with enabled = FALSE is an enrollment waiting for its first code.
+CREATE TABLE user_mfa (
+  user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+  secret BYTEA NOT NULL,
+  enabled BOOLEAN NOT NULL DEFAULT FALSE,
+  last_used_step BIGINT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  confirmed_at TIMESTAMPTZ
+);
+
+CREATE TABLE user_mfa_recovery_codes (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  code_hash TEXT NOT NULL,
+  used_at TIMESTAMPTZ,
+  UNIQUE (user_id, code_hash)
+);
+
+-- Handed out by /auth/login when a second factor is still owed.
+CREATE TABLE mfa_challenges (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  token_hash TEXT UNIQUE NOT NULL,
+  attempts INT NOT NULL DEFAULT 0,
+  expires_at TIMESTAMPTZ NOT NULL,
+  used_at TIMESTAMPTZ,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_mfa_challenges_user_id ON mfa_challenges(user_id);
+
+-- Long-lived tokens for the VS Code extension and scripts. Only the hash is stored;
+-- token_prefix is kept so users can recognise a token in the list.
+CREATE TABLE personal_access_tokens (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  token_hash TEXT UNIQUE NOT NULL,
+  token_prefix TEXT NOT NULL,
+  scopes TEXT[] NOT NULL,
+  last_used_at TIMESTAMPTZ,
+  expires_at TIMESTAMPTZ,
+  revoked BOOLEAN NOT NULL DEFAULT FALSE,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_personal_access_tokens_user_id ON personal_access_tokens(user_id);
+
+-- OAuth 2.0 device authorization grant (RFC 8628) used by the editor extensions.
+-- status: pending -> approved | denied -> consumed
+CREATE TABLE device_authorizations (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  device_code_hash TEXT UNIQUE NOT NULL,
+  user_code TEXT UNIQUE NOT NULL,
+  user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+  status TEXT NOT NULL DEFAULT 'pending',
+  interval_seconds INT NOT NULL,
+  last_polled_at TIMESTAMPTZ,
+  expires_at TIMESTAMPTZ NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Failed-login counters keyed on 'email:<address>' or 'ip:<address>'.
+CREATE TABLE auth_throttles (
+  key TEXT PRIMARY KEY,
+  failures INT NOT NULL DEFAULT 0,
+  last_failure_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  locked_until TIMESTAMPTZ
+);
+
+CREATE TABLE auth_audit_log (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+  event TEXT NOT NULL,
+  subject TEXT,
+  ip_address TEXT,
+  details JSONB,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX idx_auth_audit_log_user_id ON auth_audit_log(user_id);
+CREATE INDEX idx_auth_audit_log_created_at ON auth_audit_log(created_at);
 
 CREATE TRIGGER trg_users_updated_at
   BEFORE UPDATE ON users
@@ -63,11 +186,31 @@ CREATE TABLE subscriptions (
   starts_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
   ends_at TIMESTAMPTZ,
   status subscription_status NOT NULL DEFAULT 'active',
+  -- `created` of the last billing event applied, so late deliveries can't roll state back
+  billing_event_at TIMESTAMPTZ,
   created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
   updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
 
+-- Stripe event ids already applied, so webhook redeliveries are no-ops.
+CREATE TABLE billing_events (
+  id TEXT PRIMARY KEY,
+  type TEXT NOT NULL,
+  processed_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Token buckets for the rate limiter when RATE_LIMIT_STORE=postgres.
+-- key is 'user:<id>' or 'ip:<address>'.
+CREATE UNLOGGED TABLE rate_limit_buckets (
+  key TEXT PRIMARY KEY,
+  tokens DOUBLE PRECISION NOT NULL,
+  allowed BOOLEAN NOT NULL,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
 -- ________________________________ VSC Snippet Extension ________________________________
+CREATE TYPE snippet_visibility AS ENUM ('private', 'unlisted', 'public');
+
 CREATE TABLE snippets_extension.snippets (
   id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
   owner_id UUID NOT NULL REFERENCES users(id),
@@ -75,6 +218,16 @@ CREATE TABLE snippets_extension.snippets (
   description TEXT,
   code TEXT,
   language TEXT NOT NULL,
+  visibility snippet_visibility NOT NULL DEFAULT 'public',
+  -- editor metadata from the VS Code snippet format: trigger words and a
+  -- comma-separated list of language IDs (NULL means just `language`)
+  prefixes TEXT[] NOT NULL DEFAULT '{}',
+  scope TEXT,
+  -- set on forks; kept when the upstream is deleted so the fork survives
+  forked_from UUID REFERENCES snippets_extension.snippets(id) ON DELETE SET NULL,
+  forked_at TIMESTAMPTZ,
+  -- maintained by triggers below, including tags
+  search_vector TSVECTOR NOT NULL DEFAULT ''::TSVECTOR,
   created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
   updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
 );
@@ -84,19 +237,88 @@ CREATE TRIGGER trg_snippets_updated_at
   FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
 
 CREATE INDEX idx_snippets_language ON snippets_extension.snippets(language);
-CREATE INDEX idx_snippets_owner ON snippets_extension.snippets(owner_id);
+-- Owner listings page by (created_at, id), so the index carries both.
+CREATE INDEX idx_snippets_owner ON snippets_extension.snippets(owner_id, created_at DESC, id DESC);
+CREATE INDEX idx_snippets_forked_from ON snippets_extension.snippets(forked_from);
+CREATE INDEX idx_snippets_public ON snippets_extension.snippets(created_at DESC, id DESC) WHERE visibility = 'public';
+CREATE INDEX idx_snippets_search ON snippets_extension.snippets USING GIN (search_vector);
+CREATE INDEX idx_snippets_title_trgm ON snippets_extension.snippets USING GIN (title gin_trgm_ops);
+
+-- Immutable snapshot of a snippet after every save. A revert writes a new
+-- revision with `reverted_from` pointing at the one it restored.
+CREATE TABLE snippets_extension.snippet_revisions (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  snippet_id UUID NOT NULL REFERENCES snippets_extension.snippets(id) ON DELETE CASCADE,
+  revision INT NOT NULL,
+  title TEXT NOT NULL,
+  description TEXT,
+  code TEXT,
+  language TEXT NOT NULL,
+  tags TEXT[] NOT NULL DEFAULT ARRAY[]::TEXT[],
+  prefixes TEXT[] NOT NULL DEFAULT '{}',
+  scope TEXT,
+  visibility snippet_visibility NOT NULL,
+  author_id UUID REFERENCES users(id) ON DELETE SET NULL,
+  reverted_from INT,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  UNIQUE (snippet_id, revision)
+);
 
 CREATE TABLE snippets_extension.tags (
   id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
   name TEXT UNIQUE NOT NULL
 );
 
+CREATE INDEX idx_tags_name_trgm ON snippets_extension.tags USING GIN (name gin_trgm_ops);
+
 CREATE TABLE snippets_extension.snippet_tags (
   snippet_id UUID NOT NULL REFERENCES snippets_extension.snippets(id) ON DELETE CASCADE,
   tag_id UUID NOT NULL REFERENCES snippets_extension.tags(id) ON DELETE CASCADE,
   PRIMARY KEY (snippet_id, tag_id)
 );
 
+-- Weighted document for full-text search: title A, tags B, description C, code D.
+CREATE OR REPLACE FUNCTION snippets_extension.snippet_search_vector(
+  p_id UUID, p_title TEXT, p_description TEXT, p_code TEXT
+) RETURNS TSVECTOR AS $$
+  SELECT
+    setweight(to_tsvector('english', COALESCE(p_title, '')), 'A') ||
+    setweight(to_tsvector('english', COALESCE(
+      (SELECT string_agg(t.name, ' ')
+         FROM snippets_extension.snippet_tags st
+         JOIN snippets_extension.tags t ON t.id = st.tag_id
+        WHERE st.snippet_id = p_id), '')), 'B') ||
+    setweight(to_tsvector('english', COALESCE(p_description, '')), 'C') ||
+    setweight(to_tsvector('english', COALESCE(p_code, '')), 'D');
+$$ LANGUAGE sql STABLE;
+
+CREATE OR REPLACE FUNCTION snippets_extension.update_snippet_search_vector()
+RETURNS TRIGGER AS $$
+BEGIN
+  NEW.search_vector = snippets_extension.snippet_search_vector(NEW.id, NEW.title, NEW.description, NEW.code);
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trg_snippets_search_vector
+  BEFORE INSERT OR UPDATE OF title, description, code ON snippets_extension.snippets
+  FOR EACH ROW EXECUTE FUNCTION snippets_extension.update_snippet_search_vector();
+
+-- Tags live in their own table, so changing them has to refresh the snippet's vector.
+CREATE OR REPLACE FUNCTION snippets_extension.refresh_snippet_search_vector()
+RETURNS TRIGGER AS $$
+BEGIN
+  UPDATE snippets_extension.snippets s
+     SET search_vector = snippets_extension.snippet_search_vector(s.id, s.title, s.description, s.code)
+   WHERE s.id = COALESCE(NEW.snippet_id, OLD.snippet_id);
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER trg_snippet_tags_search_vector
+  AFTER INSERT OR DELETE ON snippets_extension.snippet_tags
+  FOR EACH ROW EXECUTE FUNCTION snippets_extension.refresh_snippet_search_vector();
+
 CREATE TABLE snippets_extension.snippet_stars (
   user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
   snippet_id UUID NOT NULL REFERENCES snippets_extension.snippets(id) ON DELETE CASCADE,
@@ -105,7 +327,53 @@ CREATE TABLE snippets_extension.snippet_stars (
 );
 
 CREATE INDEX idx_snippet_stars_user ON snippets_extension.snippet_stars(user_id);
-CREATE INDEX idx_snippet_stars_snippet ON snippets_extension.snippet_stars(snippet_id);
+CREATE INDEX idx_snippet_stars_snippet ON snippets_extension.snippet_stars(snippet_id, starred_at);
+
+CREATE TYPE trending_window AS ENUM ('day', 'week', 'month');
+
+-- Materialized by the trending job: time-decayed star velocity per public
+-- snippet and window. Rebuilt wholesale on every run.
+CREATE TABLE snippets_extension.snippet_trending (
+  snippet_id UUID NOT NULL REFERENCES snippets_extension.snippets(id) ON DELETE CASCADE,
+  time_window trending_window NOT NULL,
+  score DOUBLE PRECISION NOT NULL,
+  recent_stars BIGINT NOT NULL,
+  computed_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (time_window, snippet_id)
+);
+
+CREATE INDEX idx_snippet_trending_rank ON snippets_extension.snippet_trending(time_window, score DESC);
+
+-- User-owned folders. Nest through parent_id; position orders siblings.
+CREATE TABLE snippets_extension.collections (
+  id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+  owner_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  parent_id UUID REFERENCES snippets_extension.collections(id) ON DELETE CASCADE,
+  name TEXT NOT NULL,
+  description TEXT,
+  visibility snippet_visibility NOT NULL DEFAULT 'private',
+  position INT NOT NULL DEFAULT 0,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TRIGGER trg_collections_updated_at
+  BEFORE UPDATE ON snippets_extension.collections
+  FOR EACH ROW EXECUTE FUNCTION update_updated_at_column();
+
+CREATE INDEX idx_collections_owner ON snippets_extension.collections(owner_id);
+CREATE INDEX idx_collections_parent ON snippets_extension.collections(parent_id);
+
+-- A snippet can sit in any number of its owner's collections.
+CREATE TABLE snippets_extension.collection_snippets (
+  collection_id UUID NOT NULL REFERENCES snippets_extension.collections(id) ON DELETE CASCADE,
+  snippet_id UUID NOT NULL REFERENCES snippets_extension.snippets(id) ON DELETE CASCADE,
+  position INT NOT NULL DEFAULT 0,
+  added_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (collection_id, snippet_id)
+);
+
+CREATE INDEX idx_collection_snippets_snippet ON snippets_extension.collection_snippets(snippet_id);
 
 
 -- ________________________________ Write Right (Markdown WYSIWYG) ________________________________